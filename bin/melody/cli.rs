@@ -0,0 +1,542 @@
+use std::path::PathBuf;
+
+use melody::{
+    Column, ConflictPolicy, DuplicateOptions, ImportMode, KeepPolicy, MatchBy, MelodyErrors,
+    OutputFormat, PathTemplate, Query, Renderer, ResolveAction, ResolveOptions, Template,
+    TranscodeFormat, TranscodeOptions,
+};
+
+use crate::Errors;
+
+pub const USAGE: &str = "\
+Usage:
+    melody [options]                      Play the music library
+    melody list [options]                 Print the songs of the library
+    melody scan                           Update the library index
+    melody verify [options]               Decode every song to find corrupt and truncated files
+    melody stats [options]                Summarize the library
+    melody audit [options]                Find missing and inconsistent tags, --format json
+                                          or csv for a report to fix them from
+    melody import <dir> [options]         Organize the songs in a directory into the
+                                          music directory
+    melody undo-organize                  Reverse the last import
+    melody rename [options]               Rename songs where they are after their tags
+    melody transcode <dir> [options]      Convert songs to Opus or MP3 into a directory,
+                                          like a phone, keeping the library layout
+    melody duplicates [options]           List songs that are in the library more than once
+    melody dedupe [options]               Keep the best copy of every duplicate and delete,
+                                          quarantine or link the others
+    melody playlist list                  List saved playlists
+    melody playlist show <name> [options] List the songs of a playlist
+    melody playlist play <name> [options] Play a saved playlist
+    melody playlist create <name>         Create an empty playlist
+    melody playlist delete <name>         Delete a playlist
+    melody playlist rename <old> <new>    Rename a playlist
+    melody playlist append <name> <path>...
+                                          Add files or directories to a playlist
+    melody playlist import <name> <file>  Save a M3U/PLS/XSPF/CUE file as a playlist
+    melody playlist export <name> <file>  Write a playlist to a M3U/PLS/XSPF file
+    melody playlist smart <name> <rule>...
+                                          Save a smart playlist picked from the library
+                                          by rules like `filter=genre:jazz`, `unplayed=30d`,
+                                          `limit=2h` (or a song count) and `order=random`
+    melody help                           Show this message
+
+Options:
+    -f, --filter <query>    Only use the songs matching the query,
+                            like `artist:\"Boards of Canada\" year:>1998 duration:<5m`
+    -s, --sort <keys>       Play in order instead of shuffled, like `album,track` or `-year`
+
+Import options:
+    -m, --mode <mode>       copy (default), move, hardlink or symlink
+    -t, --template <path>   Where songs go inside the music directory, like
+                            `{albumartist}/[{year} - ]{album}/[{disc}-]{track:02} {title}.{ext}`
+    --conflict <policy>     When a different file is in the way: skip (default),
+                            overwrite-if-better or rename
+    -n, --dry-run           Only print what would be done
+
+Rename options:
+    -f, --filter <query>    Only rename the songs matching the query
+    -t, --template <name>   New file name, like `[{disc}-]{track:02} {title}.{ext}`
+    -n, --dry-run           Only print what would be done
+
+Transcode options:
+    -t, --to <format>       opus (default) or mp3, encoded by opusenc, lame or ffmpeg
+    -b, --bitrate <kbps>    128 for opus and 192 for mp3 by default
+    -f, --filter <query>    Only convert the songs matching the query
+    -p, --playlist <name>   Convert the songs of a saved playlist instead of the library
+
+Duplicates options:
+    --fingerprint           Compare how songs sound instead of their tags, finds
+                            re-tagged copies. Fingerprints are kept in the library index
+    --similarity <0-1>      How alike fingerprints have to be, 0.75 by default
+
+Dedupe options, and the duplicates options:
+    -a, --action <action>   delete or symlink the copies that arent kept
+    -q, --quarantine <dir>  Move the copies that arent kept into a directory instead
+    -k, --keep <policies>   What makes a copy better, lossless,bitrate,tags by default
+    --prefer <dir>          Keep the copies in a directory first
+    -n, --dry-run           Only print what would be done
+
+Output options, for list and show:
+    --format <format>       table (default), json or csv, also for audit
+    --template <template>   A line per song, like `{track}. {artist} - {title} ({duration})`
+    -c, --columns <list>    Columns to show, from track, artist, album, title, genre,
+                            year, duration, bitrate, path and elapsed";
+
+/// Subcommands of `melody playlist`
+#[derive(Debug)]
+pub enum PlaylistCommand {
+    List,
+    Show(String, Options),
+    Play(String, Option<Query>),
+    Create(String),
+    Delete(String),
+    Rename(String, String),
+    Append(String, Vec<PathBuf>),
+    Import(String, PathBuf),
+    Export(String, PathBuf),
+    /// Name and `key=value` rules
+    Smart(String, Vec<(String, String)>),
+}
+
+#[derive(Debug)]
+pub enum Command {
+    /// Play the library, optionally only the songs selected by a query
+    Play(Option<Query>),
+    /// Print the songs of the library
+    List(Options),
+    Scan,
+    /// Decode the songs of the library, optionally only the ones selected by a query
+    Verify(Option<Query>),
+    /// Summarize the library, optionally only the songs selected by a query
+    Stats(Option<Query>),
+    /// Look for tags to fix, only the output format of the options is used
+    Audit(Options),
+    /// Organize the songs of a directory into the music directory
+    Import(ImportArgs),
+    /// Reverse the last import
+    UndoOrganize,
+    /// Rename songs of the library after their tags
+    Rename(RenameArgs),
+    /// Convert songs of the library or a playlist for another device
+    Transcode(TranscodeArgs),
+    /// List the groups of duplicate songs in the library
+    Duplicates(DuplicateOptions),
+    /// Resolve the duplicates in the library
+    Dedupe(DedupeArgs),
+    Playlist(PlaylistCommand),
+    Help,
+}
+
+fn invalid(msg: &str) -> Errors {
+    Errors::InvalidArguments(msg.to_string())
+}
+
+/// Pops the next argument, failing with `what` is missing
+fn next(args: &mut impl Iterator<Item = String>, what: &str) -> Result<String, Errors> {
+    args.next()
+        .ok_or_else(|| invalid(&format!("missing {}", what)))
+}
+
+/// Fails if there are arguments left over
+fn done(mut args: impl Iterator<Item = String>) -> Result<(), Errors> {
+    match args.next() {
+        Some(arg) => Err(invalid(&format!("unexpected argument {:?}", arg))),
+        None => Ok(()),
+    }
+}
+
+/// Options shared by the commands that pick or print songs
+#[derive(Debug, Default)]
+pub struct Options {
+    /// `--filter` and `--sort`
+    pub query: Option<Query>,
+    /// `--format`, `--template` and `--columns`
+    pub output: Renderer,
+}
+
+/// Parses `--filter` and `--sort`, along with the output options if `output` is set.
+/// Fails on anything else
+fn parse_options(mut args: impl Iterator<Item = String>, output: bool) -> Result<Options, Errors> {
+    let mut filter: Option<String> = None;
+    let mut sort: Vec<String> = Vec::new();
+    let mut format = OutputFormat::Table;
+    let mut columns = Vec::new();
+    while let Some(arg) = args.next() {
+        // Both `--filter <query>` and `--filter=<query>` work
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        let invalid_output = |e: MelodyErrors| invalid(&format!("invalid output: {}", e));
+        match flag {
+            "-f" | "--filter" => filter = Some(value("filter query")?),
+            "-s" | "--sort" => sort.push(value("sort keys")?),
+            "--format" if output => format = value("format")?.parse().map_err(invalid_output)?,
+            "--template" if output => {
+                format = OutputFormat::Template(
+                    Template::parse(&value("template")?).map_err(invalid_output)?,
+                )
+            }
+            "-c" | "--columns" if output => {
+                columns = Column::parse_list(&value("columns")?).map_err(invalid_output)?
+            }
+            _ => return Err(invalid(&format!("unexpected argument {:?}", arg))),
+        }
+    }
+    let output = Renderer::new(format).with_columns(columns);
+    if filter.is_none() && sort.is_empty() {
+        return Ok(Options {
+            query: None,
+            output,
+        });
+    }
+    let invalid_query = |e: MelodyErrors| invalid(&format!("invalid query: {}", e));
+    let mut query = Query::parse(filter.as_deref().unwrap_or_default()).map_err(invalid_query)?;
+    for keys in sort {
+        query = query.sort_by(&keys).map_err(invalid_query)?;
+    }
+    Ok(Options {
+        query: Some(query),
+        output,
+    })
+}
+
+/// Arguments of `melody import`
+#[derive(Debug)]
+pub struct ImportArgs {
+    pub dir: PathBuf,
+    pub mode: ImportMode,
+    /// Replaces the template in the config
+    pub template: Option<PathTemplate>,
+    pub conflict: ConflictPolicy,
+    pub dry_run: bool,
+}
+
+fn parse_import(mut args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let mut import = ImportArgs {
+        dir: next(&mut args, "directory to import")?.into(),
+        mode: ImportMode::default(),
+        template: None,
+        conflict: ConflictPolicy::default(),
+        dry_run: false,
+    };
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        let invalid_option = |e: MelodyErrors| invalid(&e.to_string());
+        match flag {
+            "-m" | "--mode" => {
+                import.mode = value("import mode")?.parse().map_err(invalid_option)?
+            }
+            "-t" | "--template" => {
+                import.template = Some(value("path template")?.parse().map_err(invalid_option)?)
+            }
+            "--conflict" => {
+                import.conflict = value("conflict policy")?.parse().map_err(invalid_option)?
+            }
+            "-n" | "--dry-run" => import.dry_run = true,
+            _ => return Err(invalid(&format!("unexpected argument {:?}", arg))),
+        }
+    }
+    Ok(Command::Import(import))
+}
+
+/// Arguments of `melody rename`
+#[derive(Debug)]
+pub struct RenameArgs {
+    pub query: Option<Query>,
+    /// Replaces the template in the config
+    pub template: Option<PathTemplate>,
+    pub dry_run: bool,
+}
+
+fn parse_rename(mut args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let mut rename = RenameArgs {
+        query: None,
+        template: None,
+        dry_run: false,
+    };
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        match flag {
+            "-f" | "--filter" => {
+                rename.query = Some(
+                    Query::parse(&value("filter query")?)
+                        .map_err(|e| invalid(&format!("invalid query: {}", e)))?,
+                )
+            }
+            "-t" | "--template" => {
+                rename.template = Some(
+                    value("file name template")?
+                        .parse()
+                        .map_err(|e: MelodyErrors| invalid(&e.to_string()))?,
+                )
+            }
+            "-n" | "--dry-run" => rename.dry_run = true,
+            _ => return Err(invalid(&format!("unexpected argument {:?}", arg))),
+        }
+    }
+    Ok(Command::Rename(rename))
+}
+
+/// Arguments of `melody transcode`
+#[derive(Debug)]
+pub struct TranscodeArgs {
+    pub dest: PathBuf,
+    pub options: TranscodeOptions,
+    pub query: Option<Query>,
+    /// Saved playlist to convert instead of the library
+    pub playlist: Option<String>,
+}
+
+fn parse_transcode(mut args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let dest = next(&mut args, "destination directory")?.into();
+    let (mut format, mut bitrate) = (TranscodeFormat::Opus, None);
+    let (mut query, mut playlist) = (None, None);
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        match flag {
+            "-t" | "--to" => {
+                format = value("format")?
+                    .parse()
+                    .map_err(|e: MelodyErrors| invalid(&e.to_string()))?
+            }
+            "-b" | "--bitrate" => {
+                let kbps = value("bitrate")?;
+                bitrate = Some(
+                    kbps.trim_end_matches("k")
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|&b| b > 0)
+                        .ok_or_else(|| invalid(&format!("invalid bitrate {:?}", kbps)))?,
+                )
+            }
+            "-f" | "--filter" => {
+                query = Some(
+                    Query::parse(&value("filter query")?)
+                        .map_err(|e| invalid(&format!("invalid query: {}", e)))?,
+                )
+            }
+            "-p" | "--playlist" => playlist = Some(value("playlist name")?),
+            _ => return Err(invalid(&format!("unexpected argument {:?}", arg))),
+        }
+    }
+    let mut options = TranscodeOptions::new(format);
+    options.bitrate = bitrate.unwrap_or(options.bitrate);
+    Ok(Command::Transcode(TranscodeArgs {
+        dest,
+        options,
+        query,
+        playlist,
+    }))
+}
+
+/// Arguments of `melody dedupe`
+#[derive(Debug)]
+pub struct DedupeArgs {
+    pub find: DuplicateOptions,
+    pub resolve: ResolveOptions,
+}
+
+/// Parses the options of `melody duplicates`, `None` if `flag` isnt one of them
+fn duplicates_flag(
+    flag: &str,
+    mut value: impl FnMut(&str) -> Result<String, Errors>,
+    options: &mut DuplicateOptions,
+) -> Option<Result<(), Errors>> {
+    match flag {
+        "--fingerprint" => options.by = MatchBy::Fingerprint,
+        "--similarity" => {
+            let value = match value("similarity") {
+                Ok(value) => value,
+                Err(e) => return Some(Err(e)),
+            };
+            match value.parse().ok().filter(|s| (0.0..=1.0).contains(s)) {
+                Some(similarity) => options.similarity = similarity,
+                None => {
+                    return Some(Err(invalid(&format!(
+                        "similarity must be 0 to 1, got {:?}",
+                        value
+                    ))));
+                }
+            }
+            options.by = MatchBy::Fingerprint;
+        }
+        _ => return None,
+    }
+    Some(Ok(()))
+}
+
+fn parse_duplicates(mut args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let mut options = DuplicateOptions::default();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let value = |what: &str| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        duplicates_flag(flag, value, &mut options)
+            .unwrap_or_else(|| Err(invalid(&format!("unexpected argument {:?}", arg))))?;
+    }
+    Ok(Command::Duplicates(options))
+}
+
+fn parse_dedupe(mut args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let mut find = DuplicateOptions::default();
+    let (mut keep, mut prefer) = (KeepPolicy::defaults(), Vec::new());
+    let (mut action, mut dry_run) = (None, false);
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what: &str| value.clone().map_or_else(|| next(&mut args, what), Ok);
+        match flag {
+            "-a" | "--action" => {
+                action = Some(match value("dedupe action")?.to_lowercase().as_str() {
+                    "delete" | "rm" => ResolveAction::Delete,
+                    "symlink" | "link" => ResolveAction::Symlink,
+                    other => {
+                        return Err(invalid(&format!(
+                            "unknown dedupe action {:?}, expected delete or symlink",
+                            other
+                        )));
+                    }
+                })
+            }
+            "-q" | "--quarantine" => {
+                action = Some(ResolveAction::Quarantine(
+                    value("quarantine directory")?.into(),
+                ))
+            }
+            "-k" | "--keep" => {
+                keep = value("keep policies")?
+                    .split(',')
+                    .map(|p| p.trim().parse())
+                    .collect::<Result<_, MelodyErrors>>()
+                    .map_err(|e| invalid(&e.to_string()))?
+            }
+            "--prefer" => prefer.push(KeepPolicy::Directory(value("directory")?.into())),
+            "-n" | "--dry-run" => dry_run = true,
+            _ => duplicates_flag(flag, value, &mut find)
+                .unwrap_or_else(|| Err(invalid(&format!("unexpected argument {:?}", arg))))?,
+        }
+    }
+    let action = action.ok_or_else(|| invalid("missing --action or --quarantine"))?;
+    prefer.extend(keep);
+    Ok(Command::Dedupe(DedupeArgs {
+        find,
+        resolve: ResolveOptions {
+            keep: prefer,
+            action,
+            dry_run,
+        },
+    }))
+}
+
+fn parse_playlist(mut args: impl Iterator<Item = String>) -> Result<PlaylistCommand, Errors> {
+    use PlaylistCommand::*;
+    let command = match next(&mut args, "playlist command")?.as_str() {
+        "list" | "ls" => List,
+        "show" => {
+            let name = next(&mut args, "playlist name")?;
+            return Ok(Show(name, parse_options(args, true)?));
+        }
+        "play" => {
+            let name = next(&mut args, "playlist name")?;
+            return Ok(Play(name, parse_options(args, false)?.query));
+        }
+        "create" | "new" => Create(next(&mut args, "playlist name")?),
+        "delete" | "rm" => Delete(next(&mut args, "playlist name")?),
+        "rename" | "mv" => Rename(
+            next(&mut args, "playlist name")?,
+            next(&mut args, "new playlist name")?,
+        ),
+        "append" | "add" => {
+            let name = next(&mut args, "playlist name")?;
+            let paths: Vec<PathBuf> = args.by_ref().map(PathBuf::from).collect();
+            if paths.is_empty() {
+                return Err(invalid("missing path to append"));
+            }
+            Append(name, paths)
+        }
+        "import" => Import(
+            next(&mut args, "playlist name")?,
+            next(&mut args, "playlist file")?.into(),
+        ),
+        "export" => Export(
+            next(&mut args, "playlist name")?,
+            next(&mut args, "playlist file")?.into(),
+        ),
+        "smart" => {
+            let name = next(&mut args, "playlist name")?;
+            let rules = args
+                .by_ref()
+                .map(|rule| match rule.split_once('=') {
+                    Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                    None => Err(invalid(&format!("expected key=value, got {:?}", rule))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if rules.is_empty() {
+                return Err(invalid("missing smart playlist rules"));
+            }
+            Smart(name, rules)
+        }
+        other => return Err(invalid(&format!("unknown playlist command {:?}", other))),
+    };
+    done(args)?;
+    Ok(command)
+}
+
+fn is_help(arg: &str) -> bool {
+    matches!(arg, "help" | "-h" | "--help")
+}
+
+/// Parses the command line, without the program name
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Command, Errors> {
+    let mut args = args.peekable();
+    // Options without a command play the library
+    if args
+        .peek()
+        .is_none_or(|arg| arg.starts_with('-') && !is_help(arg))
+    {
+        return parse_options(args, false).map(|o| Command::Play(o.query));
+    }
+    let command = match args.next().as_deref() {
+        None => Command::Play(None),
+        Some("list") | Some("ls") => return parse_options(args, true).map(Command::List),
+        Some("scan") | Some("rescan") => Command::Scan,
+        Some("verify") | Some("check") => {
+            return parse_options(args, false).map(|o| Command::Verify(o.query));
+        }
+        Some("stats") | Some("statistics") => {
+            return parse_options(args, false).map(|o| Command::Stats(o.query));
+        }
+        Some("audit") => return parse_options(args, true).map(Command::Audit),
+        Some("import") => return parse_import(args),
+        Some("undo-organize") | Some("undo-import") => Command::UndoOrganize,
+        Some("rename") => return parse_rename(args),
+        Some("transcode") | Some("convert") => return parse_transcode(args),
+        Some("duplicates") | Some("dupes") => return parse_duplicates(args),
+        Some("dedupe") => return parse_dedupe(args),
+        Some("playlist") | Some("playlists") => return parse_playlist(args).map(Command::Playlist),
+        Some(arg) if is_help(arg) => Command::Help,
+        Some(other) => return Err(invalid(&format!("unknown command {:?}", other))),
+    };
+    done(args)?;
+    Ok(command)
+}
@@ -1,6 +1,9 @@
 use directories::{ProjectDirs, UserDirs};
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
+use std::time::Duration;
+
+use melody::{PathTemplate, RenameOptions, ScanOptions};
 
 use crate::Errors;
 
@@ -13,6 +16,14 @@ pub struct Settings {
     pub volume: f32,
     pub music: PathBuf,
     pub prioritize_cwd: bool,
+    /// How the music directory is walked
+    pub scan: ScanOptions,
+    /// Watch the music directory for changes while playing the library
+    pub watch: bool,
+    /// Where `melody import` puts songs inside the music directory
+    pub import_template: PathTemplate,
+    /// How `melody rename` names songs
+    pub rename_template: PathTemplate,
 }
 
 impl Settings {
@@ -29,12 +40,16 @@ impl Settings {
             let mut volume: f32 = 0.25;
             let mut prioritize_cwd: bool = false;
             let mut music: Option<PathBuf> = None;
-            for line in BufReader::new(file).lines().flatten() {
+            let mut scan = ScanOptions::default();
+            let mut watch = false;
+            let mut import_template = PathTemplate::default();
+            let mut rename_template = RenameOptions::default().template;
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
                 if line.starts_with("volume=") {
-                    if let Some(v) = line.get(7..) {
-                        if let Ok(v) = v.parse() {
-                            volume = v;
-                        }
+                    if let Some(v) = line.get(7..)
+                        && let Ok(v) = v.parse()
+                    {
+                        volume = v;
                     }
                 } else if line.starts_with("music=") {
                     if let Some(v) = line.get(6..) {
@@ -43,11 +58,37 @@ impl Settings {
                             music = Some(p)
                         }
                     }
-                } else if line.starts_with("prioritize_cwd") {
-                    if let Some(v) = line.get(15..) {
-                        if let Ok(v) = v.parse() {
-                            prioritize_cwd = v;
+                } else if line.starts_with("prioritize_cwd")
+                    && let Some(v) = line.get(15..)
+                    && let Ok(v) = v.parse()
+                {
+                    prioritize_cwd = v;
+                } else if let Some((key, v)) = line.split_once('=') {
+                    let v = v.trim();
+                    match key.trim() {
+                        "max_depth" => scan.max_depth = v.parse().ok(),
+                        "follow_symlinks" => {
+                            scan.follow_symlinks = v.parse().unwrap_or(scan.follow_symlinks)
+                        }
+                        "hidden" => scan.hidden = v.parse().unwrap_or(scan.hidden),
+                        "min_duration" => {
+                            scan.min_duration = v.parse().ok().map(Duration::from_secs_f64)
                         }
+                        "include" => scan.include.push(v.to_string()),
+                        "exclude" => scan.exclude.push(v.to_string()),
+                        "melodyignore" => {
+                            scan.ignore_files = v.parse().unwrap_or(scan.ignore_files)
+                        }
+                        "watch" => watch = v.parse().unwrap_or(watch),
+                        "import_template" => match v.parse() {
+                            Ok(template) => import_template = template,
+                            Err(e) => log::warn!("Ignoring import_template {:?}: {}", v, e),
+                        },
+                        "rename_template" => match v.parse() {
+                            Ok(template) => rename_template = template,
+                            Err(e) => log::warn!("Ignoring rename_template {:?}: {}", v, e),
+                        },
+                        _ => (),
                     }
                 }
             }
@@ -65,12 +106,16 @@ impl Settings {
                 volume,
                 prioritize_cwd,
                 music,
+                scan,
+                watch,
+                import_template,
+                rename_template,
             }
         };
-        if let Ok(v) = ::std::env::var("MELODY_VOLUME") {
-            if let Ok(v) = v.parse() {
-                settings.volume = v;
-            }
+        if let Ok(v) = ::std::env::var("MELODY_VOLUME")
+            && let Ok(v) = v.parse()
+        {
+            settings.volume = v;
         }
         if let Ok(v) = ::std::env::var("MELODY_MUSIC") {
             let p = PathBuf::from(v);
@@ -78,10 +123,10 @@ impl Settings {
                 settings.music = p;
             }
         }
-        if let Ok(v) = ::std::env::var("MELODY_PRIORITIZE_CWD") {
-            if let Ok(v) = v.parse() {
-                settings.prioritize_cwd = v;
-            }
+        if let Ok(v) = ::std::env::var("MELODY_PRIORITIZE_CWD")
+            && let Ok(v) = v.parse()
+        {
+            settings.prioritize_cwd = v;
         }
         Ok(settings)
     }
@@ -105,6 +150,10 @@ impl Settings {
             volume: 0.25,
             music: audio_dir,
             prioritize_cwd: false,
+            scan: ScanOptions::default(),
+            watch: false,
+            import_template: PathTemplate::default(),
+            rename_template: RenameOptions::default().template,
         })
     }
 }
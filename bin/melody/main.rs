@@ -3,6 +3,7 @@
 // TODO: Iterm display for album cover? (probably not)
 // TODO: Media Controls
 use std::env;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
@@ -10,7 +11,10 @@ use indicatif::{ProgressBar, ProgressStyle};
 
 use melody::*;
 
+mod cli;
 mod config;
+mod playlist;
+use cli::Command;
 use config::Settings;
 
 #[derive(Debug)]
@@ -21,54 +25,643 @@ pub enum Errors {
     FailedToGetConfig,
     FailedToCreatePlaylist,
     FailedToStartMusicPlayer,
+    InvalidArguments(String),
+    Melody(MelodyErrors),
+}
+
+impl From<MelodyErrors> for Errors {
+    fn from(e: MelodyErrors) -> Self {
+        Errors::Melody(e)
+    }
 }
 
 fn generate_progress_bar(s: Song) -> ProgressBar {
     let pb = ProgressBar::new(s.duration.as_secs());
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} {msg} [{elapsed_precise}] [{bar:40.cyan/blue}] ({eta_precise})",
-            )
-            .expect("Could not set progress bar template")
-            .progress_chars("#>-"),
-    );
+    let style = ProgressStyle::default_bar()
+        .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:40.cyan/blue}] ({eta_precise})")
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    pb.set_style(style.progress_chars("#>-"));
     let msg = format!(
         "{} - {} - {}",
         s.artist.unwrap_or_else(|| String::from("Unknown Artist")),
-        s.album.unwrap_or_else(|| String::from("Unkwon Album")),
-        s.title.unwrap_or_else(|| String::from("Unkown Title"))
+        s.album.unwrap_or_else(|| String::from("Unknown Album")),
+        s.title.unwrap_or_else(|| String::from("Unknown Title"))
     );
     pb.set_message(msg);
     pb
 }
 
+/// Progress bar for going through the files of the library, like `Fingerprinting [###] 3/10`
+fn work_progress_bar(what: &str) -> ProgressBar {
+    let pb = ProgressBar::new(0);
+    let style = ProgressStyle::default_bar()
+        .template(&format!(
+            "{{spinner:.green}} {} [{{bar:40.cyan/blue}}] {{pos}}/{{len}}",
+            what
+        ))
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    pb.set_style(style.progress_chars("#>-"));
+    pb
+}
+
+/// Progress bar for metadata scans, the length grows as files are found
+fn scan_progress_bar() -> ProgressBar {
+    let pb = ProgressBar::new(0);
+    let style = ProgressStyle::default_bar()
+        .template("{spinner:.green} Collecting tracks [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    pb.set_style(style.progress_chars("#>-"));
+    pb
+}
+
+fn update_scan_progress(pb: &ProgressBar, progress: &ScanProgress) {
+    pb.set_length(progress.seen as u64);
+    pb.set_position(progress.done() as u64);
+    if progress.failed != 0 {
+        pb.set_message(format!("({} failed)", progress.failed));
+    }
+}
+
 fn main() {
     human_panic::setup_panic!();
     pretty_env_logger::init();
-    play_test().unwrap()
+    match run() {
+        Ok(()) => (),
+        Err(Errors::InvalidArguments(msg)) => {
+            eprintln!("melody: {}\n\n{}", msg, cli::USAGE);
+            ::std::process::exit(2);
+        }
+        Err(Errors::Melody(e)) => {
+            match e.file() {
+                Some(file) => eprintln!("melody: {} ({})", e, file.display()),
+                None => eprintln!("melody: {}", e),
+            }
+            ::std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("melody: {:?}", e);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+fn run() -> Result<(), Errors> {
+    match cli::parse(env::args().skip(1))? {
+        Command::Play(query) => play_library(query),
+        Command::List(options) => {
+            let config = Settings::new()?;
+            let mut playlist = library_playlist(&config.music, &config.scan, false)
+                .ok_or(Errors::FailedToCreatePlaylist)?;
+            if let Some(query) = &options.query {
+                playlist.filter(query);
+            }
+            println!("{}", playlist.render(&options.output));
+            Ok(())
+        }
+        Command::Verify(query) => {
+            let config = Settings::new()?;
+            let mut playlist = library_playlist(&config.music, &config.scan, false)
+                .ok_or(Errors::FailedToCreatePlaylist)?;
+            if let Some(query) = &query {
+                playlist.filter(query);
+            }
+            let pb = work_progress_bar("Verifying");
+            let report = playlist.verify(|done, total| {
+                pb.set_length(total as u64);
+                pb.set_position(done as u64);
+            });
+            pb.finish_and_clear();
+            for (file, problems) in &report.problems {
+                for problem in problems {
+                    println!("{}: {}", file.display(), problem);
+                }
+            }
+            println!(
+                "{} checked, {} ok, {} with problems",
+                report.checked,
+                report.ok(),
+                report.problems.len()
+            );
+            Ok(())
+        }
+        Command::Stats(query) => {
+            let config = Settings::new()?;
+            let mut playlist = library_playlist(&config.music, &config.scan, false)
+                .ok_or(Errors::FailedToCreatePlaylist)?;
+            if let Some(query) = &query {
+                playlist.filter(query);
+            }
+            let history = PlayHistory::open_default()
+                .map_err(|e| log::warn!("Failed to open the play history: {}", e))
+                .ok();
+            println!("{}", playlist.stats(history.as_ref()));
+            Ok(())
+        }
+        Command::Audit(options) => {
+            let config = Settings::new()?;
+            let mut playlist = library_playlist(&config.music, &config.scan, false)
+                .ok_or(Errors::FailedToCreatePlaylist)?;
+            if let Some(query) = &options.query {
+                playlist.filter(query);
+            }
+            println!("{}", playlist.audit().render(options.output.format()));
+            Ok(())
+        }
+        Command::Scan => {
+            let config = Settings::new()?;
+            let mut library = Library::open_default()?;
+            let pb = scan_progress_bar();
+            let scan = library.scan_with(&config.music, &config.scan, |p| {
+                update_scan_progress(&pb, p)
+            });
+            pb.finish_and_clear();
+            let scan = scan?;
+            library.save()?;
+            for e in &scan.failed {
+                log::warn!("Failed to load {:?}: {}", e.file(), e);
+            }
+            println!(
+                "{} added, {} updated, {} removed, {} unchanged, {} failed",
+                scan.added,
+                scan.updated,
+                scan.removed,
+                scan.unchanged,
+                scan.failed.len()
+            );
+            Ok(())
+        }
+        Command::Import(args) => import(args),
+        Command::UndoOrganize => undo_organize(),
+        Command::Rename(args) => rename(args),
+        Command::Transcode(args) => transcode(args),
+        Command::Duplicates(options) => {
+            let config = Settings::new()?;
+            let groups = library_duplicates(&config, &options)?;
+            for group in &groups {
+                println!("{}", group.songs[0]);
+                for song in &group.songs {
+                    let bitrate = song.bitrate().map(|b| format!(", {} kbps", b));
+                    println!(
+                        "    {} ({}{})",
+                        song.file().display(),
+                        fmt_duration(&song.duration()),
+                        bitrate.unwrap_or_default()
+                    );
+                }
+            }
+            println!("{} groups of duplicates", groups.len());
+            Ok(())
+        }
+        Command::Dedupe(args) => dedupe(args),
+        Command::Playlist(command) => playlist::run(command),
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
+
+/// Bring the library index up to date after files were organized
+fn rescan(config: &Settings) -> Result<(), Errors> {
+    let mut library = Library::open_default()?;
+    library.scan_with(&config.music, &config.scan, |_| ())?;
+    library.save()?;
+    Ok(())
+}
+
+/// Organize the songs of a directory into the music directory and index them
+fn import(args: cli::ImportArgs) -> Result<(), Errors> {
+    let config = Settings::new()?;
+    let options = ImportOptions {
+        mode: args.mode,
+        template: args
+            .template
+            .unwrap_or_else(|| config.import_template.clone()),
+        conflict: args.conflict,
+        dry_run: args.dry_run,
+        journal: Some(OrganizeJournal::default_path()?),
+    };
+    let dir = env::current_dir()
+        .map_err(MelodyErrors::from)?
+        .join(args.dir);
+    let report = add_to_library_with(&dir, &config.music, &options)?;
+    // A dry run prints the plan with the mode, like `copy a -> b`
+    let (copied, replaced) = if args.dry_run {
+        (options.mode.name(), "replace")
+    } else {
+        ("copied", "replaced")
+    };
+    for (from, outcome) in &report.files {
+        match outcome {
+            ImportOutcome::Copied(to) => {
+                println!("{} {} -> {}", copied, from.display(), to.display())
+            }
+            ImportOutcome::Replaced(to) => {
+                println!("{} {} -> {}", replaced, from.display(), to.display())
+            }
+            ImportOutcome::Duplicate(to) => {
+                println!("duplicate {} ({})", from.display(), to.display())
+            }
+            ImportOutcome::Failed(e) => println!("failed {}: {}", from.display(), e),
+            ImportOutcome::Skipped(reason) => log::info!("Skipped {:?} ({:?})", from, reason),
+        }
+    }
+    println!(
+        "{} copied, {} replaced, {} duplicate, {} skipped, {} failed{}",
+        report.copied().count(),
+        report.replaced().count(),
+        report.duplicates().count(),
+        report.skipped(),
+        report.failed().count(),
+        if args.dry_run { " (dry run)" } else { "" }
+    );
+    if !args.dry_run && (report.copied().next().is_some() || report.replaced().next().is_some()) {
+        rescan(&config)?;
+    }
+    Ok(())
+}
+
+/// The groups of duplicate songs in the music directory
+fn library_duplicates(
+    config: &Settings,
+    options: &DuplicateOptions,
+) -> Result<Vec<DuplicateGroup>, Errors> {
+    let mut library = scanned_library(&config.music, &config.scan, false)
+        .ok_or(Errors::FailedToCreatePlaylist)?;
+    if options.by == MatchBy::Fingerprint {
+        fingerprint_library(&mut library);
+    }
+    let mut groups = library.duplicates(options);
+    // Only the songs a library scan keeps count
+    for group in &mut groups {
+        group
+            .songs
+            .retain(|s| s.file().starts_with(&config.music) && config.scan.keeps(s));
+    }
+    groups.retain(|g| g.songs.len() > 1);
+    Ok(groups)
+}
+
+/// Keep the best copy of every duplicate and get rid of the others
+fn dedupe(mut args: cli::DedupeArgs) -> Result<(), Errors> {
+    let config = Settings::new()?;
+    let cwd = env::current_dir().map_err(MelodyErrors::from)?;
+    for policy in &mut args.resolve.keep {
+        if let KeepPolicy::Directory(dir) = policy {
+            *dir = cwd.join(&dir);
+        }
+    }
+    if let ResolveAction::Quarantine(dir) = &mut args.resolve.action {
+        *dir = cwd.join(&dir);
+    }
+    let groups = library_duplicates(&config, &args.find)?;
+    let report = resolve_duplicates(&groups, &args.resolve);
+    // A dry run prints the plan, like `delete a`
+    let (deleted, quarantined, linked) = if args.resolve.dry_run {
+        ("delete", "quarantine", "link")
+    } else {
+        ("deleted", "quarantined", "linked")
+    };
+    for group in &report.groups {
+        println!("keep {}", group.keep.display());
+        for (file, outcome) in &group.files {
+            match outcome {
+                ResolveOutcome::Deleted => println!("    {} {}", deleted, file.display()),
+                ResolveOutcome::Quarantined(to) => {
+                    println!("    {} {} -> {}", quarantined, file.display(), to.display())
+                }
+                ResolveOutcome::Linked => println!("    {} {}", linked, file.display()),
+                ResolveOutcome::AlreadyLinked => {
+                    println!("    already linked {}", file.display())
+                }
+                ResolveOutcome::Failed(e) => println!("    failed {}: {}", file.display(), e),
+            }
+        }
+    }
+    println!(
+        "{} groups, {} resolved, {} failed{}",
+        report.groups.len(),
+        report.resolved(),
+        report.failed().count(),
+        if args.resolve.dry_run {
+            " (dry run)"
+        } else {
+            ""
+        }
+    );
+    if !args.resolve.dry_run && report.resolved() > 0 {
+        rescan(&config)?;
+    }
+    Ok(())
+}
+
+/// Rename songs of the library after their tags, and point the index,
+/// saved playlists and play history at the new names
+fn rename(args: cli::RenameArgs) -> Result<(), Errors> {
+    let config = Settings::new()?;
+    let mut playlist = library_playlist(&config.music, &config.scan, false)
+        .ok_or(Errors::FailedToCreatePlaylist)?;
+    if let Some(query) = &args.query {
+        playlist.filter(query);
+    }
+    let options = RenameOptions {
+        template: args.template.unwrap_or(config.rename_template),
+        dry_run: args.dry_run,
+    };
+    let report = playlist.rename_from_tags(&options);
+    let renamed = if args.dry_run { "rename" } else { "renamed" };
+    let (mut unchanged, mut skipped) = (0, 0);
+    for (file, outcome) in &report.files {
+        match outcome {
+            RenameOutcome::Renamed(to) => {
+                println!("{} {} -> {}", renamed, file.display(), to.display())
+            }
+            RenameOutcome::Conflict(to) => println!(
+                "conflict {} -> {}, the name is taken",
+                file.display(),
+                to.display()
+            ),
+            RenameOutcome::Failed(e) => println!("failed {}: {}", file.display(), e),
+            RenameOutcome::Unchanged => unchanged += 1,
+            RenameOutcome::Skipped => skipped += 1,
+        }
+    }
+    println!(
+        "{} {}, {} unchanged, {} conflicts, {} skipped, {} failed{}",
+        report.renamed().count(),
+        renamed,
+        unchanged,
+        report.conflicts().count(),
+        skipped,
+        report.failed().count(),
+        if args.dry_run { " (dry run)" } else { "" }
+    );
+    if args.dry_run {
+        return Ok(());
+    }
+    let changed = report.apply(
+        &mut Library::open_default()?,
+        &mut PlayHistory::open_default()?,
+        &PlaylistStore::open_default()?,
+    )?;
+    for name in changed {
+        println!("updated playlist {}", name);
+    }
+    Ok(())
+}
+
+/// Convert songs of the library, or a saved playlist, into another directory
+fn transcode(args: cli::TranscodeArgs) -> Result<(), Errors> {
+    let config = Settings::new()?;
+    let mut playlist = match &args.playlist {
+        Some(name) => {
+            let (playlist, unresolved) = PlaylistStore::open_default()?.load(name)?;
+            for e in &unresolved {
+                log::warn!("Skipping playlist entry: {}", e);
+            }
+            playlist
+        }
+        None => library_playlist(&config.music, &config.scan, false)
+            .ok_or(Errors::FailedToCreatePlaylist)?,
+    };
+    if let Some(query) = &args.query {
+        playlist.filter(query);
+    }
+    let dest = env::current_dir()
+        .map_err(MelodyErrors::from)?
+        .join(&args.dest);
+    let pb = work_progress_bar("Transcoding");
+    let report = playlist.transcode(&config.music, &dest, &args.options, |done, total| {
+        pb.set_length(total as u64);
+        pb.set_position(done as u64);
+    });
+    pb.finish_and_clear();
+    let report = report?;
+    for (file, outcome) in &report.files {
+        if let TranscodeOutcome::Failed(e) = outcome {
+            println!("failed {}: {}", file.display(), e);
+        }
+    }
+    println!(
+        "{} written, {} up to date, {} failed",
+        report.written().count(),
+        report.up_to_date(),
+        report.failed().count()
+    );
+    Ok(())
+}
+
+/// Reverse the last import and update the index
+fn undo_organize() -> Result<(), Errors> {
+    let config = Settings::new()?;
+    let report = OrganizeJournal::open_default()?.undo()?;
+    for e in &report.failed {
+        match e.file() {
+            Some(file) => println!("failed {}: {}", file.display(), e),
+            None => println!("failed: {}", e),
+        }
+    }
+    println!(
+        "{} undone, {} failed",
+        report.undone.len(),
+        report.failed.len()
+    );
+    if !report.undone.is_empty() {
+        rescan(&config)?;
+    }
+    Ok(())
+}
+
+/// Load every song in `dir` without using the library index
+fn dir_playlist(dir: PathBuf, options: &ScanOptions) -> Option<Playlist> {
+    let pb = scan_progress_bar();
+    let scan = Playlist::scan_dir_with(dir, options, |p| update_scan_progress(&pb, p));
+    pb.finish_and_clear();
+    let (playlist, report) = scan.ok()?;
+    for skipped in &report.skipped {
+        if skipped.reason != SkipReason::UnsupportedExtension {
+            log::warn!(
+                "Skipped {:?} ({:?}): {}",
+                skipped.error.file(),
+                skipped.reason,
+                skipped.error
+            );
+        }
+    }
+    Some(playlist)
+}
+
+/// Load the music library from the index, brought up to date with the music directory
+/// Only files that changed since the last scan are read. With `watching` the watcher
+/// keeps it up to date instead, it is only scanned if it doesnt know about the music
+/// directory yet
+fn scanned_library(music: &Path, options: &ScanOptions, watching: bool) -> Option<Library> {
+    let mut library = Library::open_default().ok()?;
+    if watching && library.songs().any(|s| s.file().starts_with(music)) {
+        return Some(library);
+    }
+    let pb = scan_progress_bar();
+    let scan = library.scan_with(music, options, |p| update_scan_progress(&pb, p));
+    pb.finish_and_clear();
+    match scan {
+        // Nothing to save if every file was reused
+        Ok(scan) if scan.added + scan.updated + scan.removed == 0 && scan.failed.is_empty() => {}
+        Ok(_) => {
+            if let Err(e) = library.save() {
+                log::warn!("Failed to save the library index: {}", e);
+            }
+        }
+        Err(e) => log::warn!("Failed to scan the library: {}", e),
+    }
+    Some(library)
 }
 
-fn play_test() -> Result<(), Errors> {
+/// Fingerprints the songs of the library that arent yet, with a progress bar
+fn fingerprint_library(library: &mut Library) {
+    let pb = work_progress_bar("Fingerprinting");
+    let failed = library.fingerprint(|done, total| {
+        pb.set_length(total as u64);
+        pb.set_position(done as u64);
+    });
+    pb.finish_and_clear();
+    for e in &failed {
+        log::warn!("Failed to fingerprint: {}", e);
+    }
+    if let Err(e) = library.save() {
+        log::warn!("Failed to save the library index: {}", e);
+    }
+}
+
+fn library_playlist(music: &Path, options: &ScanOptions, watching: bool) -> Option<Playlist> {
+    let library = scanned_library(music, options, watching)?;
+    let mut playlist = Playlist::from_library(&library);
+    playlist.tracks.retain(|s| options.keeps(s));
+    Some(playlist)
+}
+
+/// Play the library, or the current directory, shuffled unless `query` sorts
+fn play_library(query: Option<Query>) -> Result<(), Errors> {
     let config = Settings::new()?;
-    let playlist = if config.prioritize_cwd {
+    // Only the songs selected by the query count
+    let non_empty = |mut pl: Playlist| {
+        if let Some(query) = &query {
+            pl.filter(query);
+        }
+        if pl.is_empty() { None } else { Some(pl) }
+    };
+    let cwd = || {
         ::std::env::current_dir()
             .ok()
-            .and_then(Playlist::from_dir)
-            .and_then(|pl| if pl.is_empty() { None } else { Some(pl) })
-            .or_else(|| Playlist::from_dir(config.music.clone()))
-            .ok_or(Errors::FailedToCreatePlaylist)
+            .and_then(|dir| dir_playlist(dir, &config.scan))
+    };
+    let watching = config.watch && cfg!(feature = "watch");
+    let library = || library_playlist(&config.music, &config.scan, watching);
+    // The flag tells if the playlist came from the library
+    let (playlist, from_library) = if config.prioritize_cwd {
+        cwd()
+            .and_then(non_empty)
+            .map(|pl| (pl, false))
+            .or_else(|| library().and_then(non_empty).map(|pl| (pl, true)))
     } else {
-        Playlist::from_dir(config.music.clone())
-            .and_then(|pl| if pl.is_empty() { None } else { Some(pl) })
-            .or_else(|| ::std::env::current_dir().ok().and_then(Playlist::from_dir))
-            .ok_or(Errors::FailedToCreatePlaylist)
-    }?;
+        library()
+            .and_then(non_empty)
+            .map(|pl| (pl, true))
+            .or_else(|| cwd().and_then(non_empty).map(|pl| (pl, false)))
+    }
+    .ok_or(Errors::FailedToCreatePlaylist)?;
+    let shuffle = !query.as_ref().is_some_and(Query::has_sort);
+    if from_library && config.watch {
+        #[cfg(feature = "watch")]
+        if let Some(on_tick) = library_watch(&config, query.clone().unwrap_or_default()) {
+            return play(playlist, config.volume, shuffle, on_tick);
+        }
+        #[cfg(not(feature = "watch"))]
+        log::warn!("Melody was built without the watch feature, not watching the library");
+    }
+    play(playlist, config.volume, shuffle, |_| ())
+}
+
+/// Watch the music directory, keeping the index and the queue up to date
+/// New songs are only queued if they match `query`, returns a callback for `play`
+#[cfg(feature = "watch")]
+fn library_watch(config: &Settings, query: Query) -> Option<impl FnMut(&mut MusicPlayer) + use<>> {
+    let mut library = Library::open_default().ok()?;
+    let mut watcher = LibraryWatcher::new(&config.music, config.scan.clone())
+        .map_err(|e| log::warn!("Failed to watch the library: {}", e))
+        .ok()?;
+    let options = config.scan.clone();
+    Some(move |mp: &mut MusicPlayer| {
+        let events = watcher.poll(&mut library);
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            log::info!("{:?}", event);
+            let songs = |path| {
+                library
+                    .songs_of(path)
+                    .iter()
+                    .filter(|s| options.keeps(s) && query.matches(s))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            match event {
+                LibraryEvent::Added(path) => mp.enqueue(songs(&path)),
+                LibraryEvent::Changed(path) => {
+                    mp.dequeue(&path);
+                    mp.enqueue(songs(&path));
+                }
+                LibraryEvent::Removed(path) => mp.dequeue(&path),
+                LibraryEvent::Rescanned(_) => (),
+            }
+        }
+        if let Err(e) = library.save() {
+            log::warn!("Failed to save the library index: {}", e);
+        }
+    })
+}
+
+/// Count the song that just started in the play history
+fn record_play(history: &mut Option<PlayHistory>, mp: &MusicPlayer) {
+    let (Some(history), MusicPlayerStatus::NowPlaying(song)) = (history, mp.status()) else {
+        return;
+    };
+    history.record(&song);
+    if let Err(e) = history.save() {
+        log::warn!("Failed to save the play history: {}", e);
+    }
+}
+
+/// Start the next song that plays, the ones that cant be decoded are skipped
+fn start_playable(mp: &mut MusicPlayer) -> Result<(), MelodyErrors> {
+    loop {
+        match mp.start() {
+            Err(e) if !matches!(e.kind(), MelodyErrorsKind::EmptyQueue) => {
+                log::warn!("Skipping {:?}: {}", e.file().unwrap_or_default(), e);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Play `playlist` until the end of the queue
+/// `on_tick` is called with the player a few times a second
+pub fn play(
+    playlist: Playlist,
+    volume: f32,
+    shuffle: bool,
+    mut on_tick: impl FnMut(&mut MusicPlayer),
+) -> Result<(), Errors> {
     let mut mp = MusicPlayer::new(playlist);
-    mp.set_volume(config.volume);
-    drop(config);
-    mp.shuffle();
-    mp.start().unwrap();
+    mp.set_volume(volume);
+    if shuffle {
+        mp.shuffle();
+    }
+    start_playable(&mut mp).map_err(|_| Errors::FailedToStartMusicPlayer)?;
+    let mut history = PlayHistory::open_default()
+        .map_err(|e| log::warn!("Failed to open the play history: {}", e))
+        .ok();
+    record_play(&mut history, &mp);
     println!("{}", mp);
     let mut pb = match mp.status() {
         MusicPlayerStatus::NowPlaying(song) => generate_progress_bar(song),
@@ -80,10 +673,10 @@ fn play_test() -> Result<(), Errors> {
                 pb.set_position(song.elapsed.as_secs());
             }
             MusicPlayerStatus::Stopped(_) => {
-                if mp.queue().is_empty() {
+                if mp.queue().is_empty() || start_playable(&mut mp).is_err() {
                     break;
                 } else {
-                    mp.play_next();
+                    record_play(&mut history, &mp);
                     pb = match mp.status() {
                         MusicPlayerStatus::NowPlaying(song) => generate_progress_bar(song),
                         _ => unreachable!(),
@@ -92,6 +685,7 @@ fn play_test() -> Result<(), Errors> {
             }
             _ => (),
         }
+        on_tick(&mut mp);
         thread::sleep(Duration::from_millis(250))
     }
     println!("{}", mp.status());
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use melody::{MelodyErrors, Playlist, PlaylistStore, SmartPlaylist};
+
+use crate::cli::PlaylistCommand;
+use crate::config::Settings;
+use crate::{Errors, play};
+
+/// Warn about entries that could not be loaded
+fn report(unresolved: &[MelodyErrors]) {
+    for e in unresolved {
+        match e.file() {
+            Some(file) => eprintln!("Skipped {}: {}", file.display(), e),
+            None => eprintln!("Skipped entry: {}", e),
+        }
+    }
+}
+
+/// Load every song of a file, directory or playlist file
+fn load_path(path: &Path) -> Result<Playlist, Errors> {
+    if path.is_file()
+        && let Ok((playlist, unresolved)) = Playlist::open(path)
+        && !playlist.is_empty()
+    {
+        report(&unresolved);
+        return Ok(playlist);
+    }
+    Playlist::from_dir(path.to_path_buf()).ok_or(Errors::FailedToCreatePlaylist)
+}
+
+pub fn run(command: PlaylistCommand) -> Result<(), Errors> {
+    use PlaylistCommand::*;
+    let store = PlaylistStore::open_default()?;
+    match command {
+        List => {
+            for name in store.list()? {
+                if store.is_smart(&name) {
+                    println!("{} (smart)", name);
+                } else {
+                    println!("{}", name);
+                }
+            }
+        }
+        Show(name, options) => {
+            let (mut playlist, unresolved) = store.load(&name)?;
+            report(&unresolved);
+            if let Some(query) = &options.query {
+                playlist.filter(query);
+            }
+            println!("{}", playlist.render(&options.output));
+        }
+        Play(name, query) => {
+            let (mut playlist, unresolved) = store.load(&name)?;
+            report(&unresolved);
+            if let Some(query) = &query {
+                playlist.filter(query);
+            }
+            let config = Settings::new()?;
+            play(playlist, config.volume, false, |_| ())?;
+        }
+        Create(name) => store.create(&name)?,
+        Delete(name) => store.delete(&name)?,
+        Rename(from, to) => store.rename(&from, &to)?,
+        Append(name, paths) => {
+            for path in paths {
+                let playlist = load_path(&path)?;
+                store.append(&name, &playlist.tracks)?;
+                println!("Added {} songs to {}", playlist.tracks.len(), name);
+            }
+        }
+        Import(name, file) => {
+            let (playlist, unresolved) = Playlist::open(&file)?;
+            report(&unresolved);
+            store.save(&name, &playlist)?;
+            println!("Saved {} songs as {}", playlist.tracks.len(), name);
+        }
+        Export(name, file) => {
+            let (playlist, unresolved) = store.load(&name)?;
+            report(&unresolved);
+            playlist.save(&file)?;
+        }
+        Smart(name, rules) => {
+            let rules: Vec<String> = rules.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let smart = SmartPlaylist::parse(&rules.join("\n"))?;
+            store.save_smart(&name, &smart)?;
+            let (playlist, _) = store.load(&name)?;
+            println!(
+                "Saved {}, it currently has {} songs",
+                name,
+                playlist.tracks.len()
+            );
+        }
+    }
+    Ok(())
+}
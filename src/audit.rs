@@ -0,0 +1,495 @@
+use crate::render::{OutputFormat, csv_field, json_string};
+use crate::song::{Playlist, Song};
+use crate::utils::non_empty_tag;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Version of the JSON report, bumped on incompatible changes
+pub const AUDIT_VERSION: u32 = 1;
+
+/// An album, the songs with the same album name in one directory
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct AlbumRef {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl fmt::Display for AlbumRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "album {:?} ({})", self.name, self.dir.display())
+    }
+}
+
+/// One spelling of a tag and the files that use it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagValue {
+    /// `None` if the tag isnt set
+    pub value: Option<String>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Something to fix with a tag editor, found by `Playlist::audit`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Issue {
+    /// A track without some of artist, album, title and track number
+    MissingTags {
+        file: PathBuf,
+        tags: Vec<&'static str>,
+    },
+    /// The tracks of an album dont agree on its album artist or year
+    Inconsistent {
+        album: AlbumRef,
+        tag: &'static str,
+        /// The most used value first
+        values: Vec<TagValue>,
+    },
+    /// Track numbers missing below the highest one of an album or disc
+    TrackGap {
+        album: AlbumRef,
+        disc: Option<u32>,
+        missing: Vec<u32>,
+    },
+    /// Tracks of an album or disc with the same number
+    DuplicateTrack {
+        album: AlbumRef,
+        disc: Option<u32>,
+        track: u32,
+        files: Vec<PathBuf>,
+    },
+    /// An artist, album artist, album or genre spelled with different capitalization
+    Capitalization {
+        tag: &'static str,
+        /// The most used spelling first
+        values: Vec<TagValue>,
+    },
+}
+
+impl Issue {
+    /// Name used in the JSON and CSV reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Issue::MissingTags { .. } => "missing_tags",
+            Issue::Inconsistent { .. } => "inconsistent_tag",
+            Issue::TrackGap { .. } => "track_gap",
+            Issue::DuplicateTrack { .. } => "duplicate_track",
+            Issue::Capitalization { .. } => "capitalization",
+        }
+    }
+    /// The files that need their tags fixed
+    pub fn files(&self) -> Vec<&Path> {
+        match self {
+            Issue::MissingTags { file, .. } => vec![file.as_path()],
+            Issue::DuplicateTrack { files, .. } => files.iter().map(PathBuf::as_path).collect(),
+            Issue::Inconsistent { values, .. } | Issue::Capitalization { values, .. } => values
+                .iter()
+                .flat_map(|v| v.files.iter().map(PathBuf::as_path))
+                .collect(),
+            Issue::TrackGap { .. } => Vec::new(),
+        }
+    }
+    fn json(&self) -> String {
+        let paths = |files: &[PathBuf]| {
+            let files: Vec<String> = files
+                .iter()
+                .map(|f| json_string(&f.to_string_lossy()))
+                .collect();
+            format!("[{}]", files.join(","))
+        };
+        let values = |values: &[TagValue]| {
+            let values: Vec<String> = values
+                .iter()
+                .map(|v| {
+                    format!(
+                        "{{\"value\":{},\"files\":{}}}",
+                        v.value.as_deref().map_or("null".to_string(), json_string),
+                        paths(&v.files)
+                    )
+                })
+                .collect();
+            format!("[{}]", values.join(","))
+        };
+        let album = |album: &AlbumRef| {
+            format!(
+                "\"album\":{},\"dir\":{}",
+                json_string(&album.name),
+                json_string(&album.dir.to_string_lossy())
+            )
+        };
+        let disc = |disc: &Option<u32>| disc.map_or("null".to_string(), |d| d.to_string());
+        let fields = match self {
+            Issue::MissingTags { file, tags } => {
+                let tags: Vec<String> = tags.iter().map(|t| json_string(t)).collect();
+                format!(
+                    "\"file\":{},\"tags\":[{}]",
+                    json_string(&file.to_string_lossy()),
+                    tags.join(",")
+                )
+            }
+            Issue::Inconsistent {
+                album: a,
+                tag,
+                values: v,
+            } => format!(
+                "{},\"tag\":{},\"values\":{}",
+                album(a),
+                json_string(tag),
+                values(v)
+            ),
+            Issue::TrackGap {
+                album: a,
+                disc: d,
+                missing,
+            } => {
+                let missing: Vec<String> = missing.iter().map(|n| n.to_string()).collect();
+                format!(
+                    "{},\"disc\":{},\"missing\":[{}]",
+                    album(a),
+                    disc(d),
+                    missing.join(",")
+                )
+            }
+            Issue::DuplicateTrack {
+                album: a,
+                disc: d,
+                track,
+                files,
+            } => format!(
+                "{},\"disc\":{},\"track\":{},\"files\":{}",
+                album(a),
+                disc(d),
+                track,
+                paths(files)
+            ),
+            Issue::Capitalization { tag, values: v } => {
+                format!("\"tag\":{},\"values\":{}", json_string(tag), values(v))
+            }
+        };
+        format!("{{\"kind\":{},{}}}", json_string(self.kind()), fields)
+    }
+    /// Rows of `kind,album,dir,disc,tag,value,file`, one per file
+    fn csv(&self) -> Vec<String> {
+        let row =
+            |album: Option<&AlbumRef>, disc: Option<u32>, tag: &str, value: &str, file: &Path| {
+                [
+                    self.kind(),
+                    album.map_or("", |a| &a.name),
+                    &album.map_or(String::new(), |a| a.dir.to_string_lossy().into_owned()),
+                    &disc.map_or(String::new(), |d| d.to_string()),
+                    tag,
+                    value,
+                    &file.to_string_lossy(),
+                ]
+                .iter()
+                .map(|f| csv_field(f))
+                .collect::<Vec<_>>()
+                .join(",")
+            };
+        let value_rows = |album: Option<&AlbumRef>, tag: &str, values: &[TagValue]| {
+            values
+                .iter()
+                .flat_map(|v| {
+                    let value = v.value.as_deref().unwrap_or_default();
+                    v.files.iter().map(move |f| row(album, None, tag, value, f))
+                })
+                .collect()
+        };
+        match self {
+            Issue::MissingTags { file, tags } => tags
+                .iter()
+                .map(|tag| row(None, None, tag, "", file))
+                .collect(),
+            Issue::Inconsistent { album, tag, values } => value_rows(Some(album), tag, values),
+            Issue::TrackGap {
+                album,
+                disc,
+                missing,
+            } => missing
+                .iter()
+                .map(|n| row(Some(album), *disc, "track", &n.to_string(), Path::new("")))
+                .collect(),
+            Issue::DuplicateTrack {
+                album,
+                disc,
+                track,
+                files,
+            } => files
+                .iter()
+                .map(|f| row(Some(album), *disc, "track", &track.to_string(), f))
+                .collect(),
+            Issue::Capitalization { tag, values } => value_rows(None, tag, values),
+        }
+    }
+}
+
+/// Writes the spellings like `"Aphex Twin" (10 tracks), none (1 track)`
+fn fmt_values(values: &[TagValue]) -> String {
+    values
+        .iter()
+        .map(|v| {
+            let value = v
+                .value
+                .as_ref()
+                .map_or("none".to_string(), |s| format!("{:?}", s));
+            let s = if v.files.len() == 1 { "" } else { "s" };
+            format!("{} ({} track{})", value, v.files.len(), s)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn fmt_disc(disc: Option<u32>) -> String {
+    disc.map_or(String::new(), |d| format!(" disc {}", d))
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::MissingTags { file, tags } => {
+                write!(f, "{}: missing {}", file.display(), tags.join(", "))
+            }
+            Issue::Inconsistent { album, tag, values } => {
+                write!(f, "{}: {} differs, {}", album, tag, fmt_values(values))
+            }
+            Issue::TrackGap {
+                album,
+                disc,
+                missing,
+            } => {
+                let missing: Vec<String> = missing.iter().map(|n| n.to_string()).collect();
+                write!(
+                    f,
+                    "{}{}: track {} missing",
+                    album,
+                    fmt_disc(*disc),
+                    missing.join(", ")
+                )
+            }
+            Issue::DuplicateTrack {
+                album,
+                disc,
+                track,
+                files,
+            } => {
+                let files: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+                write!(
+                    f,
+                    "{}{}: track {} is used by {}",
+                    album,
+                    fmt_disc(*disc),
+                    track,
+                    files.join(", ")
+                )
+            }
+            Issue::Capitalization { tag, values } => {
+                write!(f, "{} capitalized differently, {}", tag, fmt_values(values))
+            }
+        }
+    }
+}
+
+/// Outcome of `Playlist::audit`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AuditReport {
+    /// Number of tracks audited
+    pub tracks: usize,
+    /// Missing tags first, then album problems sorted by album, then capitalization
+    pub issues: Vec<Issue>,
+}
+
+impl AuditReport {
+    /// Writes the report for people, or for scripts as JSON or CSV.
+    /// Templates arent used, they write the same as a table
+    ///
+    /// JSON looks like `{"version":1,"tracks":12,"issues":[{"kind":"track_gap",
+    /// "album":"SAW","dir":"/music/SAW","disc":null,"missing":[3]}]}`,
+    /// CSV has a row per file and issue: `kind,album,dir,disc,tag,value,file`
+    pub fn render(&self, format: &OutputFormat) -> String {
+        match format {
+            OutputFormat::Json => {
+                let issues: Vec<String> = self.issues.iter().map(Issue::json).collect();
+                format!(
+                    "{{\"version\":{},\"tracks\":{},\"issues\":[{}]}}",
+                    AUDIT_VERSION,
+                    self.tracks,
+                    issues.join(",")
+                )
+            }
+            OutputFormat::Csv => {
+                let mut lines = vec!["kind,album,dir,disc,tag,value,file".to_string()];
+                lines.extend(self.issues.iter().flat_map(Issue::csv));
+                lines.join("\n")
+            }
+            OutputFormat::Table | OutputFormat::Template(_) => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        write!(f, "{} issues in {} tracks", self.issues.len(), self.tracks)
+    }
+}
+
+/// The artist, album_artist, album or genre of `song`
+fn name_tag<'a>(song: &'a Song, name: &str) -> Option<&'a str> {
+    match name {
+        "artist" => song.artist(),
+        "album_artist" => song.album_artist(),
+        "album" => song.album(),
+        _ => song.genre(),
+    }
+}
+
+/// Groups the files by value, the most used value first
+fn tag_values<'a>(songs: impl Iterator<Item = (Option<String>, &'a Song)>) -> Vec<TagValue> {
+    let mut by_value: BTreeMap<Option<String>, Vec<PathBuf>> = BTreeMap::new();
+    for (value, song) in songs {
+        by_value
+            .entry(value)
+            .or_default()
+            .push(song.file().to_path_buf());
+    }
+    let mut values: Vec<TagValue> = by_value
+        .into_iter()
+        .map(|(value, files)| TagValue { value, files })
+        .collect();
+    values.sort_by_key(|v| Reverse(v.files.len()));
+    values
+}
+
+/// Checks the album artist, year and track numbers of one album
+fn audit_album(album: &AlbumRef, songs: &[&Song], issues: &mut Vec<Issue>) {
+    for name in ["album_artist", "year"] {
+        let value = |s: &Song| match name {
+            "year" => s.year().map(|y| y.to_string()),
+            _ => non_empty_tag(s.album_artist()).map(String::from),
+        };
+        let values = tag_values(songs.iter().map(|s| (value(s), *s)));
+        if values.len() > 1 {
+            issues.push(Issue::Inconsistent {
+                album: album.clone(),
+                tag: name,
+                values,
+            });
+        }
+    }
+    let mut discs: BTreeMap<Option<u32>, BTreeMap<u32, Vec<PathBuf>>> = BTreeMap::new();
+    for song in songs {
+        if let Some(track) = song.track() {
+            discs
+                .entry(song.disc())
+                .or_default()
+                .entry(track)
+                .or_default()
+                .push(song.file().to_path_buf());
+        }
+    }
+    for (disc, tracks) in discs {
+        // A single numbered track is likely a lone song, not an incomplete album
+        if tracks.len() > 1
+            && let Some(&last) = tracks.keys().next_back()
+        {
+            let missing: Vec<u32> = (1..last).filter(|n| !tracks.contains_key(n)).collect();
+            if !missing.is_empty() {
+                issues.push(Issue::TrackGap {
+                    album: album.clone(),
+                    disc,
+                    missing,
+                });
+            }
+        }
+        for (track, files) in tracks {
+            if files.len() > 1 {
+                issues.push(Issue::DuplicateTrack {
+                    album: album.clone(),
+                    disc,
+                    track,
+                    files,
+                });
+            }
+        }
+    }
+}
+
+impl Playlist {
+    /// Looks for tags to fix: tracks missing artist, album, title or track number,
+    /// albums whose tracks disagree on the album artist or year, gaps and duplicates
+    /// in the track numbers and names capitalized differently across songs.
+    /// Albums are the songs with the same album name in one directory
+    /// # Example
+    /// ```no_run
+    /// use melody::OutputFormat;
+    /// let playlist = melody::Playlist::from_dir(std::path::PathBuf::from("/home/user/Music")).unwrap();
+    /// println!("{}", playlist.audit().render(&OutputFormat::Json));
+    /// ```
+    pub fn audit(&self) -> AuditReport {
+        let mut issues = Vec::new();
+        // Keyed by the lowercased name, so different capitalization is still one album
+        let mut albums: BTreeMap<(String, PathBuf), Vec<&Song>> = BTreeMap::new();
+        for song in &self.tracks {
+            let present = [
+                ("artist", non_empty_tag(song.artist()).is_some()),
+                ("album", non_empty_tag(song.album()).is_some()),
+                ("title", non_empty_tag(song.title()).is_some()),
+                ("track", song.track().is_some()),
+            ];
+            let tags: Vec<&'static str> = present
+                .iter()
+                .filter(|(_, present)| !present)
+                .map(|(name, _)| *name)
+                .collect();
+            if !tags.is_empty() {
+                issues.push(Issue::MissingTags {
+                    file: song.file().to_path_buf(),
+                    tags,
+                });
+            }
+            if let Some(album) = non_empty_tag(song.album()) {
+                let dir = song.file().parent().unwrap_or(Path::new("")).to_path_buf();
+                albums
+                    .entry((album.to_lowercase(), dir))
+                    .or_default()
+                    .push(song);
+            }
+        }
+        for ((_, dir), songs) in &albums {
+            let album = AlbumRef {
+                name: non_empty_tag(songs[0].album())
+                    .unwrap_or_default()
+                    .to_string(),
+                dir: dir.clone(),
+            };
+            audit_album(&album, songs, &mut issues);
+        }
+        for name in ["artist", "album_artist", "album", "genre"] {
+            let mut spellings: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+            for song in &self.tracks {
+                if let Some(v) = non_empty_tag(name_tag(song, name)) {
+                    spellings.entry(v.to_lowercase()).or_default().insert(v);
+                }
+            }
+            for (lower, spelled) in spellings {
+                if spelled.len() < 2 {
+                    continue;
+                }
+                let songs = self.tracks.iter().filter_map(|s| {
+                    let v = non_empty_tag(name_tag(s, name))?;
+                    (v.to_lowercase() == lower).then(|| (Some(v.to_string()), s))
+                });
+                issues.push(Issue::Capitalization {
+                    tag: name,
+                    values: tag_values(songs),
+                });
+            }
+        }
+        AuditReport {
+            tracks: self.tracks.len(),
+            issues,
+        }
+    }
+}
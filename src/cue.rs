@@ -0,0 +1,556 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::{Playlist, Song};
+use crate::utils::{get_filetype, read_text, resolve_entry};
+use lofty::file::{TaggedFile, TaggedFileExt};
+use lofty::tag::ItemKey;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A track of a CUE sheet
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CueTrack {
+    /// Track number
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Position of `INDEX 01` inside the file
+    pub start: Duration,
+}
+
+/// A `FILE` entry of a CUE sheet and the tracks stored in it
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CueFile {
+    /// Path as written in the sheet
+    pub path: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// A parsed CUE sheet
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CueSheet {
+    /// Album artist
+    pub performer: Option<String>,
+    /// Album title
+    pub title: Option<String>,
+    pub genre: Option<String>,
+    /// Release year, from `REM DATE`
+    pub year: Option<u32>,
+    pub files: Vec<CueFile>,
+}
+
+/// Splits a CUE line into words, keeping quoted strings together
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            words.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    words
+}
+
+/// Parses a `mm:ss:ff` timestamp, there are 75 frames in a second
+fn timestamp(s: &str) -> Option<Duration> {
+    let mut parts = s.split(':').map(|p| p.parse::<u64>().ok());
+    let (min, sec, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || sec >= 60 || frames >= 75 {
+        return None;
+    }
+    Some(Duration::from_secs(min * 60 + sec) + Duration::from_nanos(frames * 1_000_000_000 / 75))
+}
+
+/// Formats a `mm:ss:ff` timestamp, rounded to the nearest frame
+fn fmt_timestamp(d: Duration) -> String {
+    let frames = d.as_secs() * 75 + (d.subsec_nanos() as u64 * 75 + 500_000_000) / 1_000_000_000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / (75 * 60),
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+/// Quotes a CUE string, which has no way to escape `"`
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+impl CueSheet {
+    /// Parse the text of a CUE sheet
+    pub fn parse(text: &str) -> Result<Self, MelodyErrors> {
+        let malformed = |line: usize, what: &str| {
+            MelodyErrors::new(
+                MelodyErrorsKind::MalformedPlaylist,
+                &format!("Malformed CUE sheet at line {}: {}", line + 1, what),
+                None,
+            )
+        };
+        let mut sheet = CueSheet::default();
+        for (n, line) in text.lines().enumerate() {
+            let words = words(line);
+            let Some(command) = words.first() else {
+                continue;
+            };
+            let arg = words.get(1).cloned();
+            match command.to_uppercase().as_str() {
+                "FILE" => sheet.files.push(CueFile {
+                    path: arg.ok_or_else(|| malformed(n, "FILE without a path"))?,
+                    tracks: Vec::new(),
+                }),
+                "TRACK" => {
+                    let number = arg
+                        .and_then(|a| a.parse().ok())
+                        .ok_or_else(|| malformed(n, "invalid TRACK number"))?;
+                    sheet
+                        .files
+                        .last_mut()
+                        .ok_or_else(|| malformed(n, "TRACK before FILE"))?
+                        .tracks
+                        .push(CueTrack {
+                            number,
+                            ..CueTrack::default()
+                        });
+                }
+                "INDEX" => {
+                    // Only `INDEX 01` marks the start of a track, `INDEX 00` is the pregap
+                    if arg.as_deref().and_then(|a| a.parse::<u32>().ok()) != Some(1) {
+                        continue;
+                    }
+                    let start = words
+                        .get(2)
+                        .and_then(|t| timestamp(t))
+                        .ok_or_else(|| malformed(n, "invalid INDEX time"))?;
+                    let track = sheet
+                        .files
+                        .last_mut()
+                        .and_then(|f| f.tracks.last_mut())
+                        .ok_or_else(|| malformed(n, "INDEX before TRACK"))?;
+                    track.start = start;
+                }
+                "TITLE" | "PERFORMER" => {
+                    let track = sheet.files.last_mut().and_then(|f| f.tracks.last_mut());
+                    let field = match (command.to_uppercase().as_str(), track) {
+                        ("TITLE", Some(track)) => &mut track.title,
+                        ("TITLE", None) => &mut sheet.title,
+                        (_, Some(track)) => &mut track.performer,
+                        (_, None) => &mut sheet.performer,
+                    };
+                    *field = arg.filter(|a| !a.is_empty());
+                }
+                "REM" => match arg.as_deref().map(str::to_uppercase).as_deref() {
+                    Some("GENRE") => sheet.genre = words.get(2).cloned(),
+                    // Usually just the year, but full dates show up too
+                    Some("DATE") => {
+                        sheet.year = words
+                            .get(2)
+                            .and_then(|d| d.get(..4))
+                            .and_then(|y| y.parse().ok())
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        if sheet.files.iter().all(|f| f.tracks.is_empty()) {
+            return Err(malformed(0, "no tracks"));
+        }
+        Ok(sheet)
+    }
+
+    /// Read and parse a `.cue` file
+    pub fn open(path: &Path) -> Result<Self, MelodyErrors> {
+        Self::parse(&read_text(path)?).map_err(|e| e.with_file(path))
+    }
+
+    /// Reads the `CUESHEET` tag embedded in a file (FLAC, APE, WavPack)
+    pub fn embedded(file: &Path) -> Option<Self> {
+        let tagged_file = lofty::read_from_path(file).ok()?;
+        Self::parse(&embedded_text(&tagged_file)?).ok()
+    }
+
+    /// A sheet for `songs`, the tracks of a single file, stored as `path`
+    /// Album tags that every track shares go to the top of the sheet
+    pub(crate) fn from_songs(songs: &[Song], path: String) -> Self {
+        let first = songs.first();
+        let performer = first.and_then(|s| s.album_artist.clone().or_else(|| s.artist.clone()));
+        let tracks = songs
+            .iter()
+            .enumerate()
+            .map(|(i, song)| CueTrack {
+                number: song.track.unwrap_or(i as u32 + 1),
+                title: song.title.clone(),
+                performer: song
+                    .artist
+                    .clone()
+                    .filter(|a| Some(a) != performer.as_ref()),
+                start: song.start.unwrap_or_default(),
+            })
+            .collect();
+        Self {
+            performer,
+            title: first.and_then(|s| s.album.clone()),
+            genre: first.and_then(|s| s.genre.clone()),
+            year: first.and_then(|s| s.year),
+            files: vec![CueFile { path, tracks }],
+        }
+    }
+
+    /// Virtual songs for the tracks of a single `FILE` entry
+    /// `song` is the loaded audio file the tracks point into
+    fn songs_of(&self, cue_file: &CueFile, song: &Song) -> Vec<Song> {
+        let file_duration = song.duration();
+        cue_file
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                let end = cue_file
+                    .tracks
+                    .get(i + 1)
+                    .map(|next| next.start)
+                    .unwrap_or(file_duration);
+                Song {
+                    artist: track
+                        .performer
+                        .clone()
+                        .or_else(|| self.performer.clone())
+                        .or_else(|| song.artist.clone()),
+                    album: self.title.clone().or_else(|| song.album.clone()),
+                    album_artist: self.performer.clone().or_else(|| song.album_artist.clone()),
+                    title: track.title.clone(),
+                    track: Some(track.number),
+                    disc: song.disc,
+                    genre: self.genre.clone().or_else(|| song.genre.clone()),
+                    year: self.year.or(song.year),
+                    compilation: song.compilation,
+                    duration: end.saturating_sub(track.start),
+                    bitrate: song.bitrate,
+                    file: song.file.clone(),
+                    elapsed: Duration::from_millis(0),
+                    start: Some(track.start),
+                }
+            })
+            .collect()
+    }
+
+    /// Virtual songs for every track of the sheet
+    /// `base` is the directory `FILE` entries are relative to.
+    /// Returns the songs along with the files that could not be loaded
+    pub fn songs(&self, base: &Path) -> (Vec<Song>, Vec<MelodyErrors>) {
+        let mut songs = Vec::new();
+        let mut errors = Vec::new();
+        for cue_file in &self.files {
+            match resolve_entry(base, &cue_file.path).and_then(Song::load) {
+                Ok(song) => songs.extend(self.songs_of(cue_file, &song)),
+                Err(e) => errors.push(e),
+            }
+        }
+        (songs, errors)
+    }
+}
+
+/// Writes the sheet in the format `parse` reads
+impl fmt::Display for CueSheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(genre) = &self.genre {
+            writeln!(f, "REM GENRE {}", quoted(genre))?;
+        }
+        if let Some(year) = self.year {
+            writeln!(f, "REM DATE {}", year)?;
+        }
+        if let Some(performer) = &self.performer {
+            writeln!(f, "PERFORMER {}", quoted(performer))?;
+        }
+        if let Some(title) = &self.title {
+            writeln!(f, "TITLE {}", quoted(title))?;
+        }
+        for file in &self.files {
+            let kind = match get_filetype(Path::new(&file.path)).as_deref() {
+                Some("mp3") => "MP3",
+                Some("aiff" | "aif") => "AIFF",
+                _ => "WAVE",
+            };
+            writeln!(f, "FILE {} {}", quoted(&file.path), kind)?;
+            for track in &file.tracks {
+                writeln!(f, "  TRACK {:02} AUDIO", track.number)?;
+                if let Some(title) = &track.title {
+                    writeln!(f, "    TITLE {}", quoted(title))?;
+                }
+                if let Some(performer) = &track.performer {
+                    writeln!(f, "    PERFORMER {}", quoted(performer))?;
+                }
+                writeln!(f, "    INDEX 01 {}", fmt_timestamp(track.start))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Text of the `CUESHEET` tag of an already parsed file
+fn embedded_text(tagged_file: &TaggedFile) -> Option<String> {
+    tagged_file.tags().iter().find_map(|tag| {
+        tag.items().find_map(|item| match item.key() {
+            ItemKey::Unknown(key) if key.eq_ignore_ascii_case("CUESHEET") => {
+                item.value().text().map(String::from)
+            }
+            _ => None,
+        })
+    })
+}
+
+/// Loads `file` and splits it into the tracks of its CUE sheet, see `split_song`
+pub(crate) fn load_tracks(file: PathBuf) -> Result<Vec<Song>, MelodyErrors> {
+    let (song, tagged_file) = Song::load_tagged(file)?;
+    Ok(split_song(song, embedded_text(&tagged_file)))
+}
+
+/// Possible locations of a sidecar CUE sheet for `file`
+/// `album.cue` and `album.flac.cue` are both common
+fn sidecars(file: &Path) -> [PathBuf; 2] {
+    let mut full = file.as_os_str().to_owned();
+    full.push(".cue");
+    [file.with_extension("cue"), PathBuf::from(full)]
+}
+
+/// Splits a song into the tracks of its CUE sheet, if it has one
+/// Checks for a sidecar `.cue` file first, then `embedded`, the text of its `CUESHEET` tag.
+/// Songs without a sheet are returned as is
+fn split_song(song: Song, embedded: Option<String>) -> Vec<Song> {
+    if song.start.is_some() {
+        return vec![song];
+    }
+    let base = song.file().parent().unwrap_or_else(|| Path::new(""));
+    for sidecar in sidecars(song.file()) {
+        if !sidecar.is_file() {
+            continue;
+        }
+        let Ok(sheet) = CueSheet::open(&sidecar) else {
+            continue;
+        };
+        // Rips are often transcoded after the sheet was written,
+        // so a single `FILE` entry is trusted even if the name differs
+        let matching = if sheet.files.len() == 1 {
+            sheet.files.first()
+        } else {
+            sheet
+                .files
+                .iter()
+                .find(|f| resolve_entry(base, &f.path).is_ok_and(|p| p == song.file()))
+        };
+        if let Some(cue_file) = matching {
+            return sheet.songs_of(cue_file, &song);
+        }
+    }
+    if let Some(sheet) = embedded.and_then(|text| CueSheet::parse(&text).ok()) {
+        // The `FILE` entry of an embedded sheet is the file itself
+        let tracks = sheet.files.iter().flat_map(|f| f.tracks.clone()).collect();
+        let cue_file = CueFile {
+            path: String::new(),
+            tracks,
+        };
+        return sheet.songs_of(&cue_file, &song);
+    }
+    vec![song]
+}
+
+/// Where a track of a CUE sheet sits in its file, `<start>,<duration>` in seconds
+/// Playlists store it next to the file so the track can be found again
+pub(crate) fn format_span(song: &Song) -> Option<String> {
+    let start = song.start()?;
+    Some(format!(
+        "{:.3},{:.3}",
+        start.as_secs_f64(),
+        song.duration().as_secs_f64()
+    ))
+}
+
+/// Parses a span written by `format_span`
+pub(crate) fn parse_span(s: &str) -> Option<(Duration, Duration)> {
+    let (start, duration) = s.split_once(',')?;
+    let secs = |s: &str| Duration::try_from_secs_f64(s.trim().parse().ok()?).ok();
+    Some((secs(start)?, secs(duration)?))
+}
+
+/// Loads the track of `file` starting at `start`, from its CUE sheet if it still has one.
+/// Otherwise the span is played with the tags of the whole file
+pub(crate) fn load_span(
+    file: PathBuf,
+    (start, duration): (Duration, Duration),
+) -> Result<Song, MelodyErrors> {
+    let (song, tagged_file) = Song::load_tagged(file)?;
+    let mut whole = song.clone();
+    let track = split_song(song, embedded_text(&tagged_file))
+        .into_iter()
+        .find(|track| {
+            track
+                .start()
+                .is_some_and(|s| s.abs_diff(start) < Duration::from_millis(1))
+        });
+    Ok(track.unwrap_or_else(|| {
+        whole.start = Some(start);
+        whole.duration = duration;
+        whole.title = None;
+        whole
+    }))
+}
+
+impl Playlist {
+    /// Read a CUE sheet, each track becomes a virtual song spanning part of its file
+    /// Returns the playlist along with the files that could not be loaded
+    pub fn from_cue(path: &Path) -> Result<(Self, Vec<MelodyErrors>), MelodyErrors> {
+        let sheet = CueSheet::open(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let (tracks, errors) = sheet.songs(base);
+        Ok((Self { tracks }, errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixtures;
+
+    const SHEET: &str = r#"REM GENRE "Electronic"
+REM DATE 2002-02-18
+PERFORMER "Boards of Canada"
+TITLE "Geogaddi"
+
+FILE "Geogaddi.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Ready Lets Go"
+    INDEX 01 00:00:00
+  track 02 audio
+    title "Music Is Math"
+    performer "BoC"
+    INDEX 00 00:58:10
+    INDEX 01 01:00:37
+FILE "bonus.wav" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+"#;
+
+    #[test]
+    fn parses_sheets() {
+        let sheet = CueSheet::parse(SHEET).unwrap();
+        assert_eq!(sheet.performer.as_deref(), Some("Boards of Canada"));
+        assert_eq!(sheet.title.as_deref(), Some("Geogaddi"));
+        assert_eq!(sheet.genre.as_deref(), Some("Electronic"));
+        assert_eq!(sheet.year, Some(2002));
+        let paths: Vec<_> = sheet.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["Geogaddi.flac", "bonus.wav"]);
+        assert_eq!(
+            sheet.files[0].tracks[1],
+            CueTrack {
+                number: 2,
+                title: Some("Music Is Math".to_string()),
+                performer: Some("BoC".to_string()),
+                // `INDEX 00` is the pregap, the track starts at `INDEX 01`
+                start: Duration::from_secs(60) + Duration::from_nanos(37 * 1_000_000_000 / 75),
+            }
+        );
+        assert_eq!(sheet.files[1].tracks[0].title, None);
+    }
+
+    #[test]
+    fn rejects_malformed_sheets() {
+        for broken in [
+            "TRACK 01 AUDIO\n  INDEX 01 00:00:00",
+            "FILE \"a.wav\" WAVE\n  INDEX 01 00:00:00",
+            "FILE \"a.wav\" WAVE\n  TRACK one AUDIO",
+            "FILE \"a.wav\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:60:00",
+            "FILE \"a.wav\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:00:75",
+            "FILE \"a.wav\" WAVE",
+            "",
+        ] {
+            assert!(CueSheet::parse(broken).is_err(), "{:?}", broken);
+        }
+    }
+
+    #[test]
+    fn writes_sheets_it_can_read() {
+        let mut sheet = CueSheet::parse(SHEET).unwrap();
+        sheet.files[0].tracks[0].title = Some("Ready \"Lets\" Go".to_string());
+        let written = CueSheet::parse(&sheet.to_string()).unwrap();
+        sheet.files[0].tracks[0].title = Some("Ready 'Lets' Go".to_string());
+        assert_eq!(written, sheet);
+        assert_eq!(fmt_timestamp(Duration::from_millis(999_999)), "16:40:00");
+    }
+
+    /// A 6 second rip with a sidecar sheet splitting it in two
+    fn album(fixtures: &Fixtures) -> Playlist {
+        fixtures.song("album.wav", 6, Some("Boards of Canada"), None);
+        let cue = fixtures.0.join("album.cue");
+        std::fs::write(
+            &cue,
+            "FILE \"album.wav\" WAVE\n\
+             \x20 TRACK 01 AUDIO\n\
+             \x20   TITLE \"Music Is Math\"\n\
+             \x20   INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   TITLE \"Beware the Friendly Stranger\"\n\
+             \x20   INDEX 01 00:04:00\n",
+        )
+        .unwrap();
+        let (playlist, errors) = Playlist::from_cue(&cue).unwrap();
+        assert!(errors.is_empty());
+        playlist
+    }
+
+    fn spans(playlist: &Playlist) -> Vec<(Option<String>, Option<Duration>, Duration)> {
+        playlist
+            .tracks
+            .iter()
+            .map(|s| (s.title().map(String::from), s.start(), s.duration()))
+            .collect()
+    }
+
+    #[test]
+    fn playlists_keep_cue_spans() {
+        let fixtures = Fixtures::new("cue-spans");
+        let playlist = album(&fixtures);
+        assert_eq!(
+            spans(&playlist),
+            [
+                (
+                    Some("Music Is Math".to_string()),
+                    Some(Duration::ZERO),
+                    Duration::from_secs(4)
+                ),
+                (
+                    Some("Beware the Friendly Stranger".to_string()),
+                    Some(Duration::from_secs(4)),
+                    Duration::from_secs(2)
+                ),
+            ]
+        );
+        type Write = fn(&Playlist, &Path) -> Result<(), MelodyErrors>;
+        type Read = fn(&Path) -> Result<(Playlist, Vec<MelodyErrors>), MelodyErrors>;
+        let formats: [(&str, Write, Read); 3] = [
+            ("m3u", Playlist::write_m3u, Playlist::from_m3u),
+            ("pls", Playlist::write_pls, Playlist::from_pls),
+            ("xspf", Playlist::write_xspf, Playlist::from_xspf),
+        ];
+        for (extension, write, read) in formats {
+            let path = fixtures.0.join("album").with_extension(extension);
+            write(&playlist, &path).unwrap();
+            let (read, errors) = read(&path).unwrap();
+            assert!(errors.is_empty(), "{}", extension);
+            assert_eq!(spans(&read), spans(&playlist), "{}", extension);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::fingerprint::{DEFAULT_SIMILARITY, Fingerprint};
+use crate::library::Library;
+use crate::song::{Playlist, Song};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How far apart the durations of two copies of a song can be
+pub const DUPLICATE_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Songs that look like copies of the same recording
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateGroup {
+    /// At least two songs, sorted by path
+    pub songs: Vec<Song>,
+}
+
+impl DuplicateGroup {
+    /// Paths of the copies
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.songs.iter().map(Song::file)
+    }
+}
+
+/// What makes two songs duplicates
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MatchBy {
+    /// The same artist, album and title
+    #[default]
+    Tags,
+    /// Sounding alike, see `Fingerprint`. Finds re-tagged and untagged copies
+    Fingerprint,
+}
+
+/// How `duplicate_groups` matches songs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuplicateOptions {
+    pub by: MatchBy,
+    /// Songs whose durations differ by more than this arent duplicates
+    pub tolerance: Duration,
+    /// How alike fingerprints have to be, from 0 to 1, for `MatchBy::Fingerprint`
+    pub similarity: f32,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        Self {
+            by: MatchBy::Tags,
+            tolerance: DUPLICATE_TOLERANCE,
+            similarity: DEFAULT_SIMILARITY,
+        }
+    }
+}
+
+/// Lowercases a tag and drops punctuation and extra spaces,
+/// so `The Beatles` and `the  beatles!` compare equal
+fn normalize(tag: Option<&str>) -> String {
+    let tag: String = tag
+        .unwrap_or_default()
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    tag.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Normalized artist, album and title, `None` without an artist and title to go on
+fn tag_key(song: &Song) -> Option<(String, String, String)> {
+    let artist = normalize(song.artist());
+    let title = normalize(song.title());
+    if artist.is_empty() || title.is_empty() {
+        return None;
+    }
+    Some((artist, normalize(song.album()), title))
+}
+
+/// Finds the index of the group `i` belongs to, for `fingerprint_groups`
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Clusters songs whose fingerprints are alike, only songs within
+/// `options.tolerance` of each other's duration are compared
+fn fingerprint_groups(
+    mut songs: Vec<(&Song, &Fingerprint)>,
+    options: &DuplicateOptions,
+) -> Vec<DuplicateGroup> {
+    songs.sort_by_key(|(s, _)| (s.duration(), s.file().to_path_buf()));
+    let mut parents: Vec<usize> = (0..songs.len()).collect();
+    for (i, (song, fingerprint)) in songs.iter().enumerate() {
+        for (j, (other, other_fingerprint)) in songs.iter().enumerate().skip(i + 1) {
+            if other.duration() - song.duration() > options.tolerance {
+                break;
+            }
+            if fingerprint.similarity(other_fingerprint) >= options.similarity {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[b] = a;
+            }
+        }
+    }
+    let mut clusters: HashMap<usize, Vec<Song>> = HashMap::new();
+    for (i, (song, _)) in songs.iter().enumerate() {
+        let root = root(&mut parents, i);
+        clusters.entry(root).or_default().push((*song).clone());
+    }
+    let mut groups: Vec<DuplicateGroup> = clusters
+        .into_values()
+        .filter(|songs| songs.len() > 1)
+        .map(|mut songs| {
+            songs.sort_by(|a, b| a.file().cmp(b.file()));
+            DuplicateGroup { songs }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.songs[0].file().cmp(b.songs[0].file()));
+    groups
+}
+
+/// Groups `songs` that are copies of each other
+/// By tags, songs are bucketed by their normalized artist, album and title, then split up
+/// where durations are further apart than `options.tolerance`.
+/// Untagged songs and the tracks of CUE sheets are never duplicates.
+/// By fingerprint, every song is decoded, which is slow, see `Library::duplicates`
+/// for using the fingerprints kept in the index
+/// # Example
+/// ```no_run
+/// let library = melody::Library::open_default()?;
+/// let songs: Vec<_> = library.songs().cloned().collect();
+/// for group in melody::duplicate_groups(&songs, &Default::default()) {
+///     println!("{:?}", group.files().collect::<Vec<_>>());
+/// }
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+pub fn duplicate_groups(songs: &[Song], options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    if options.by == MatchBy::Fingerprint {
+        let fingerprints: Vec<(&Song, Fingerprint)> = songs
+            .iter()
+            .filter(|s| s.start().is_none())
+            .filter_map(|s| match Fingerprint::compute(s.file()) {
+                Ok(fingerprint) => Some((s, fingerprint)),
+                Err(e) => {
+                    log::warn!("Failed to fingerprint {:?}: {}", s.file(), e);
+                    None
+                }
+            })
+            .collect();
+        return fingerprint_groups(fingerprints.iter().map(|(s, f)| (*s, f)).collect(), options);
+    }
+    let mut buckets: HashMap<_, Vec<&Song>> = HashMap::new();
+    for song in songs.iter().filter(|s| s.start().is_none()) {
+        if let Some(key) = tag_key(song) {
+            buckets.entry(key).or_default().push(song);
+        }
+    }
+    let mut groups = Vec::new();
+    for mut bucket in buckets.into_values().filter(|b| b.len() > 1) {
+        bucket.sort_by_key(|s| (s.duration(), s.file().to_path_buf()));
+        bucket.dedup_by(|a, b| a.file() == b.file());
+        // Each group spans at most `tolerance`, starting from its shortest song
+        let mut rest = bucket.as_slice();
+        while let Some(first) = rest.first() {
+            let len = rest
+                .iter()
+                .take_while(|s| s.duration() - first.duration() <= options.tolerance)
+                .count();
+            let (group, next) = rest.split_at(len);
+            if group.len() > 1 {
+                let mut songs: Vec<Song> = group.iter().map(|&s| s.clone()).collect();
+                songs.sort_by(|a, b| a.file().cmp(b.file()));
+                groups.push(DuplicateGroup { songs });
+            }
+            rest = next;
+        }
+    }
+    groups.sort_by(|a, b| a.songs[0].file().cmp(b.songs[0].file()));
+    groups
+}
+
+/// Find  Duplicates
+/// `music_dir` - Music directory to find duplicates
+/// Returns the groups of duplicates by tags, see `duplicate_groups`
+pub fn find_duplicates(music_dir: &Path) -> Vec<DuplicateGroup> {
+    find_duplicates_with(music_dir, &DuplicateOptions::default())
+}
+
+/// Same as `find_duplicates`, matching songs according to `options`
+pub fn find_duplicates_with(music_dir: &Path, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    match Playlist::from_dir(PathBuf::from(music_dir)) {
+        Some(pl) => duplicate_groups(&pl.tracks, options),
+        None => Vec::new(),
+    }
+}
+
+impl Library {
+    /// Groups of duplicate songs in the library, see `duplicate_groups`
+    /// By fingerprint, only the fingerprints already in the index are used,
+    /// see `Library::fingerprint`
+    pub fn duplicates(&self, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+        if options.by == MatchBy::Tags {
+            let songs: Vec<Song> = self.songs().cloned().collect();
+            return duplicate_groups(&songs, options);
+        }
+        let songs = self
+            .songs()
+            .filter(|s| s.start().is_none())
+            .filter_map(|s| Some((s, self.fingerprint_of(s.file())?)))
+            .collect();
+        fingerprint_groups(songs, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixtures;
+
+    fn files(groups: &[DuplicateGroup]) -> Vec<Vec<String>> {
+        groups
+            .iter()
+            .map(|g| {
+                g.files()
+                    .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matching_song_accepts_equal_durations() {
+        let fixtures = Fixtures::new("duplicates-matching");
+        let a = fixtures.song("a.wav", 2, Some("Boards of Canada"), Some("Alpha"));
+        let b = fixtures.song("b.wav", 2, Some("Boards of Canada"), Some("Alpha"));
+        let c = fixtures.song("c.wav", 6, Some("Boards of Canada"), Some("Alpha"));
+        assert!(a.matching_song(&b, true));
+        assert!(!a.matching_song(&c, true));
+    }
+
+    #[test]
+    fn groups_copies_with_normalized_tags() {
+        let fixtures = Fixtures::new("duplicates-normalized");
+        let songs = vec![
+            fixtures.song("a.wav", 2, Some("Boards of Canada"), Some("Music Is Math")),
+            fixtures.song(
+                "b.wav",
+                3,
+                Some("boards  of canada"),
+                Some("Music is Math!"),
+            ),
+            fixtures.song("c.wav", 2, Some("Boards of Canada"), Some("Gyroscope")),
+            fixtures.song("d.wav", 2, Some("Aphex Twin"), Some("Xtal")),
+        ];
+        let groups = duplicate_groups(&songs, &DuplicateOptions::default());
+        assert_eq!(files(&groups), vec![vec!["a.wav", "b.wav"]]);
+    }
+
+    #[test]
+    fn splits_groups_by_duration() {
+        let fixtures = Fixtures::new("duplicates-durations");
+        let songs = vec![
+            fixtures.song("a.wav", 1, Some("Aphex Twin"), Some("Xtal")),
+            fixtures.song("b.wav", 2, Some("Aphex Twin"), Some("Xtal")),
+            fixtures.song("c.wav", 7, Some("Aphex Twin"), Some("Xtal")),
+            fixtures.song("d.wav", 8, Some("Aphex Twin"), Some("Xtal")),
+            fixtures.song("e.wav", 12, Some("Aphex Twin"), Some("Xtal")),
+        ];
+        let groups = duplicate_groups(&songs, &DuplicateOptions::default());
+        assert_eq!(
+            files(&groups),
+            vec![vec!["a.wav", "b.wav"], vec!["c.wav", "d.wav"]]
+        );
+        let loose = DuplicateOptions {
+            tolerance: Duration::from_secs(20),
+            ..Default::default()
+        };
+        assert_eq!(duplicate_groups(&songs, &loose)[0].songs.len(), 5);
+    }
+
+    #[test]
+    fn untagged_songs_are_not_duplicates() {
+        let fixtures = Fixtures::new("duplicates-untagged");
+        let songs = vec![
+            fixtures.song("a.wav", 2, None, None),
+            fixtures.song("b.wav", 2, None, None),
+            fixtures.song("c.wav", 2, Some("Aphex Twin"), None),
+            fixtures.song("d.wav", 2, Some("Aphex Twin"), None),
+        ];
+        assert!(duplicate_groups(&songs, &DuplicateOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn finds_duplicates_in_a_directory() {
+        let fixtures = Fixtures::new("duplicates-directory");
+        fixtures.song("one/a.wav", 2, Some("Aphex Twin"), Some("Xtal"));
+        fixtures.song("two/a.wav", 2, Some("Aphex Twin"), Some("Xtal"));
+        fixtures.song("two/b.wav", 2, Some("Aphex Twin"), Some("Ageispolis"));
+        let groups = find_duplicates(&fixtures.0);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].files().collect::<Vec<_>>(),
+            vec![fixtures.0.join("one/a.wav"), fixtures.0.join("two/a.wav")]
+        );
+    }
+}
@@ -52,6 +52,19 @@ impl MelodyErrors {
     pub fn file(&self) -> Option<PathBuf> {
         self.file.clone()
     }
+    /// Attaches the file the error relates to
+    pub(crate) fn with_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+    /// Wraps an io error that happened while working on `file`
+    pub(crate) fn from_io(err: IoError, file: &Path) -> Self {
+        MelodyErrors::new(
+            MelodyErrorsKind::Io(err.kind()),
+            &format!("{}", err),
+            Some(file),
+        )
+    }
 }
 
 impl From<IoError> for MelodyErrors {
@@ -88,6 +101,21 @@ pub enum MelodyErrorsKind {
     NotPaused,
     AlreadyPlaying,
     MissingDuration,
+    MalformedPlaylist,
+    FailedToGetDataDir,
+    InvalidPlaylistName,
+    PlaylistNotFound,
+    PlaylistExists,
+    InvalidGlob,
+    SymlinkLoop,
+    TooShort,
+    FailedToWatch,
+    InvalidQuery,
+    ReadOnlyPlaylist,
+    InvalidTemplate,
+    InvalidOption,
+    FailedToDecode,
+    FailedToEncode,
     MetaDataError(lofty::error::LoftyError),
 }
 impl ::std::convert::From<lofty::error::LoftyError> for MelodyErrorsKind {
@@ -0,0 +1,351 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::utils::open_decoder;
+use rodio::Source;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Audio is mixed down to mono and resampled to this rate first
+const SAMPLE_RATE: u32 = 11025;
+/// Samples per analysis frame, about 0.37s
+const FRAME: usize = 4096;
+/// Samples between the starts of two frames
+const HOP: usize = 2048;
+/// Only the start of a recording is fingerprinted
+const MAX_SECONDS: u32 = 120;
+/// Frequencies outside of this range are ignored, in Hz
+const MIN_FREQ: f32 = 28.0;
+const MAX_FREQ: f32 = 3520.0;
+/// How many frames two fingerprints can be shifted against each other when compared
+const MAX_SHIFT: isize = 24;
+/// Frames with less energy than this are silent, about 60 dB below a full scale tone
+const SILENCE: f32 = 1.0;
+/// Hash of a silent frame, others never hash to it as that takes all 12 pitch classes
+/// being equal
+const SILENT: u32 = 0;
+
+/// Default for `DuplicateOptions::similarity`, unrelated recordings score 0.5 to 0.6
+pub const DEFAULT_SIMILARITY: f32 = 0.75;
+
+/// Acoustic fingerprint of the first two minutes of a recording, in the spirit of chromaprint
+///
+/// The audio is cut into overlapping frames, each frame's spectrum is folded into the
+/// 12 pitch classes and a 32 bit hash records how they compare to each other and to the
+/// previous frame. Copies of a recording in other formats or bitrates keep most of those
+/// bits, so fingerprints can be compared with `similarity` even if the tags dont match
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Fingerprint {
+    frames: Vec<u32>,
+}
+
+/// In place radix-2 FFT, `re` and `im` have to be a power of two long
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Mixes interleaved samples down to mono at `SAMPLE_RATE`, by averaging or repeating
+fn resample(samples: impl Iterator<Item = i16>, rate: u32, channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    let step = rate.max(1) as f64 / SAMPLE_RATE as f64;
+    let limit = (SAMPLE_RATE * MAX_SECONDS) as usize;
+    let mut out = Vec::with_capacity(limit);
+    let (mut sum, mut count, mut position, mut next) = (0.0, 0usize, 0.0, step);
+    let mut frame = 0.0;
+    for (i, sample) in samples.enumerate() {
+        frame += sample as f32 / i16::MAX as f32;
+        if !(i + 1).is_multiple_of(channels) {
+            continue;
+        }
+        sum += frame / channels as f32;
+        frame = 0.0;
+        count += 1;
+        position += 1.0;
+        if position < next {
+            continue;
+        }
+        // Upsampling repeats a sample
+        while position >= next {
+            out.push(sum / count as f32);
+            next += step;
+        }
+        (sum, count) = (0.0, 0);
+        if out.len() >= limit {
+            out.truncate(limit);
+            break;
+        }
+    }
+    out
+}
+
+/// Energy of the 12 pitch classes in each frame
+fn chroma(samples: &[f32]) -> Vec<[f32; 12]> {
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME as f32).cos())
+        .collect();
+    // Pitch class of every FFT bin in range
+    let bins: Vec<(usize, usize)> = (1..FRAME / 2)
+        .filter_map(|k| {
+            let freq = k as f32 * SAMPLE_RATE as f32 / FRAME as f32;
+            if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+                return None;
+            }
+            let note = 12.0 * (freq / 440.0).log2() + 69.0;
+            Some((k, (note.round() as i32).rem_euclid(12) as usize))
+        })
+        .collect();
+    let (mut re, mut im) = (vec![0.0; FRAME], vec![0.0; FRAME]);
+    samples
+        .windows(FRAME)
+        .step_by(HOP)
+        .map(|frame| {
+            for (i, (&s, &w)) in frame.iter().zip(&window).enumerate() {
+                re[i] = s * w;
+                im[i] = 0.0;
+            }
+            fft(&mut re, &mut im);
+            let mut chroma = [0.0; 12];
+            for &(k, class) in &bins {
+                chroma[class] += re[k] * re[k] + im[k] * im[k];
+            }
+            let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+            if norm > SILENCE {
+                chroma.iter_mut().for_each(|c| *c /= norm);
+            } else {
+                chroma = [0.0; 12];
+            }
+            chroma
+        })
+        .collect()
+}
+
+/// Hashes a frame against the previous one, both smoothed over their neighbours
+fn hash(prev: &[f32; 12], cur: &[f32; 12]) -> u32 {
+    let mut bits = 0u32;
+    for p in 0..12 {
+        bits |= ((cur[p] > cur[(p + 1) % 12]) as u32) << p;
+        bits |= ((cur[p] > prev[p]) as u32) << (p + 12);
+    }
+    for p in 0..8 {
+        bits |= ((cur[p] > cur[(p + 3) % 12]) as u32) << (p + 24);
+    }
+    bits
+}
+
+impl Fingerprint {
+    /// Decodes the file at `path` and fingerprints it
+    pub fn compute(path: &Path) -> Result<Self, MelodyErrors> {
+        let decoder = open_decoder(path)?;
+        let (rate, channels) = (decoder.sample_rate(), decoder.channels());
+        let fingerprint = Self::from_samples(decoder, rate, channels);
+        if fingerprint.frames.is_empty() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::FailedToDecode,
+                "Too short to fingerprint",
+                Some(path),
+            ));
+        }
+        Ok(fingerprint)
+    }
+    /// Fingerprints interleaved samples, playing at `rate` with `channels` channels
+    pub fn from_samples(samples: impl Iterator<Item = i16>, rate: u32, channels: u16) -> Self {
+        let chroma = chroma(&resample(samples, rate, channels));
+        // Averaging over 3 frames evens out small differences between encodings
+        let smooth: Vec<[f32; 12]> = (0..chroma.len())
+            .map(|i| {
+                let around = &chroma[i.saturating_sub(1)..(i + 2).min(chroma.len())];
+                let mut avg = [0.0; 12];
+                for frame in around {
+                    for p in 0..12 {
+                        avg[p] += frame[p] / around.len() as f32;
+                    }
+                }
+                avg
+            })
+            .collect();
+        let frames = smooth
+            .windows(2)
+            .zip(chroma.iter().skip(1))
+            .map(|(w, cur)| {
+                if cur.iter().all(|&c| c == 0.0) {
+                    SILENT
+                } else {
+                    hash(&w[0], &w[1])
+                }
+            })
+            .collect();
+        Self { frames }
+    }
+    /// Number of hashed frames, about 5 per second
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    /// Number of frames that arent silent
+    fn sounding(&self) -> usize {
+        self.frames.iter().filter(|&&f| f != SILENT).count()
+    }
+    /// How alike two fingerprints are, from 0 to 1
+    /// The share of matching bits, at the best alignment within a few seconds.
+    /// Silent frames are left out, or every song with a silent intro would match
+    pub fn similarity(&self, other: &Self) -> f32 {
+        let min_overlap = (self.sounding().min(other.sounding()) / 2).max(1);
+        let mut best = 0.0f32;
+        for shift in -MAX_SHIFT..=MAX_SHIFT {
+            let (a, b) = if shift >= 0 {
+                (
+                    self.frames.get(shift as usize..),
+                    Some(other.frames.as_slice()),
+                )
+            } else {
+                (
+                    Some(self.frames.as_slice()),
+                    other.frames.get((-shift) as usize..),
+                )
+            };
+            let (Some(a), Some(b)) = (a, b) else {
+                continue;
+            };
+            let (overlap, differing) = a
+                .iter()
+                .zip(b)
+                .filter(|&(&x, &y)| x != SILENT && y != SILENT)
+                .fold((0, 0), |(n, d), (x, y)| (n + 1, d + (x ^ y).count_ones()));
+            if overlap < min_overlap {
+                continue;
+            }
+            best = best.max(1.0 - differing as f32 / (overlap * 32) as f32);
+        }
+        best
+    }
+}
+
+/// Writes the frames as hex, 8 digits each
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in &self.frames {
+            write!(f, "{:08x}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            MelodyErrors::new(
+                MelodyErrorsKind::MalformedPlaylist,
+                "Invalid fingerprint",
+                None,
+            )
+        };
+        if !s.is_ascii() || !s.len().is_multiple_of(8) {
+            return Err(invalid());
+        }
+        let frames = (0..s.len())
+            .step_by(8)
+            .map(|i| u32::from_str_radix(&s[i..i + 8], 16).map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Fixtures, tones};
+
+    /// Two melodies, a note every half second
+    const MELODY: [f32; 16] = [
+        440.0, 494.0, 523.0, 587.0, 659.0, 587.0, 523.0, 494.0, 440.0, 392.0, 349.0, 392.0, 440.0,
+        523.0, 659.0, 880.0,
+    ];
+    const OTHER_MELODY: [f32; 16] = [
+        311.0, 370.0, 415.0, 466.0, 554.0, 622.0, 740.0, 831.0, 740.0, 622.0, 554.0, 466.0, 415.0,
+        370.0, 311.0, 277.0,
+    ];
+
+    fn fingerprint(fixtures: &Fixtures, name: &str, samples: &[i16]) -> Fingerprint {
+        let song = fixtures.wav(name, samples, Some("Boards of Canada"), Some("Alpha"));
+        Fingerprint::compute(song.file()).unwrap()
+    }
+
+    fn with_intro(secs: f32, samples: Vec<i16>) -> Vec<i16> {
+        let mut intro = tones(&[0.0], secs, 0.0);
+        intro.extend(samples);
+        intro
+    }
+
+    #[test]
+    fn same_recording_matches() {
+        let fixtures = Fixtures::new("fingerprint-same");
+        let loud = fingerprint(&fixtures, "a.wav", &tones(&MELODY, 0.5, 0.8));
+        let quiet = fingerprint(&fixtures, "b.wav", &tones(&MELODY, 0.5, 0.3));
+        let late = fingerprint(
+            &fixtures,
+            "c.wav",
+            &with_intro(1.0, tones(&MELODY, 0.5, 0.8)),
+        );
+        assert!(loud.similarity(&quiet) > DEFAULT_SIMILARITY);
+        assert!(loud.similarity(&late) > DEFAULT_SIMILARITY);
+    }
+
+    #[test]
+    fn different_recordings_dont_match() {
+        let fixtures = Fixtures::new("fingerprint-different");
+        let a = fingerprint(&fixtures, "a.wav", &tones(&MELODY, 0.5, 0.8));
+        let b = fingerprint(&fixtures, "b.wav", &tones(&OTHER_MELODY, 0.5, 0.8));
+        assert!(a.similarity(&b) < DEFAULT_SIMILARITY);
+    }
+
+    #[test]
+    fn silent_intros_dont_match() {
+        let fixtures = Fixtures::new("fingerprint-silence");
+        let a = fingerprint(
+            &fixtures,
+            "a.wav",
+            &with_intro(30.0, tones(&MELODY, 0.5, 0.8)),
+        );
+        let b = fingerprint(
+            &fixtures,
+            "b.wav",
+            &with_intro(30.0, tones(&OTHER_MELODY, 0.5, 0.8)),
+        );
+        assert!(a.similarity(&b) < DEFAULT_SIMILARITY);
+        let silence = fingerprint(&fixtures, "c.wav", &tones(&[0.0], 10.0, 0.0));
+        assert_eq!(silence.similarity(&silence), 0.0);
+    }
+}
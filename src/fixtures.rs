@@ -0,0 +1,92 @@
+use crate::song::Song;
+use lofty::config::WriteOptions;
+use lofty::prelude::TagExt;
+use lofty::tag::{Accessor, Tag, TagType};
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+
+/// Sample rate of the fixture WAV files
+const RATE: u32 = 8000;
+
+/// A directory of fixtures, removed when dropped
+pub(crate) struct Fixtures(pub(crate) PathBuf);
+
+impl Fixtures {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("melody-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Fixtures(dir)
+    }
+    /// Writes a silent 8kHz mono WAV file tagged with `artist` and `title`
+    pub(crate) fn song(
+        &self,
+        name: &str,
+        secs: u32,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Song {
+        self.wav(name, &vec![0; (secs * RATE) as usize], artist, title)
+    }
+    /// Writes an 8kHz mono WAV file of `samples` tagged with `artist` and `title`
+    pub(crate) fn wav(
+        &self,
+        name: &str,
+        samples: &[i16],
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Song {
+        let path = self.0.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        fs::write(&path, wav).unwrap();
+        let mut tag = Tag::new(TagType::Id3v2);
+        if let Some(artist) = artist {
+            tag.set_artist(artist.to_string());
+        }
+        if let Some(title) = title {
+            tag.set_title(title.to_string());
+        }
+        tag.set_album("Geogaddi".to_string());
+        tag.save_to_path(&path, WriteOptions::default()).unwrap();
+        Song::load(path).unwrap()
+    }
+}
+
+impl Drop for Fixtures {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Samples for `wav`, playing each of `notes` for `secs` seconds at `volume` (0 to 1),
+/// a note of 0 Hz is silence
+pub(crate) fn tones(notes: &[f32], secs: f32, volume: f32) -> Vec<i16> {
+    let len = (secs * RATE as f32) as usize;
+    notes
+        .iter()
+        .flat_map(|&freq| {
+            (0..len).map(move |i| {
+                let t = i as f32 / RATE as f32;
+                ((2.0 * PI * freq * t).sin() * volume * i16::MAX as f32) as i16
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,91 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::Playlist;
+use crate::utils::{get_filetype, read_text};
+use std::path::Path;
+
+/// Playlist file formats Melody can read and write
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlaylistFormat {
+    /// M3U / extended M3U, `.m3u8` is the UTF-8 variant
+    M3u,
+    /// INI style PLS playlists
+    Pls,
+    /// XML Shareable Playlist Format
+    Xspf,
+    /// CUE sheets, can only be read
+    Cue,
+}
+
+impl PlaylistFormat {
+    /// Guess the format from the file extension
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match get_filetype(path)?.as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+            "pls" => Some(PlaylistFormat::Pls),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            "cue" => Some(PlaylistFormat::Cue),
+            _ => None,
+        }
+    }
+    /// Guess the format from the contents of a playlist
+    pub fn from_contents(text: &str) -> Option<Self> {
+        let head = text.trim_start();
+        if head.starts_with("#EXTM3U") {
+            Some(PlaylistFormat::M3u)
+        } else if head.to_lowercase().starts_with("[playlist]") {
+            Some(PlaylistFormat::Pls)
+        } else if (head.starts_with("<?xml") || head.starts_with("<playlist"))
+            && head.contains("xspf.org")
+        {
+            Some(PlaylistFormat::Xspf)
+        } else {
+            None
+        }
+    }
+    /// Detect the format of the playlist at `path`
+    /// The contents take priority over the extension, plain path lists are treated as M3U
+    pub fn detect(path: &Path) -> Result<Self, MelodyErrors> {
+        let text = read_text(path)?;
+        if let Some(format) = Self::from_contents(&text) {
+            return Ok(format);
+        }
+        if let Some(format) = Self::from_extension(path) {
+            return Ok(format);
+        }
+        if text.trim_start().starts_with('<') {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::UnkownFileType,
+                "Unrecognized playlist format",
+                Some(path),
+            ));
+        }
+        Ok(PlaylistFormat::M3u)
+    }
+}
+
+impl Playlist {
+    /// Open a playlist of any supported format, detecting the format automatically
+    /// Returns the playlist along with the entries that could not be resolved or loaded
+    pub fn open(path: &Path) -> Result<(Self, Vec<MelodyErrors>), MelodyErrors> {
+        match PlaylistFormat::detect(path)? {
+            PlaylistFormat::M3u => Self::from_m3u(path),
+            PlaylistFormat::Pls => Self::from_pls(path),
+            PlaylistFormat::Xspf => Self::from_xspf(path),
+            PlaylistFormat::Cue => Self::from_cue(path),
+        }
+    }
+    /// Save the playlist, choosing the format from the extension of `path`
+    /// Unknown extensions are written as M3U
+    pub fn save(&self, path: &Path) -> Result<(), MelodyErrors> {
+        match PlaylistFormat::from_extension(path).unwrap_or(PlaylistFormat::M3u) {
+            PlaylistFormat::M3u => self.write_m3u(path),
+            PlaylistFormat::Pls => self.write_pls(path),
+            PlaylistFormat::Xspf => self.write_xspf(path),
+            PlaylistFormat::Cue => Err(MelodyErrors::new(
+                MelodyErrorsKind::UnsupportedFileType,
+                "Writing CUE sheets is not supported",
+                Some(path),
+            )),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::errors::MelodyErrors;
+use crate::library::{escape_path, unescape_path};
+use crate::song::Song;
+use crate::store::data_dir;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HISTORY_VERSION: u32 = 1;
+const HISTORY_HEADER: &str = "melody-history";
+
+/// How often and when a song was played
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Plays {
+    pub count: u32,
+    /// When it was last played, `None` if never
+    pub last: Option<SystemTime>,
+}
+
+/// Songs are told apart by file and, for virtual songs, their start
+type SongKey = (PathBuf, Option<u128>);
+
+fn key(song: &Song) -> SongKey {
+    (
+        song.file().to_path_buf(),
+        song.start().map(|s| s.as_millis()),
+    )
+}
+
+/// Play counts and last played times, saved in Melody's data directory
+/// # Example
+/// ```no_run
+/// let history = melody::PlayHistory::open_default()?;
+/// let library = melody::Library::open_default()?;
+/// for song in library.songs() {
+///     println!("{} played {} times", song, history.plays(song).count);
+/// }
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct PlayHistory {
+    path: PathBuf,
+    plays: BTreeMap<SongKey, Plays>,
+}
+
+impl PlayHistory {
+    /// Open the history stored at `path`, a missing file gives an empty history
+    pub fn open(path: PathBuf) -> Result<Self, MelodyErrors> {
+        let mut history = Self {
+            path,
+            plays: BTreeMap::new(),
+        };
+        if !history.path.exists() {
+            return Ok(history);
+        }
+        let text = fs::read_to_string(&history.path)
+            .map_err(|e| MelodyErrors::from_io(e, &history.path))?;
+        let mut lines = text.lines();
+        let header = format!("{}\t{}", HISTORY_HEADER, HISTORY_VERSION);
+        if lines.next() != Some(header.as_str()) {
+            log::warn!("Discarding unknown play history {:?}", history.path);
+            return Ok(history);
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [path, start, count, last] = fields.as_slice() else {
+                log::warn!("Skipping malformed play history line: {:?}", line);
+                continue;
+            };
+            let plays = Plays {
+                count: count.parse().unwrap_or(0),
+                last: last
+                    .parse()
+                    .ok()
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            };
+            let key = (unescape_path(path), start.parse().ok());
+            history.plays.insert(key, plays);
+        }
+        Ok(history)
+    }
+    /// Open the history stored in Melody's data directory
+    pub fn open_default() -> Result<Self, MelodyErrors> {
+        Self::open(data_dir()?.join("history"))
+    }
+    /// Where the history is stored
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Write the history to disk
+    pub fn save(&self) -> Result<(), MelodyErrors> {
+        let io_err = |e| MelodyErrors::from_io(e, &self.path);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut out = BufWriter::new(File::create(&tmp).map_err(io_err)?);
+        writeln!(out, "{}\t{}", HISTORY_HEADER, HISTORY_VERSION).map_err(io_err)?;
+        for ((path, start), plays) in &self.plays {
+            let last = plays
+                .last
+                .and_then(|l| l.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs().to_string())
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                escape_path(path),
+                start.map(|s| s.to_string()).unwrap_or_default(),
+                plays.count,
+                last
+            )
+            .map_err(io_err)?;
+        }
+        out.flush().map_err(io_err)?;
+        drop(out);
+        fs::rename(&tmp, &self.path).map_err(io_err)
+    }
+    /// Record that `song` is being played now
+    pub fn record(&mut self, song: &Song) {
+        self.record_at(song, SystemTime::now())
+    }
+    /// Record that `song` was played at `time`
+    pub fn record_at(&mut self, song: &Song, time: SystemTime) {
+        let plays = self.plays.entry(key(song)).or_default();
+        plays.count += 1;
+        plays.last = Some(plays.last.map_or(time, |last| last.max(time)));
+    }
+    /// How often and when `song` was played
+    pub fn plays(&self, song: &Song) -> Plays {
+        self.plays.get(&key(song)).copied().unwrap_or_default()
+    }
+    /// Moves the plays of the file at `from` to `to`, after it was renamed
+    /// Plays already recorded for `to` are added up
+    pub fn move_file(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<SongKey> = self
+            .plays
+            .keys()
+            .filter(|(path, _)| path == from)
+            .cloned()
+            .collect();
+        for (path, start) in moved {
+            if let Some(plays) = self.plays.remove(&(path, start)) {
+                let entry = self.plays.entry((to.to_path_buf(), start)).or_default();
+                entry.count += plays.count;
+                entry.last = entry.last.max(plays.last);
+            }
+        }
+    }
+    /// When `song` was last played, `None` if never
+    pub fn last_played(&self, song: &Song) -> Option<SystemTime> {
+        self.plays(song).last
+    }
+}
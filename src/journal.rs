@@ -0,0 +1,239 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::library::{escape_path, unescape_path};
+use crate::organize::{ImportMode, transfer};
+use crate::store::data_dir;
+use crate::utils::read_text;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_VERSION: u32 = 1;
+const JOURNAL_HEADER: &str = "melody-organize";
+const BACKUP_SUFFIX: &str = ".melody-backup";
+
+/// A file put into the library by `add_to_library`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JournalEntry {
+    pub mode: ImportMode,
+    /// Where the file came from
+    pub from: PathBuf,
+    /// Where it went in the library
+    pub to: PathBuf,
+    /// A worse file was at `to`, it is kept next to it until the next import
+    pub replaced: bool,
+}
+
+/// Where the file replaced at `to` is kept, a hidden file in the same directory
+pub(crate) fn backup_path(to: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(to.file_name().unwrap_or_default());
+    name.push(BACKUP_SUFFIX);
+    to.with_file_name(name)
+}
+
+/// Checks if `path` is a backup made by `backup_path`, scans leave them out
+pub(crate) fn is_backup(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().ends_with(BACKUP_SUFFIX.as_bytes()))
+}
+
+/// Writes a journal while organizing, every entry is flushed right away
+/// so even an interrupted run can be undone
+pub(crate) struct JournalWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl JournalWriter {
+    /// Starts a new journal at `path` for an import into `root`
+    pub(crate) fn create(path: &Path, root: &Path) -> Result<Self, MelodyErrors> {
+        let io_err = |e| MelodyErrors::from_io(e, path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            out: BufWriter::new(File::create(path).map_err(io_err)?),
+        };
+        writer.line(&format!("{}\t{}", JOURNAL_HEADER, JOURNAL_VERSION))?;
+        writer.line(&format!("root\t{}", escape_path(root)))?;
+        Ok(writer)
+    }
+    fn line(&mut self, line: &str) -> Result<(), MelodyErrors> {
+        writeln!(self.out, "{}", line)
+            .and_then(|_| self.out.flush())
+            .map_err(|e| MelodyErrors::from_io(e, &self.path))
+    }
+    pub(crate) fn record(&mut self, entry: &JournalEntry) -> Result<(), MelodyErrors> {
+        self.line(&format!(
+            "{}\t{}\t{}\t{}",
+            entry.mode.name(),
+            escape_path(&entry.from),
+            escape_path(&entry.to),
+            if entry.replaced { "1" } else { "" }
+        ))
+    }
+}
+
+/// Outcome of `OrganizeJournal::undo`
+#[derive(Debug, Default)]
+pub struct UndoReport {
+    /// Entries that were reversed
+    pub undone: Vec<JournalEntry>,
+    /// Entries that couldnt be reversed, they stay in the journal
+    pub failed: Vec<MelodyErrors>,
+}
+
+/// Record of the last `add_to_library` run, used to reverse it
+/// # Example
+/// ```no_run
+/// let report = melody::OrganizeJournal::open_default()?.undo()?;
+/// println!("{} files put back", report.undone.len());
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct OrganizeJournal {
+    path: PathBuf,
+    root: PathBuf,
+    entries: Vec<JournalEntry>,
+}
+
+fn malformed(path: &Path, msg: &str) -> MelodyErrors {
+    MelodyErrors::new(MelodyErrorsKind::MalformedPlaylist, msg, Some(path))
+}
+
+impl OrganizeJournal {
+    /// Where the journal is kept in Melody's data directory
+    pub fn default_path() -> Result<PathBuf, MelodyErrors> {
+        Ok(data_dir()?.join("organize-journal"))
+    }
+    /// Read the journal at `path`
+    pub fn open(path: &Path) -> Result<Self, MelodyErrors> {
+        if !path.exists() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::PathDoesNotExist,
+                "Nothing to undo, there is no organize journal",
+                Some(path),
+            ));
+        }
+        let text = read_text(path)?;
+        let mut lines = text.lines();
+        let header = format!("{}\t{}", JOURNAL_HEADER, JOURNAL_VERSION);
+        if lines.next() != Some(header.as_str()) {
+            return Err(malformed(path, "Unknown organize journal version"));
+        }
+        let root = lines
+            .next()
+            .and_then(|l| l.strip_prefix("root\t"))
+            .map(unescape_path)
+            .ok_or_else(|| malformed(path, "Organize journal has no library root"))?;
+        let mut entries = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [mode, from, to, replaced] = fields.as_slice() else {
+                // Only the last line can be cut off by an interrupted run
+                log::warn!("Skipping malformed organize journal line: {:?}", line);
+                continue;
+            };
+            entries.push(JournalEntry {
+                mode: mode.parse().map_err(|e: MelodyErrors| e.with_file(path))?,
+                from: unescape_path(from),
+                to: unescape_path(to),
+                replaced: *replaced == "1",
+            });
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            root,
+            entries,
+        })
+    }
+    /// Read the journal kept in Melody's data directory
+    pub fn open_default() -> Result<Self, MelodyErrors> {
+        Self::open(&Self::default_path()?)
+    }
+    /// The library the files were organized into
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Files put into the library, in the order it happened
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+    /// Removes the backups of replaced files, once they cant be restored anymore
+    pub(crate) fn discard_backups(&self) {
+        for entry in self.entries.iter().filter(|e| e.replaced) {
+            let backup = backup_path(&entry.to);
+            if let Err(e) = fs::remove_file(&backup) {
+                log::warn!("Failed to remove backup {:?}: {}", backup, e);
+            }
+        }
+    }
+    /// Removes the directories left empty between `dir` and the library root
+    fn prune(&self, mut dir: &Path) {
+        while dir.starts_with(&self.root) && dir != self.root && fs::remove_dir(dir).is_ok() {
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => return,
+            }
+        }
+    }
+    fn undo_entry(&self, entry: &JournalEntry) -> Result<(), MelodyErrors> {
+        if entry.to.symlink_metadata().is_err() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::PathDoesNotExist,
+                "Organized file is gone",
+                Some(&entry.to),
+            ));
+        }
+        match entry.mode {
+            ImportMode::Move => {
+                if entry.from.exists() {
+                    return Err(MelodyErrors::new(
+                        MelodyErrorsKind::Io(std::io::ErrorKind::AlreadyExists),
+                        "Can not move the file back, its old path is taken",
+                        Some(&entry.from),
+                    ));
+                }
+                if let Some(parent) = entry.from.parent() {
+                    fs::create_dir_all(parent).map_err(|e| MelodyErrors::from_io(e, parent))?;
+                }
+                transfer(&entry.to, &entry.from, ImportMode::Move)?;
+            }
+            _ => fs::remove_file(&entry.to).map_err(|e| MelodyErrors::from_io(e, &entry.to))?,
+        }
+        if entry.replaced {
+            let backup = backup_path(&entry.to);
+            fs::rename(&backup, &entry.to).map_err(|e| MelodyErrors::from_io(e, &backup))?;
+        }
+        if let Some(parent) = entry.to.parent() {
+            self.prune(parent);
+        }
+        Ok(())
+    }
+    /// Reverse the run: moved files go back, copies and links are removed
+    /// and replaced files are restored. Afterwards the journal only keeps
+    /// the entries that failed, or is removed if there are none
+    pub fn undo(self) -> Result<UndoReport, MelodyErrors> {
+        let mut report = UndoReport::default();
+        let mut kept = Vec::new();
+        for entry in self.entries.iter().rev() {
+            match self.undo_entry(entry) {
+                Ok(()) => report.undone.push(entry.clone()),
+                Err(e) => {
+                    report.failed.push(e);
+                    kept.push(entry);
+                }
+            }
+        }
+        if kept.is_empty() {
+            fs::remove_file(&self.path).map_err(|e| MelodyErrors::from_io(e, &self.path))?;
+        } else {
+            let mut writer = JournalWriter::create(&self.path, &self.root)?;
+            for entry in kept.into_iter().rev() {
+                writer.record(entry)?;
+            }
+        }
+        Ok(report)
+    }
+}
@@ -1,3 +1,11 @@
+//! Music library and playback for the terminal: scanning and indexing a music directory,
+//! playlists in M3U, PLS, XSPF and CUE, queries, duplicates, organizing and transcoding.
+//!
+//! Transcoding is the only part that needs other programs, no encoder library is linked.
+//! The audio is encoded by `opusenc` or `lame`, or `ffmpeg` if those arent installed.
+//! They have to be in `PATH` and the `external-encoders` feature enabled,
+//! see `Playlist::transcode`.
+
 // TODO: Implement tag edit feature
 // TODO: Stable Tag Detection (duration etc) currently iffy
 // TODO: Write tests
@@ -10,13 +18,77 @@
 // extern crate tabwriter;
 // extern crate walkdir;
 
+mod audit;
+mod cue;
+mod duplicates;
 mod errors;
+mod fingerprint;
+#[cfg(test)]
+mod fixtures;
+mod formats;
+mod history;
+mod journal;
+mod library;
+mod m3u;
+mod pls;
+mod query;
+mod render;
+mod scan;
+#[cfg(feature = "serde")]
+mod schema;
+mod smart;
 mod song;
+mod stats;
+mod store;
+mod transcode;
 mod utils;
+mod verify;
+mod walk;
+#[cfg(feature = "watch")]
+mod watch;
+mod xspf;
 
 mod musicplayer;
+mod organize;
+mod pathtemplate;
+mod rename;
+mod resolve;
 
+pub use audit::{AUDIT_VERSION, AlbumRef, AuditReport, Issue, TagValue};
+pub use cue::{CueFile, CueSheet, CueTrack};
+pub use duplicates::{
+    DUPLICATE_TOLERANCE, DuplicateGroup, DuplicateOptions, MatchBy, duplicate_groups,
+    find_duplicates, find_duplicates_with,
+};
 pub use errors::{MelodyErrors, MelodyErrorsKind};
+pub use fingerprint::{DEFAULT_SIMILARITY, Fingerprint};
+pub use formats::PlaylistFormat;
+pub use history::{PlayHistory, Plays};
+pub use journal::{JournalEntry, OrganizeJournal, UndoReport};
+pub use library::{Library, LibraryScan};
 pub use musicplayer::{MusicPlayer, MusicPlayerStatus};
+pub use organize::{
+    ConflictPolicy, ImportMode, ImportOptions, ImportOutcome, ImportReport, add_to_library,
+    add_to_library_with, organize_song,
+};
+pub use pathtemplate::{DEFAULT_PATH_TEMPLATE, PathTemplate};
+pub use query::Query;
+pub use rename::{DEFAULT_RENAME_TEMPLATE, RenameOptions, RenameOutcome, RenameReport};
+pub use render::{Column, OutputFormat, Renderer, Template};
+pub use resolve::{
+    KeepPolicy, Resolution, ResolveAction, ResolveOptions, ResolveOutcome, ResolveReport,
+    pick_keeper, resolve_duplicates,
+};
+pub use scan::{ScanProgress, ScanReport, SkipReason, Skipped};
+#[cfg(feature = "serde")]
+pub use schema::{ErrorRecord, SCHEMA_VERSION};
+pub use smart::{Limit, SmartPlaylist};
 pub use song::{Playlist, Song};
-pub use utils::{add_to_library, find_duplicates, fmt_duration, organize_song};
+pub use stats::{BITRATE_RANGES, LibraryStats};
+pub use store::PlaylistStore;
+pub use transcode::{TranscodeFormat, TranscodeOptions, TranscodeOutcome, TranscodeReport};
+pub use utils::{detect_format, fmt_duration, supported_formats};
+pub use verify::{DURATION_TOLERANCE, Problem, VerifyReport, verify_file};
+pub use walk::{IGNORE_FILE, ScanOptions};
+#[cfg(feature = "watch")]
+pub use watch::{DEFAULT_DEBOUNCE, LibraryEvent, LibraryWatcher};
@@ -0,0 +1,516 @@
+use crate::cue::load_tracks;
+use crate::errors::MelodyErrors;
+use crate::fingerprint::Fingerprint;
+use crate::scan::{ScanProgress, load_files, parallel_map};
+use crate::song::{Playlist, Song};
+use crate::store::data_dir;
+use crate::utils::supported_song;
+use crate::walk::{ScanOptions, walk_files};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Version of the index format, bumped whenever the stored `Song` fields change.
+/// Indexes of another version are discarded and rebuilt on the next scan
+const INDEX_VERSION: u32 = 4;
+const INDEX_HEADER: &str = "melody-library";
+
+/// Cached songs of a single file
+#[derive(Clone, Debug)]
+struct IndexedFile {
+    /// Modification time in nanoseconds since the unix epoch
+    modified: u128,
+    size: u64,
+    /// Empty if the file failed to load, so it isnt probed again until it changes
+    songs: Vec<Song>,
+    /// Acoustic fingerprint, only computed on request by `Library::fingerprint`
+    fingerprint: Option<Fingerprint>,
+}
+
+/// Modification time and size of a file, used to detect changes
+fn file_stamp(path: &Path) -> Result<(u128, u64), MelodyErrors> {
+    let metadata = fs::metadata(path).map_err(|e| MelodyErrors::from_io(e, path))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    Ok((modified, metadata.len()))
+}
+
+/// Escapes tabs, newlines and backslashes so a value fits in one field
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub(crate) fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Escapes a path like `escape`, bytes that arent UTF-8 are written as `\xHH`
+/// so the path reads back the same
+pub(crate) fn escape_path(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let mut out = String::new();
+        for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+            out.push_str(&escape(chunk.valid()));
+            for byte in chunk.invalid() {
+                out.push_str(&format!("\\x{:02x}", byte));
+            }
+        }
+        out
+    }
+    #[cfg(not(unix))]
+    escape(&path.to_string_lossy())
+}
+
+/// Reverses `escape_path`
+pub(crate) fn unescape_path(s: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("\\x") {
+        // An escaped backslash followed by `x` isnt a byte
+        let backslashes = rest[..i].chars().rev().take_while(|&c| c == '\\').count();
+        match rest
+            .get(i + 2..i + 4)
+            .map(|hex| u8::from_str_radix(hex, 16))
+        {
+            Some(Ok(byte)) if backslashes % 2 == 0 => {
+                bytes.extend_from_slice(unescape(&rest[..i]).as_bytes());
+                bytes.push(byte);
+                rest = &rest[i + 4..];
+            }
+            _ => {
+                bytes.extend_from_slice(unescape(&rest[..i + 2]).as_bytes());
+                rest = &rest[i + 2..];
+            }
+        }
+    }
+    bytes.extend_from_slice(unescape(rest).as_bytes());
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn optional(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(unescape(s))
+    }
+}
+
+/// Serializes the fields of a song that arent already stored with its file
+fn write_song(song: &Song) -> String {
+    let opt = |s: Option<&str>| s.map(escape).unwrap_or_default();
+    [
+        song.start()
+            .map(|s| s.as_millis().to_string())
+            .unwrap_or_default(),
+        song.duration().as_millis().to_string(),
+        opt(song.artist()),
+        opt(song.album()),
+        opt(song.title()),
+        song.track().map(|t| t.to_string()).unwrap_or_default(),
+        opt(song.genre()),
+        song.year().map(|y| y.to_string()).unwrap_or_default(),
+        song.bitrate().map(|b| b.to_string()).unwrap_or_default(),
+        opt(song.album_artist()),
+        song.disc().map(|d| d.to_string()).unwrap_or_default(),
+        if song.compilation { "1" } else { "" }.to_string(),
+    ]
+    .join("\t")
+}
+
+fn read_song(file: &Path, fields: &[&str]) -> Option<Song> {
+    let [
+        start,
+        duration,
+        artist,
+        album,
+        title,
+        track,
+        genre,
+        year,
+        bitrate,
+        album_artist,
+        disc,
+        compilation,
+    ] = fields
+    else {
+        return None;
+    };
+    Some(Song {
+        artist: optional(artist),
+        album: optional(album),
+        album_artist: optional(album_artist),
+        title: optional(title),
+        track: track.parse().ok(),
+        disc: disc.parse().ok(),
+        genre: optional(genre),
+        year: year.parse().ok(),
+        compilation: *compilation == "1",
+        duration: Duration::from_millis(duration.parse().ok()?),
+        bitrate: bitrate.parse().ok(),
+        file: file.to_path_buf(),
+        elapsed: Duration::from_millis(0),
+        start: start.parse().ok().map(Duration::from_millis),
+    })
+}
+
+/// Outcome of `Library::scan`
+#[derive(Debug, Default)]
+pub struct LibraryScan {
+    /// Files that werent in the index
+    pub added: usize,
+    /// Files whose modification time or size changed
+    pub updated: usize,
+    /// Files that are gone
+    pub removed: usize,
+    /// Files reused from the index
+    pub unchanged: usize,
+    /// Files that were (re)loaded and failed
+    pub failed: Vec<MelodyErrors>,
+}
+
+/// On-disk index of a music library
+/// Songs are cached per file, keyed by path, modification time and size,
+/// so rescans only have to read the tags of files that changed
+/// # Example
+/// ```no_run
+/// let mut library = melody::Library::open_default()?;
+/// library.scan(std::path::Path::new("/home/melody/Music"));
+/// library.save()?;
+/// let playlist = melody::Playlist::from_library(&library);
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Library {
+    /// Where the index is stored
+    path: PathBuf,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+impl Library {
+    /// Open the index stored at `path`
+    /// A missing index, or one written by an incompatible version, gives an empty library
+    pub fn open(path: PathBuf) -> Result<Self, MelodyErrors> {
+        let mut library = Self {
+            path,
+            files: BTreeMap::new(),
+        };
+        if !library.path.exists() {
+            return Ok(library);
+        }
+        let text = fs::read_to_string(&library.path)
+            .map_err(|e| MelodyErrors::from_io(e, &library.path))?;
+        let mut lines = text.lines();
+        let header = format!("{}\t{}", INDEX_HEADER, INDEX_VERSION);
+        if lines.next() != Some(header.as_str()) {
+            log::info!("Discarding outdated library index {:?}", library.path);
+            return Ok(library);
+        }
+        let mut current: Option<(PathBuf, IndexedFile)> = None;
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.split_first() {
+                Some((&"F", [path, modified, size])) => {
+                    if let Some((path, file)) = current.take() {
+                        library.files.insert(path, file);
+                    }
+                    let (Ok(modified), Ok(size)) = (modified.parse(), size.parse()) else {
+                        continue;
+                    };
+                    current = Some((
+                        unescape_path(path),
+                        IndexedFile {
+                            modified,
+                            size,
+                            songs: Vec::new(),
+                            fingerprint: None,
+                        },
+                    ));
+                }
+                Some((&"P", [fingerprint])) => {
+                    if let Some((_, file)) = current.as_mut() {
+                        file.fingerprint = fingerprint.parse().ok();
+                    }
+                }
+                Some((&"S", song)) => {
+                    if let Some((path, file)) = current.as_mut()
+                        && let Some(song) = read_song(path, song)
+                    {
+                        file.songs.push(song);
+                    }
+                }
+                _ => log::warn!("Skipping malformed library index line: {:?}", line),
+            }
+        }
+        if let Some((path, file)) = current.take() {
+            library.files.insert(path, file);
+        }
+        Ok(library)
+    }
+    /// Open the index stored in Melody's data directory
+    pub fn open_default() -> Result<Self, MelodyErrors> {
+        Self::open(data_dir()?.join("library.index"))
+    }
+    /// Where the index is stored
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Write the index to disk
+    pub fn save(&self) -> Result<(), MelodyErrors> {
+        let io_err = |e| MelodyErrors::from_io(e, &self.path);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+        // Write to a temporary file first so a crash cant leave a truncated index
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut out = BufWriter::new(File::create(&tmp).map_err(io_err)?);
+        writeln!(out, "{}\t{}", INDEX_HEADER, INDEX_VERSION).map_err(io_err)?;
+        for (path, file) in &self.files {
+            writeln!(
+                out,
+                "F\t{}\t{}\t{}",
+                escape_path(path),
+                file.modified,
+                file.size
+            )
+            .map_err(io_err)?;
+            if let Some(fingerprint) = &file.fingerprint {
+                writeln!(out, "P\t{}", fingerprint).map_err(io_err)?;
+            }
+            for song in &file.songs {
+                writeln!(out, "S\t{}", write_song(song)).map_err(io_err)?;
+            }
+        }
+        out.flush().map_err(io_err)?;
+        drop(out);
+        fs::rename(&tmp, &self.path).map_err(io_err)
+    }
+    /// Scan `root`, making the index mirror it
+    /// New and changed files are loaded, unchanged files keep their cached songs
+    /// and files that are gone (or outside of `root`) are dropped
+    pub fn scan(&mut self, root: &Path) -> LibraryScan {
+        self.scan_with_progress(root, |_| ())
+    }
+    /// Same as `scan`, `progress` is called as files that need
+    /// to be read are found and loaded
+    pub fn scan_with_progress(
+        &mut self,
+        root: &Path,
+        progress: impl FnMut(&ScanProgress),
+    ) -> LibraryScan {
+        // The default options have no globs, so they cant fail
+        self.scan_with(root, &ScanOptions::default(), progress)
+            .unwrap_or_default()
+    }
+    /// Same as `scan_with_progress`, walking `root` according to `options`.
+    /// `min_duration` isnt applied, the index keeps every song (see `ScanOptions::keeps`).
+    /// Fails if the include or exclude globs are invalid
+    pub fn scan_with(
+        &mut self,
+        root: &Path,
+        options: &ScanOptions,
+        mut progress: impl FnMut(&ScanProgress),
+    ) -> Result<LibraryScan, MelodyErrors> {
+        let mut report = LibraryScan::default();
+        let mut files = BTreeMap::new();
+        let mut stamps = BTreeMap::new();
+        let stale = walk_files(root, options)?
+            .filter_map(|f| f.map_err(|e| log::warn!("{}", e)).ok())
+            .filter(|path| {
+                let Ok((modified, size)) = file_stamp(path) else {
+                    return false;
+                };
+                // Only files that arent cached need their format sniffed
+                let cached = self.files.remove(path);
+                match cached {
+                    Some(file) if file.modified == modified && file.size == size => {
+                        report.unchanged += 1;
+                        files.insert(path.clone(), file);
+                        return false;
+                    }
+                    _ if !supported_song(path) => return false,
+                    Some(_) => report.updated += 1,
+                    None => report.added += 1,
+                }
+                stamps.insert(path.clone(), (modified, size));
+                true
+            });
+        let loaded = load_files(stale, &mut progress);
+        for (path, songs) in loaded {
+            let songs = songs.unwrap_or_else(|e| {
+                report.failed.push(e);
+                Vec::new()
+            });
+            let (modified, size) = stamps.get(&path).copied().unwrap_or_default();
+            files.insert(
+                path,
+                IndexedFile {
+                    modified,
+                    size,
+                    songs,
+                    fingerprint: None,
+                },
+            );
+        }
+        report.removed = self.files.len();
+        self.files = files;
+        Ok(report)
+    }
+    /// Every song in the library, ordered by path
+    pub fn songs(&self) -> impl Iterator<Item = &Song> {
+        self.files.values().flat_map(|f| f.songs.iter())
+    }
+    /// Number of songs in the library
+    pub fn len(&self) -> usize {
+        self.files.values().map(|f| f.songs.len()).sum()
+    }
+    /// Returns if the library has no songs
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Songs cached for the file at `path`, empty if it isnt indexed
+    pub fn songs_of(&self, path: &Path) -> &[Song] {
+        self.files
+            .get(path)
+            .map(|f| f.songs.as_slice())
+            .unwrap_or(&[])
+    }
+    /// Acoustic fingerprint of the file at `path`, if it was computed
+    pub fn fingerprint_of(&self, path: &Path) -> Option<&Fingerprint> {
+        self.files.get(path)?.fingerprint.as_ref()
+    }
+    /// Computes the fingerprints the index doesnt have yet, on a few threads
+    /// Only whole files are fingerprinted, not the tracks of CUE sheets.
+    /// `progress` is called with the number of files done and the number to do.
+    /// Returns the errors of the files that failed, they are tried again next time
+    pub fn fingerprint(&mut self, mut progress: impl FnMut(usize, usize)) -> Vec<MelodyErrors> {
+        let todo: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, f)| f.fingerprint.is_none())
+            .filter(|(_, f)| matches!(f.songs.as_slice(), [song] if song.start().is_none()))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let (total, mut done) = (todo.len(), 0);
+        let mut failed = Vec::new();
+        parallel_map(
+            todo,
+            |path: PathBuf| {
+                let fingerprint = Fingerprint::compute(&path);
+                (path, fingerprint)
+            },
+            |(path, fingerprint), _| {
+                match fingerprint {
+                    Ok(fingerprint) => {
+                        if let Some(file) = self.files.get_mut(&path) {
+                            file.fingerprint = Some(fingerprint);
+                        }
+                    }
+                    Err(e) => failed.push(e),
+                }
+                done += 1;
+                progress(done, total);
+            },
+        );
+        failed
+    }
+    /// Reloads the file at `path` if it changed since it was indexed
+    /// Returns `None` if it is unchanged, otherwise if it was indexed before
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn update_file(&mut self, path: &Path) -> Option<bool> {
+        if !self.is_stale(path) {
+            return None;
+        }
+        let (modified, size) = file_stamp(path).ok()?;
+        let songs = load_tracks(path.to_path_buf()).unwrap_or_else(|e| {
+            log::warn!("Failed to load {:?}: {}", path, e);
+            Vec::new()
+        });
+        let file = IndexedFile {
+            modified,
+            size,
+            songs,
+            fingerprint: None,
+        };
+        Some(self.files.insert(path.to_path_buf(), file).is_some())
+    }
+    /// Drops `path` from the index, or every file below it if it is a directory
+    /// Returns the files that were dropped
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn remove_files(&mut self, path: &Path) -> Vec<PathBuf> {
+        let removed: Vec<PathBuf> = self
+            .files
+            .range(path.to_path_buf()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in &removed {
+            self.files.remove(p);
+        }
+        removed
+    }
+    /// Moves the entry of the file at `from` to `to`, after it was renamed
+    /// Its songs and fingerprint are kept, so it isnt read again on the next scan.
+    /// Returns if `from` was indexed
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> bool {
+        let Some(mut file) = self.files.remove(from) else {
+            return false;
+        };
+        for song in &mut file.songs {
+            song.file = to.to_path_buf();
+        }
+        self.files.insert(to.to_path_buf(), file);
+        true
+    }
+    /// Checks if the file at `path` changed since it was indexed
+    pub fn is_stale(&self, path: &Path) -> bool {
+        match (self.files.get(path), file_stamp(path)) {
+            (Some(file), Ok((modified, size))) => file.modified != modified || file.size != size,
+            _ => true,
+        }
+    }
+}
+
+impl Playlist {
+    /// Create a playlist from the songs cached in a library index
+    /// No files are read, see `Library::scan` for updating the index
+    pub fn from_library(library: &Library) -> Self {
+        Self {
+            tracks: library.songs().cloned().collect(),
+        }
+    }
+}
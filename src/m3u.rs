@@ -0,0 +1,237 @@
+use crate::cue::{format_span, load_span, parse_span};
+use crate::errors::MelodyErrors;
+use crate::song::{Playlist, Song};
+use crate::utils::{playlist_entry, read_text, resolve_entry};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Directive Melody writes before tracks of CUE sheets, `#MELODY-SPAN:<start>,<duration>`
+/// in seconds. Other players skip it as a comment and play the whole file
+const SPAN_DIRECTIVE: &str = "#MELODY-SPAN:";
+
+/// Information from an `#EXTINF` line
+struct ExtInf {
+    duration: Option<Duration>,
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+impl ExtInf {
+    /// Parses `#EXTINF:<seconds>[ attributes],<artist> - <title>`
+    fn parse(line: &str) -> Self {
+        // Extended attributes (`tvg-id="..."`) may follow the duration,
+        // their values are quoted and can hold commas
+        let mut quoted = false;
+        let comma = line.find(|c| {
+            quoted ^= c == '"';
+            c == ',' && !quoted
+        });
+        let (head, name) = comma.map_or((line, ""), |i| (&line[..i], &line[i + 1..]));
+        let seconds = head.split_whitespace().next().unwrap_or("");
+        let duration = seconds
+            .parse::<f64>()
+            .ok()
+            .and_then(|s| Duration::try_from_secs_f64(s).ok());
+        let name = name.trim();
+        let (artist, title) = match name.split_once(" - ") {
+            Some((artist, title)) => (Some(artist.trim()), Some(title.trim())),
+            None => (None, Some(name)),
+        };
+        Self {
+            duration,
+            artist: artist.filter(|a| !a.is_empty()).map(String::from),
+            title: title.filter(|t| !t.is_empty()).map(String::from),
+        }
+    }
+
+    /// Fills in what the tags of the song didnt provide
+    fn apply(self, song: &mut Song) {
+        if song.artist.is_none() {
+            song.artist = self.artist;
+        }
+        if song.title.is_none() {
+            song.title = self.title;
+        }
+        if song.duration.is_zero()
+            && let Some(duration) = self.duration
+        {
+            song.duration = duration;
+        }
+    }
+}
+
+impl Playlist {
+    /// Read an M3U / M3U8 playlist
+    /// Relative entries are resolved against the directory of the playlist,
+    /// tracks of CUE sheets written by `write_m3u` are resolved again.
+    /// Returns the playlist along with the entries that could not be resolved or loaded
+    pub fn from_m3u(path: &Path) -> Result<(Self, Vec<MelodyErrors>), MelodyErrors> {
+        let text = read_text(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut tracks = Vec::new();
+        let mut unresolved = Vec::new();
+        let mut extinf: Option<ExtInf> = None;
+        let mut span = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(info) = line.strip_prefix("#EXTINF:") {
+                extinf = Some(ExtInf::parse(info));
+                continue;
+            }
+            if let Some(value) = line.strip_prefix(SPAN_DIRECTIVE) {
+                span = parse_span(value);
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let info = extinf.take();
+            let loaded = match span.take() {
+                Some(span) => resolve_entry(base, line).and_then(|file| load_span(file, span)),
+                None => resolve_entry(base, line).and_then(Song::load),
+            };
+            match loaded {
+                Ok(mut song) => {
+                    if let Some(info) = info {
+                        info.apply(&mut song);
+                    }
+                    tracks.push(song)
+                }
+                Err(e) => unresolved.push(e),
+            }
+        }
+        Ok((Self { tracks }, unresolved))
+    }
+
+    /// Write the playlist as an extended M3U file (UTF-8)
+    /// Songs stored below the playlist's directory are written as relative paths,
+    /// tracks of CUE sheets get a `#MELODY-SPAN` line with where they start
+    pub fn write_m3u(&self, path: &Path) -> Result<(), MelodyErrors> {
+        File::create(path).map_err(|e| MelodyErrors::from_io(e, path))?;
+        append_m3u(path, &self.tracks)
+    }
+}
+
+/// Appends extended M3U entries for `songs` to the playlist at `path`
+/// The `#EXTM3U` header is written if the file is new or empty
+pub(crate) fn append_m3u(path: &Path, songs: &[Song]) -> Result<(), MelodyErrors> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let io_err = |e| MelodyErrors::from_io(e, path);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(io_err)?;
+    let empty = file.metadata().map_err(io_err)?.len() == 0;
+    let mut out = BufWriter::new(file);
+    if empty {
+        writeln!(out, "#EXTM3U").map_err(io_err)?;
+    }
+    for song in songs {
+        let name = match (song.artist(), song.title()) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.to_string(),
+            _ => song
+                .file()
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let seconds = if song.duration().is_zero() {
+            -1
+        } else {
+            song.duration().as_secs() as i64
+        };
+        if let Some(span) = format_span(song) {
+            writeln!(out, "{}{}", SPAN_DIRECTIVE, span).map_err(io_err)?;
+        }
+        writeln!(out, "#EXTINF:{},{}", seconds, name).map_err(io_err)?;
+        writeln!(out, "{}", playlist_entry(base, song.file()).display()).map_err(io_err)?;
+    }
+    out.flush().map_err(io_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixtures;
+
+    fn extinf(line: &str) -> (Option<Duration>, Option<String>, Option<String>) {
+        let info = ExtInf::parse(line);
+        (info.duration, info.artist, info.title)
+    }
+
+    #[test]
+    fn parses_extinf() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            extinf("321,Boards of Canada - Music Is Math"),
+            (
+                Some(Duration::from_secs(321)),
+                some("Boards of Canada"),
+                some("Music Is Math")
+            )
+        );
+        assert_eq!(
+            extinf(r#"-1 tvg-id="boc" tvg-name="Alpha, Omega",Alpha"#),
+            (None, None, some("Alpha"))
+        );
+        assert_eq!(
+            extinf("12.5"),
+            (Some(Duration::from_millis(12500)), None, None)
+        );
+        // Too long or not a number at all, the duration of the file is used instead
+        for seconds in ["1e300", "inf", "NaN", "soon"] {
+            assert_eq!(extinf(&format!("{},Alpha", seconds)).0, None, "{}", seconds);
+        }
+    }
+
+    #[test]
+    fn reads_spans_and_extinf() {
+        let fixtures = Fixtures::new("m3u-spans");
+        fixtures.song("album.wav", 6, None, None);
+        let path = fixtures.0.join("album.m3u");
+        std::fs::write(
+            &path,
+            "#EXTM3U\n\
+             #MELODY-SPAN:2.000,3.000\n\
+             #EXTINF:3,Boards of Canada - Alpha\n\
+             album.wav\n\
+             #MELODY-SPAN:later\n\
+             #EXTINF:1e300,Omega\n\
+             album.wav\n",
+        )
+        .unwrap();
+        let (playlist, errors) = Playlist::from_m3u(&path).unwrap();
+        assert!(errors.is_empty());
+        let [alpha, omega] = playlist.tracks.as_slice() else {
+            panic!("expected two tracks, got {:?}", playlist.tracks);
+        };
+        assert_eq!(alpha.start(), Some(Duration::from_secs(2)));
+        assert_eq!(alpha.duration(), Duration::from_secs(3));
+        assert_eq!(alpha.artist(), Some("Boards of Canada"));
+        assert_eq!(alpha.title(), Some("Alpha"));
+        // A broken span is ignored and the whole file played
+        assert_eq!(omega.start(), None);
+        assert_eq!(omega.duration(), Duration::from_secs(6));
+        assert_eq!(omega.title(), Some("Omega"));
+
+        // Written back the same way, relative to the playlist
+        let copy = fixtures.0.join("copy.m3u");
+        playlist.write_m3u(&copy).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&copy).unwrap(),
+            "#EXTM3U\n\
+             #MELODY-SPAN:2.000,3.000\n\
+             #EXTINF:3,Boards of Canada - Alpha\n\
+             album.wav\n\
+             #EXTINF:6,Omega\n\
+             album.wav\n"
+        );
+    }
+}
@@ -1,11 +1,11 @@
 use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::render::Renderer;
 use crate::song::{Playlist, Song};
-use crate::utils::fmt_duration;
+use crate::utils::{fmt_duration, open_decoder};
 use rand::{rng, seq::SliceRandom};
+use rodio::Source;
 use std::fmt;
-use std::fs::File;
-use std::io::{BufReader, Write};
-use tabwriter::TabWriter;
+use std::path::Path;
 
 /// Music Player Status
 /// Showing the status of the Music player
@@ -87,6 +87,8 @@ impl MusicPlayer {
 
     /// Plays the first song in the Queue if any
     /// Otherwise throws an error
+    /// A song that cant be opened or decoded is taken off the queue and its error returned,
+    /// calling `start` again plays the next one
     pub fn start(&mut self) -> Result<(), MelodyErrors> {
         if self.playlist.is_empty() {
             Err(MelodyErrors::new(
@@ -98,13 +100,14 @@ impl MusicPlayer {
             if self.sink.empty() {
                 self.playing_time.0 = ::std::time::Instant::now();
                 let current = self.playlist.remove(0);
-                // TODO: Make this return an error
-                let file = File::open(&current)
-                    .unwrap_or_else(|_| panic!("Failed to read {:#?}", current.file));
-                // TODO: Make this return an error
-                let source = rodio::Decoder::new(BufReader::new(file))
-                    .unwrap_or_else(|_| panic!("Failed to decode {:#?}", current.file));
-                self.sink.append(source);
+                let source = open_decoder(current.file())?;
+                match current.start {
+                    // Virtual songs only play their span of the file
+                    Some(start) => self
+                        .sink
+                        .append(source.skip_duration(start).take_duration(current.duration)),
+                    None => self.sink.append(source),
+                }
 
                 self.current = Some(current);
             };
@@ -160,6 +163,14 @@ impl MusicPlayer {
     pub fn queue(&self) -> &Vec<Song> {
         &self.playlist
     }
+    /// Add songs to the end of the queue
+    pub fn enqueue(&mut self, songs: impl IntoIterator<Item = Song>) {
+        self.playlist.extend(songs)
+    }
+    /// Remove every queued song of `file`, the current song keeps playing
+    pub fn dequeue(&mut self, file: &Path) {
+        self.playlist.retain(|s| s.file() != file)
+    }
     /// Return the music players status
     pub fn status(&self) -> MusicPlayerStatus {
         if self.sink.empty() {
@@ -180,39 +191,7 @@ impl MusicPlayer {
 
 impl fmt::Display for MusicPlayer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let now_playing = match self.current {
-            Some(ref song) => {
-                let status: &str = if self.sink.is_paused() {
-                    "[paused] "
-                } else {
-                    ""
-                };
-                format!("{}{}\n", status, song)
-            }
-            None => String::new(),
-        };
-        let mut tw = TabWriter::new(Vec::new());
-        let mut lines: Vec<String> = vec![String::from(
-            "|\tArtist\t|\tAlbum\t|\tTitle\t|\tDuration\t|",
-        )];
-        for track in &self.playlist {
-            let duration = fmt_duration(&track.duration);
-            lines.push(format!(
-                "|\t{}\t|\t{}\t|\t{}\t|\t{}\t|",
-                track.artist().unwrap_or("Unkown Artist"),
-                track.album().unwrap_or("Unkown Album"),
-                track.title().unwrap_or("Unkown Title"),
-                duration
-            ))
-        }
-        write!(tw, "{}", lines.join("\n")).unwrap();
-        tw.flush().unwrap();
-        write!(
-            f,
-            "{}{}",
-            now_playing,
-            String::from_utf8(tw.into_inner().unwrap()).unwrap()
-        )
+        f.write_str(&self.render(&Renderer::default()))
     }
 }
 
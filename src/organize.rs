@@ -0,0 +1,492 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::journal::{JournalEntry, JournalWriter, OrganizeJournal, backup_path};
+use crate::pathtemplate::PathTemplate;
+use crate::scan::SkipReason;
+use crate::song::Song;
+use crate::utils::{detect_format, list_files, supported_song};
+use lofty::file::FileType;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind as IoErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How songs are put into the library
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ImportMode {
+    /// Copy the file, the original stays where it is
+    #[default]
+    Copy,
+    /// Move the file, copying it if it is on another filesystem
+    Move,
+    /// Hard link the file, both have to be on the same filesystem
+    Hardlink,
+    /// Put a symbolic link to the original in the library
+    Symlink,
+}
+
+impl ImportMode {
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            ImportMode::Copy => "copy",
+            ImportMode::Move => "move",
+            ImportMode::Hardlink => "hardlink",
+            ImportMode::Symlink => "symlink",
+        }
+    }
+}
+
+impl FromStr for ImportMode {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "copy" => Ok(ImportMode::Copy),
+            "move" => Ok(ImportMode::Move),
+            "hardlink" | "link" => Ok(ImportMode::Hardlink),
+            "symlink" => Ok(ImportMode::Symlink),
+            other => Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidOption,
+                &format!(
+                    "Unknown import mode {:?}, expected copy, move, hardlink or symlink",
+                    other
+                ),
+                None,
+            )),
+        }
+    }
+}
+
+/// What to do when the library already has a different file where a song would go
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Leave both files alone, the song is reported as a duplicate
+    #[default]
+    Skip,
+    /// Replace the file if the song is better quality, lossless beats lossy,
+    /// then the higher bitrate wins
+    OverwriteIfBetter,
+    /// Add a suffix to the new file, like `Song (1).flac`
+    Rename,
+}
+
+impl ConflictPolicy {
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::OverwriteIfBetter => "overwrite-if-better",
+            ConflictPolicy::Rename => "rename",
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite-if-better" | "better" => Ok(ConflictPolicy::OverwriteIfBetter),
+            "rename" => Ok(ConflictPolicy::Rename),
+            other => Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidOption,
+                &format!(
+                    "Unknown conflict policy {:?}, expected skip, overwrite-if-better or rename",
+                    other
+                ),
+                None,
+            )),
+        }
+    }
+}
+
+/// Options for `add_to_library_with`
+#[derive(Clone, Debug, Default)]
+pub struct ImportOptions {
+    pub mode: ImportMode,
+    /// Where songs go inside the library
+    pub template: PathTemplate,
+    pub conflict: ConflictPolicy,
+    /// Only plan the import, the outcomes say what would happen
+    /// Songs are checked against the library and against the songs planned before them
+    /// in the same run, so conflicts come out like they would for a real import
+    pub dry_run: bool,
+    /// Write a journal here so the import can be undone with `OrganizeJournal::undo`,
+    /// it replaces the journal of the previous import
+    pub journal: Option<PathBuf>,
+}
+
+/// What happened to a single file during an import
+#[derive(Debug)]
+pub enum ImportOutcome {
+    /// Put into the library at this path, copied, moved or linked depending on the `ImportMode`
+    Copied(PathBuf),
+    /// Put into the library at this path in place of a worse file
+    Replaced(PathBuf),
+    /// Not a song Melody can play, it was left alone
+    Skipped(SkipReason),
+    /// The library already has this song, or another file at this path, it was left alone
+    Duplicate(PathBuf),
+    Failed(MelodyErrors),
+}
+
+/// Outcome of `add_to_library`, one entry per file found
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Source files and what happened to them, sorted by path
+    pub files: Vec<(PathBuf, ImportOutcome)>,
+}
+
+impl ImportReport {
+    /// Files put into the library, with their new paths
+    pub fn copied(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                ImportOutcome::Copied(to) => Some((from.as_path(), to.as_path())),
+                _ => None,
+            })
+    }
+    /// Files that replaced a worse version in the library, with their new paths
+    pub fn replaced(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                ImportOutcome::Replaced(to) => Some((from.as_path(), to.as_path())),
+                _ => None,
+            })
+    }
+    /// Files that were already in the library, with the path they have there
+    pub fn duplicates(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                ImportOutcome::Duplicate(to) => Some((from.as_path(), to.as_path())),
+                _ => None,
+            })
+    }
+    /// Number of files that werent songs
+    pub fn skipped(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, o)| matches!(o, ImportOutcome::Skipped(_)))
+            .count()
+    }
+    /// Errors of the files that failed
+    pub fn failed(&self) -> impl Iterator<Item = &MelodyErrors> {
+        self.files.iter().filter_map(|(_, outcome)| match outcome {
+            ImportOutcome::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+}
+
+/// Puts `from` at `to` the way `mode` says
+pub(crate) fn transfer(from: &Path, to: &Path, mode: ImportMode) -> Result<(), MelodyErrors> {
+    let io_err = |e| MelodyErrors::from_io(e, from);
+    match mode {
+        ImportMode::Copy => fs::copy(from, to).map(drop).map_err(io_err),
+        // Renaming only works on the same filesystem
+        ImportMode::Move => fs::rename(from, to).or_else(|_| {
+            fs::copy(from, to).map_err(io_err)?;
+            fs::remove_file(from).map_err(io_err)
+        }),
+        ImportMode::Hardlink => fs::hard_link(from, to).map_err(io_err),
+        #[cfg(unix)]
+        ImportMode::Symlink => std::os::unix::fs::symlink(from, to).map_err(io_err),
+        #[cfg(windows)]
+        ImportMode::Symlink => std::os::windows::fs::symlink_file(from, to).map_err(io_err),
+    }
+}
+
+/// Checks if `a` and `b` are the same file, or have the same content
+fn same_file(a: &Path, b: &Path) -> bool {
+    if let (Ok(a), Ok(b)) = (a.canonicalize(), b.canonicalize())
+        && a == b
+    {
+        return true;
+    }
+    let (Ok(mut a), Ok(mut b)) = (File::open(a), File::open(b)) else {
+        return false;
+    };
+    if a.metadata().ok().map(|m| m.len()) != b.metadata().ok().map(|m| m.len()) {
+        return false;
+    }
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let Ok(n) = a.read(&mut buf_a) else {
+            return false;
+        };
+        if n == 0 {
+            return true;
+        }
+        if b.read_exact(&mut buf_b[..n]).is_err() || buf_a[..n] != buf_b[..n] {
+            return false;
+        }
+    }
+}
+
+/// Quality of a song for `ConflictPolicy::OverwriteIfBetter`, lossless first then bitrate
+pub(crate) fn quality(song: &Song) -> (bool, u32) {
+    let lossless = matches!(
+        detect_format(song.file()),
+        Some(FileType::Flac | FileType::Wav | FileType::Aiff)
+    );
+    (lossless, song.bitrate().unwrap_or(0))
+}
+
+/// `dest` with ` (n)` added to the file name
+pub(crate) fn numbered(dest: &Path, n: usize) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    match dest.extension() {
+        Some(ext) => dest.with_file_name(format!("{} ({}).{}", stem, n, ext.to_string_lossy())),
+        None => dest.with_file_name(format!("{} ({})", stem, n)),
+    }
+}
+
+/// Decides what happens to `song`, without touching any files
+/// `claimed` has the files a dry run would already have put into the library, by their path
+fn plan(
+    song: &Song,
+    dest: PathBuf,
+    conflict: ConflictPolicy,
+    claimed: &HashMap<PathBuf, PathBuf>,
+) -> ImportOutcome {
+    // `symlink_metadata` so a broken link in the library counts too
+    let occupant = |path: &Path| match claimed.get(path) {
+        Some(file) => Some(file.clone()),
+        None => path.symlink_metadata().is_ok().then(|| path.to_path_buf()),
+    };
+    let Some(existing) = occupant(&dest) else {
+        return ImportOutcome::Copied(dest);
+    };
+    if same_file(song.file(), &existing) {
+        return ImportOutcome::Duplicate(dest);
+    }
+    match conflict {
+        ConflictPolicy::Skip => ImportOutcome::Duplicate(dest),
+        ConflictPolicy::OverwriteIfBetter => match Song::load(existing) {
+            Ok(existing) if quality(song) > quality(&existing) => ImportOutcome::Replaced(dest),
+            _ => ImportOutcome::Duplicate(dest),
+        },
+        ConflictPolicy::Rename => (1..)
+            .map(|n| numbered(&dest, n))
+            .find_map(|path| match occupant(&path) {
+                None => Some(ImportOutcome::Copied(path)),
+                Some(existing) if same_file(song.file(), &existing) => {
+                    Some(ImportOutcome::Duplicate(path))
+                }
+                Some(_) => None,
+            })
+            .unwrap_or(ImportOutcome::Duplicate(dest)),
+    }
+}
+
+/// Checks `song` and plans where it goes in `to`, see `plan`
+fn plan_song(
+    song: &Song,
+    to: &Path,
+    options: &ImportOptions,
+    claimed: &HashMap<PathBuf, PathBuf>,
+) -> Result<ImportOutcome, MelodyErrors> {
+    if song.file().is_dir() {
+        return Err(MelodyErrors::new(
+            MelodyErrorsKind::PathIsNotAFile,
+            "Song is not a file",
+            Some(song.file()),
+        ));
+    }
+    Ok(plan(
+        song,
+        to.join(options.template.render(song)),
+        options.conflict,
+        claimed,
+    ))
+}
+
+/// Does what `plan` decided for `song`
+fn carry_out(
+    song: &Song,
+    outcome: ImportOutcome,
+    mode: ImportMode,
+) -> Result<ImportOutcome, MelodyErrors> {
+    let (ImportOutcome::Copied(dest) | ImportOutcome::Replaced(dest)) = &outcome else {
+        return Ok(outcome);
+    };
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).map_err(|e| MelodyErrors::from_io(e, dir))?;
+    }
+    let replaced = matches!(outcome, ImportOutcome::Replaced(_));
+    let backup = backup_path(dest);
+    if replaced {
+        fs::rename(dest, &backup).map_err(|e| MelodyErrors::from_io(e, dest))?;
+    }
+    if let Err(e) = transfer(song.file(), dest, mode) {
+        // Put the old file back so nothing is lost
+        if replaced && let Err(e) = fs::rename(&backup, dest) {
+            log::warn!("Failed to restore {:?} from {:?}: {}", dest, backup, e);
+        }
+        return Err(e);
+    }
+    Ok(outcome)
+}
+
+/// Function meant to organize a specific song
+/// Song: Song to organize
+/// to: Library directory, the song goes where `options.template` says inside of it
+/// Returns `Copied` or `Replaced` with the new path, or `Duplicate` if it was left out
+/// because of `options.conflict`. Nothing is changed for a dry run
+/// A replaced file is kept as a hidden backup next to the new one, see `OrganizeJournal`
+pub fn organize_song(
+    song: &Song,
+    to: &Path,
+    options: &ImportOptions,
+) -> Result<ImportOutcome, MelodyErrors> {
+    let outcome = plan_song(song, to, options, &HashMap::new())?;
+    if options.dry_run {
+        return Ok(outcome);
+    }
+    carry_out(song, outcome, options.mode)
+}
+
+/// Checks the arguments of `add_to_library`
+fn check_dirs(from: &Path, to: &Path) -> Result<(), MelodyErrors> {
+    use self::MelodyErrorsKind::*;
+    for (name, dir) in [("from", from), ("to", to)] {
+        if dir.is_relative() {
+            return Err(MelodyErrors::new(
+                NotAbsolutePath,
+                &format!("`{}` path is not absolute", name),
+                Some(dir),
+            ));
+        }
+        if !dir.exists() {
+            return Err(MelodyErrors::new(
+                Io(IoErrorKind::NotFound),
+                &format!("`{}` path does not exist", name),
+                Some(dir),
+            ));
+        }
+        if !dir.is_dir() {
+            return Err(MelodyErrors::new(
+                PathIsNotADir,
+                &format!("`{}` is not a directory", name),
+                Some(dir),
+            ));
+        }
+    }
+    // Otherwise imported songs would be found again while walking `from`
+    if to.starts_with(from) {
+        return Err(MelodyErrors::new(
+            ChildOfParentRecursion,
+            "`to` is inside `from`",
+            Some(to),
+        ));
+    }
+    Ok(())
+}
+
+/// Add to library, copying the songs
+/// See `add_to_library_with`
+pub fn add_to_library(from: &Path, to: &Path) -> Result<ImportReport, MelodyErrors> {
+    add_to_library_with(from, to, &ImportOptions::default())
+}
+
+/// Add to library
+/// `from` - Original Directory that the music being moved from, must be an absolute path
+/// `to` - New Directory that the music is being moved to, must be an absolute path
+/// `to` can not be inside of `from`
+/// Every song is organized into `to` with `organize_song`,
+/// files that arent songs or failed to be sorted will remain
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// let options = melody::ImportOptions {
+///     mode: melody::ImportMode::Move,
+///     ..Default::default()
+/// };
+/// let report = melody::add_to_library_with(Path::new("/tmp/downloads"), Path::new("/music"), &options)?;
+/// for (from, to) in report.copied() {
+///     println!("{} -> {}", from.display(), to.display());
+/// }
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+pub fn add_to_library_with(
+    from: &Path,
+    to: &Path,
+    options: &ImportOptions,
+) -> Result<ImportReport, MelodyErrors> {
+    check_dirs(from, to)?;
+    let mut files: Vec<PathBuf> = list_files(from.to_path_buf())
+        .filter(|f| f.is_file())
+        .collect();
+    files.sort();
+    let journal_path = options.journal.as_ref().filter(|_| !options.dry_run);
+    // Destinations of a dry run, which arent in the library to conflict with later songs
+    let mut claimed = HashMap::new();
+    // Only started once something changes, so runs that do nothing keep the last journal
+    let mut journal: Option<JournalWriter> = None;
+    let mut report = ImportReport::default();
+    for file in files {
+        if !supported_song(&file) {
+            report.files.push((
+                file,
+                ImportOutcome::Skipped(SkipReason::UnsupportedExtension),
+            ));
+            continue;
+        }
+        let planned = Song::load(file.clone()).and_then(|song| {
+            let outcome = plan_song(&song, to, options, &claimed)?;
+            Ok((song, outcome))
+        });
+        let (song, outcome, dest) = match planned {
+            Ok((song, outcome)) => match &outcome {
+                ImportOutcome::Copied(dest) | ImportOutcome::Replaced(dest) => {
+                    let dest = dest.clone();
+                    (song, outcome, dest)
+                }
+                _ => {
+                    report.files.push((file, outcome));
+                    continue;
+                }
+            },
+            Err(e) => {
+                report.files.push((file, ImportOutcome::Failed(e)));
+                continue;
+            }
+        };
+        if options.dry_run {
+            claimed.insert(dest, file.clone());
+            report.files.push((file, outcome));
+            continue;
+        }
+        if journal.is_none()
+            && let Some(path) = journal_path
+        {
+            // The previous import cant be undone anymore
+            if path.exists() {
+                match OrganizeJournal::open(path) {
+                    Ok(old) => old.discard_backups(),
+                    Err(e) => log::warn!("Failed to read the old organize journal: {}", e),
+                }
+            }
+            journal = Some(JournalWriter::create(path, to)?);
+        }
+        let outcome = carry_out(&song, outcome, options.mode).unwrap_or_else(ImportOutcome::Failed);
+        if let (Some(journal), ImportOutcome::Copied(dest) | ImportOutcome::Replaced(dest)) =
+            (&mut journal, &outcome)
+        {
+            let entry = JournalEntry {
+                mode: options.mode,
+                from: file.clone(),
+                to: dest.clone(),
+                replaced: matches!(outcome, ImportOutcome::Replaced(_)),
+            };
+            if let Err(e) = journal.record(&entry) {
+                log::warn!("Failed to write the organize journal: {}", e);
+            }
+        }
+        report.files.push((file, outcome));
+    }
+    Ok(report)
+}
@@ -0,0 +1,440 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::{Song, UNKNOWN_ALBUM, UNKNOWN_ARTIST, UNKNOWN_TITLE};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Layout used when no template is given, `Artist/Album/file name`
+pub const DEFAULT_PATH_TEMPLATE: &str = "{artist}/{album}/{filename}.{ext}";
+
+/// Longest file or directory name most filesystems allow, in bytes
+const MAX_NAME: usize = 255;
+
+/// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Track,
+    Disc,
+    Year,
+    Genre,
+    Compilation,
+    Filename,
+    Ext,
+}
+
+impl Field {
+    const NAMES: &'static str =
+        "artist, albumartist, album, title, track, disc, year, genre, compilation, filename, ext";
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.trim().to_lowercase().as_str() {
+            "artist" => Field::Artist,
+            "albumartist" | "album_artist" => Field::AlbumArtist,
+            "album" => Field::Album,
+            "title" => Field::Title,
+            "track" => Field::Track,
+            "disc" | "disk" => Field::Disc,
+            "year" => Field::Year,
+            "genre" => Field::Genre,
+            "compilation" => Field::Compilation,
+            "filename" => Field::Filename,
+            "ext" | "extension" => Field::Ext,
+            _ => return None,
+        })
+    }
+    fn value(self, song: &Song) -> Option<String> {
+        let text = |s: Option<&str>| s.map(str::trim).filter(|s| !s.is_empty()).map(String::from);
+        match self {
+            Field::Artist => text(song.artist()),
+            Field::AlbumArtist => text(song.album_artist()).or_else(|| text(song.artist())),
+            Field::Album => text(song.album()),
+            Field::Title => text(song.title()),
+            Field::Track => song.track().map(|n| n.to_string()),
+            Field::Disc => song.disc().map(|n| n.to_string()),
+            Field::Year => song.year().map(|n| n.to_string()),
+            Field::Genre => text(song.genre()),
+            Field::Compilation => song.is_compilation().then(|| "Various Artists".to_string()),
+            Field::Filename => text(song.file().file_stem().and_then(|s| s.to_str())),
+            Field::Ext => song
+                .file()
+                .extension()
+                .and_then(|s| s.to_str())
+                .map(str::to_lowercase),
+        }
+    }
+    /// Written when the field is missing outside of an optional section
+    fn placeholder(self) -> &'static str {
+        match self {
+            Field::Artist | Field::AlbumArtist | Field::Compilation => UNKNOWN_ARTIST,
+            Field::Album => UNKNOWN_ALBUM,
+            Field::Title => UNKNOWN_TITLE,
+            Field::Genre => "Unknown Genre",
+            Field::Filename => "Unknown File",
+            Field::Track | Field::Disc | Field::Year | Field::Ext => "",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Text(String),
+    /// Alternatives tried in order, the width and if it pads with zeros
+    Field(Vec<Field>, usize, bool),
+    /// Left out entirely if one of its fields is missing
+    Optional(Vec<Part>),
+}
+
+/// Where `organize_song` puts a song, relative to the library
+///
+/// `{field}` is replaced by a tag, `/` separates directories:
+/// `{albumartist}/[{year} - ]{album}/[{disc}-]{track:02} {title}.{ext}`
+/// - Fields are artist, albumartist (falls back to artist), album, title, track, disc,
+///   year, genre, compilation, filename (without the extension) and ext
+/// - `{track:02}` pads with zeros, `{title:20}` with spaces
+/// - `{a|b}` uses `b` if `a` is missing. `compilation` is only there for compilations,
+///   so `{compilation|albumartist}` files those under "Various Artists"
+/// - `[...]` is left out if a field inside it is missing, other missing fields
+///   are written as "Unknown Artist", "Unknown Album" and so on, or nothing for numbers
+/// - `{{`, `}}`, `[[` and `]]` are literal brackets
+///
+/// Tags are made safe for FAT and NTFS: `<>:"/\|?*` and control characters become `_`,
+/// names dont end in dots or spaces, reserved names like `CON` get a `_` and
+/// names are cut to 255 bytes, keeping the extension
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PathTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+fn invalid(msg: &str) -> MelodyErrors {
+    MelodyErrors::new(MelodyErrorsKind::InvalidTemplate, msg, None)
+}
+
+/// Parses parts until the end of the template, or the `]` closing an optional section
+fn parse_parts(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    optional: bool,
+) -> Result<Vec<Part>, MelodyErrors> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' | '[' | ']' if chars.peek() == Some(&c) => {
+                chars.next();
+                text.push(c);
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(invalid("Unterminated `{` in template")),
+                    }
+                }
+                let (names, width) = name.split_once(':').unwrap_or((&name, ""));
+                let fields = names
+                    .split('|')
+                    .map(|n| {
+                        Field::parse(n).ok_or_else(|| {
+                            invalid(&format!(
+                                "Unknown field {:?}, expected one of {}",
+                                n,
+                                Field::NAMES
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let zeros = width.starts_with('0');
+                let width = match width {
+                    "" => 0,
+                    w => w
+                        .parse()
+                        .map_err(|_| invalid(&format!("Invalid width for {}: {:?}", names, w)))?,
+                };
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Field(fields, width, zeros));
+            }
+            '[' => {
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Optional(parse_parts(chars, true)?));
+            }
+            ']' if optional => {
+                if !text.is_empty() {
+                    parts.push(Part::Text(text));
+                }
+                return Ok(parts);
+            }
+            '}' => return Err(invalid("Unmatched `}` in template")),
+            ']' => return Err(invalid("Unmatched `]` in template")),
+            c => text.push(c),
+        }
+    }
+    if optional {
+        return Err(invalid("Unterminated `[` in template"));
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+/// Replaces characters that cant be in a file name on FAT or NTFS
+fn sanitize_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Cuts `s` to at most `max` bytes without splitting a character
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Makes a single file or directory name safe, `keep_ext` keeps the
+/// extension when it has to be shortened
+fn sanitize_name(name: &str, keep_ext: bool) -> String {
+    let mut name = name.trim().trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() || name == "." || name == ".." {
+        return "_".to_string();
+    }
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        name.insert(stem.len(), '_');
+    }
+    if name.len() > MAX_NAME {
+        let ext = match name.rfind('.') {
+            Some(dot) if keep_ext && name.len() - dot < 16 => name[dot..].to_string(),
+            _ => String::new(),
+        };
+        let stem = truncate(&name, MAX_NAME - ext.len()).trim_end_matches(['.', ' ']);
+        name = format!("{}{}", stem, ext);
+    }
+    name
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, MelodyErrors> {
+        let parts = parse_parts(&mut template.chars().peekable(), false)?;
+        if parts.is_empty() {
+            return Err(invalid("Empty path template"));
+        }
+        Ok(Self {
+            source: template.to_string(),
+            parts,
+        })
+    }
+    /// Renders `parts`, `None` if `optional` and a field is missing
+    fn render_parts(parts: &[Part], song: &Song, optional: bool) -> Option<String> {
+        let mut out = String::new();
+        for part in parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(fields, width, zeros) => {
+                    let value = fields.iter().find_map(|f| f.value(song));
+                    match value.map(|v| sanitize_value(&v)) {
+                        Some(value) if *zeros => {
+                            out.push_str(&format!("{:0>width$}", value, width = width))
+                        }
+                        Some(value) => out.push_str(&format!("{:width$}", value, width = width)),
+                        None if optional => return None,
+                        None => {
+                            let last = fields.last().map_or("", |f| f.placeholder());
+                            out.push_str(last)
+                        }
+                    }
+                }
+                Part::Optional(inner) => {
+                    out.push_str(&Self::render_parts(inner, song, true).unwrap_or_default())
+                }
+            }
+        }
+        Some(out)
+    }
+    /// Path of `song` relative to the library
+    pub fn render(&self, song: &Song) -> PathBuf {
+        let rendered = Self::render_parts(&self.parts, song, false).unwrap_or_default();
+        let names: Vec<&str> = rendered
+            .split(['/', '\\'])
+            .filter(|n| !n.trim().is_empty())
+            .collect();
+        match names.split_last() {
+            Some((file, dirs)) => dirs
+                .iter()
+                .map(|d| sanitize_name(d, false))
+                .chain(Some(sanitize_name(file, true)))
+                .collect(),
+            None => PathBuf::from("_"),
+        }
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_PATH_TEMPLATE).expect("The default path template is valid")
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn song(artist: &str, album: &str, title: &str, track: Option<u32>, year: Option<u32>) -> Song {
+        let text = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        Song {
+            artist: text(artist),
+            album: text(album),
+            album_artist: None,
+            title: text(title),
+            track,
+            disc: None,
+            genre: None,
+            year,
+            compilation: false,
+            duration: Duration::from_secs(60),
+            bitrate: None,
+            file: PathBuf::from("/import/01 track.FLAC"),
+            elapsed: Duration::ZERO,
+            start: None,
+        }
+    }
+
+    fn render(template: &str, song: &Song) -> String {
+        let path = PathTemplate::parse(template).unwrap().render(song);
+        path.to_string_lossy().replace('\\', "/")
+    }
+
+    #[test]
+    fn parses_templates() {
+        let template =
+            PathTemplate::parse("{albumartist}/[{year} - ]{album:3}/{{{track:02}]]").unwrap();
+        assert_eq!(
+            template.parts,
+            [
+                Part::Field(vec![Field::AlbumArtist], 0, false),
+                Part::Text("/".to_string()),
+                Part::Optional(vec![
+                    Part::Field(vec![Field::Year], 0, false),
+                    Part::Text(" - ".to_string()),
+                ]),
+                Part::Field(vec![Field::Album], 3, false),
+                Part::Text("/{".to_string()),
+                Part::Field(vec![Field::Track], 2, true),
+                Part::Text("]".to_string()),
+            ]
+        );
+        assert_eq!(
+            PathTemplate::parse("{compilation|album_artist}")
+                .unwrap()
+                .parts,
+            [Part::Field(
+                vec![Field::Compilation, Field::AlbumArtist],
+                0,
+                false
+            )]
+        );
+        for broken in [
+            "",
+            "{artist",
+            "{colour}",
+            "{track:two}",
+            "artist}",
+            "[{year}",
+            "{year}]",
+        ] {
+            assert!(PathTemplate::parse(broken).is_err(), "{:?}", broken);
+        }
+    }
+
+    #[test]
+    fn optional_sections_need_every_field() {
+        let template = "{artist}/[{year} - ]{album}/[{track:02} ]{title}.{ext}";
+        let dated = song(
+            "Boards of Canada",
+            "Geogaddi",
+            "Music Is Math",
+            Some(3),
+            Some(2002),
+        );
+        assert_eq!(
+            render(template, &dated),
+            "Boards of Canada/2002 - Geogaddi/03 Music Is Math.flac"
+        );
+        let undated = song("Boards of Canada", "Geogaddi", "Music Is Math", None, None);
+        assert_eq!(
+            render(template, &undated),
+            "Boards of Canada/Geogaddi/Music Is Math.flac"
+        );
+        // Outside of a section missing fields get a placeholder
+        let untagged = song("", "", "", None, None);
+        assert_eq!(
+            render(template, &untagged),
+            "Unknown Artist/Unknown Album/Unknown Title.flac"
+        );
+    }
+
+    #[test]
+    fn pads_fields() {
+        let track = song("Boards of Canada", "Geogaddi", "Alpha", Some(7), None);
+        assert_eq!(render("{track:02} {title}", &track), "07 Alpha");
+        assert_eq!(render("{track:03}-{title:7}-", &track), "007-Alpha  -");
+        let long = song("Boards of Canada", "Geogaddi", "Alpha", Some(123), None);
+        assert_eq!(render("{track:02}", &long), "123");
+    }
+
+    #[test]
+    fn sanitizes_names() {
+        let reserved = song("CON", "aux.", "Nul", Some(1), None);
+        assert_eq!(
+            render("{artist}/{album}/{title}.{ext}", &reserved),
+            "CON_/aux_/Nul_.flac"
+        );
+        let dots = song("..", "Geogaddi... ", "AC/DC: \"Live\"?", None, None);
+        assert_eq!(
+            render("{artist}/{album}/{title}", &dots),
+            "_/Geogaddi/AC_DC_ _Live__"
+        );
+        // Only separators in the template make directories
+        let traversal = song("../../etc", "Geogaddi", "passwd", None, None);
+        assert_eq!(render("{artist}/{title}", &traversal), ".._.._etc/passwd");
+        let long = song("Boards of Canada", "Geogaddi", &"a".repeat(300), None, None);
+        let name = render("{title}.{ext}", &long);
+        assert_eq!(name.len(), MAX_NAME);
+        assert!(name.ends_with("a.flac"));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cue::{format_span, load_span, parse_span};
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::{Playlist, Song};
+use crate::utils::{playlist_entry, read_text, resolve_entry};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// A single `FileN`/`TitleN`/`LengthN` group
+#[derive(Default)]
+struct PlsEntry {
+    file: Option<String>,
+    title: Option<String>,
+    length: Option<i64>,
+    /// `MelodySpanN`, where a track of a CUE sheet sits in the file
+    span: Option<(Duration, Duration)>,
+}
+
+impl Playlist {
+    /// Read a PLS playlist
+    /// Relative entries are resolved against the directory of the playlist,
+    /// tracks of CUE sheets written by `write_pls` are resolved again.
+    /// Returns the playlist along with the entries that could not be resolved or loaded
+    pub fn from_pls(path: &Path) -> Result<(Self, Vec<MelodyErrors>), MelodyErrors> {
+        let text = read_text(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        if !text
+            .lines()
+            .any(|l| l.trim().eq_ignore_ascii_case("[playlist]"))
+        {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::MalformedPlaylist,
+                "Missing [playlist] section",
+                Some(path),
+            ));
+        }
+        // Entries are numbered, but the numbers dont have to be in order
+        let mut entries: BTreeMap<u32, PlsEntry> = BTreeMap::new();
+        for line in text.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+            let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+            let Ok(index) = key[split..].parse::<u32>() else {
+                continue;
+            };
+            let entry = entries.entry(index).or_default();
+            match &key[..split] {
+                "file" => entry.file = Some(value.to_string()),
+                "title" => entry.title = Some(value.to_string()),
+                "length" => entry.length = value.parse().ok(),
+                "melodyspan" => entry.span = parse_span(value),
+                _ => (),
+            }
+        }
+        let mut tracks = Vec::with_capacity(entries.len());
+        let mut unresolved = Vec::new();
+        for (index, entry) in entries {
+            let Some(file) = entry.file else {
+                unresolved.push(MelodyErrors::new(
+                    MelodyErrorsKind::MalformedPlaylist,
+                    &format!("Entry {} has no File{} key", index, index),
+                    Some(path),
+                ));
+                continue;
+            };
+            let loaded = match entry.span {
+                Some(span) => resolve_entry(base, &file).and_then(|file| load_span(file, span)),
+                None => resolve_entry(base, &file).and_then(Song::load),
+            };
+            match loaded {
+                Ok(mut song) => {
+                    if let Some(title) = entry.title {
+                        match title.split_once(" - ") {
+                            Some((artist, title)) => {
+                                song.artist.get_or_insert_with(|| artist.trim().to_string());
+                                song.title.get_or_insert_with(|| title.trim().to_string());
+                            }
+                            None => {
+                                song.title.get_or_insert(title);
+                            }
+                        }
+                    }
+                    if song.duration.is_zero()
+                        && let Some(length) = entry.length.filter(|l| *l > 0)
+                    {
+                        song.duration = Duration::from_secs(length as u64);
+                    }
+                    tracks.push(song)
+                }
+                Err(e) => unresolved.push(e),
+            }
+        }
+        Ok((Self { tracks }, unresolved))
+    }
+
+    /// Write the playlist as a PLS (version 2) file
+    /// Songs stored below the playlist's directory are written as relative paths,
+    /// tracks of CUE sheets get a `MelodySpanN` key other players ignore
+    pub fn write_pls(&self, path: &Path) -> Result<(), MelodyErrors> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let io_err = |e| MelodyErrors::from_io(e, path);
+        let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+        writeln!(out, "[playlist]").map_err(io_err)?;
+        for (i, song) in self.tracks.iter().enumerate() {
+            let n = i + 1;
+            writeln!(
+                out,
+                "File{}={}",
+                n,
+                playlist_entry(base, song.file()).display()
+            )
+            .map_err(io_err)?;
+            match (song.artist(), song.title()) {
+                (Some(artist), Some(title)) => writeln!(out, "Title{}={} - {}", n, artist, title),
+                (None, Some(title)) => writeln!(out, "Title{}={}", n, title),
+                _ => Ok(()),
+            }
+            .map_err(io_err)?;
+            let length = if song.duration().is_zero() {
+                -1
+            } else {
+                song.duration().as_secs() as i64
+            };
+            writeln!(out, "Length{}={}", n, length).map_err(io_err)?;
+            if let Some(span) = format_span(song) {
+                writeln!(out, "MelodySpan{}={}", n, span).map_err(io_err)?;
+            }
+        }
+        writeln!(out, "NumberOfEntries={}", self.tracks.len()).map_err(io_err)?;
+        writeln!(out, "Version=2").map_err(io_err)?;
+        out.flush().map_err(io_err)
+    }
+}
@@ -0,0 +1,549 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::library::Library;
+use crate::song::{Playlist, Song};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Song fields a query can filter or sort on
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Artist,
+    Album,
+    Title,
+    Genre,
+    Path,
+    Track,
+    Year,
+    Duration,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "artist" => Field::Artist,
+            "album" => Field::Album,
+            "title" => Field::Title,
+            "genre" => Field::Genre,
+            "path" | "file" => Field::Path,
+            "track" => Field::Track,
+            "year" => Field::Year,
+            "duration" | "length" => Field::Duration,
+            _ => return None,
+        })
+    }
+    fn is_numeric(self) -> bool {
+        matches!(self, Field::Track | Field::Year | Field::Duration)
+    }
+    fn text(self, song: &Song) -> Option<String> {
+        match self {
+            Field::Artist => song.artist().map(str::to_string),
+            Field::Album => song.album().map(str::to_string),
+            Field::Title => song.title().map(str::to_string),
+            Field::Genre => song.genre().map(str::to_string),
+            Field::Path => Some(song.file().to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+    /// Numeric value, durations are in milliseconds
+    fn number(self, song: &Song) -> Option<u64> {
+        match self {
+            Field::Track => song.track().map(u64::from),
+            Field::Year => song.year().map(u64::from),
+            Field::Duration => Some(song.duration().as_millis() as u64),
+            _ => None,
+        }
+    }
+    /// Orders two songs by this field, songs missing it go last even when `descending`
+    fn cmp(self, a: &Song, b: &Song, descending: bool) -> Ordering {
+        fn missing_last<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) if descending => b.cmp(&a),
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+        if self.is_numeric() {
+            missing_last(self.number(a), self.number(b), descending)
+        } else {
+            missing_last(
+                self.text(a).map(|s| s.to_lowercase()),
+                self.text(b).map(|s| s.to_lowercase()),
+                descending,
+            )
+        }
+    }
+}
+
+/// Comparison against a numeric field
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Compare {
+    Eq(u64),
+    Lt(u64),
+    Le(u64),
+    Gt(u64),
+    Ge(u64),
+    /// Inclusive range, `a..b`
+    Range(u64, u64),
+}
+
+impl Compare {
+    fn matches(self, n: u64) -> bool {
+        match self {
+            Compare::Eq(v) => n == v,
+            Compare::Lt(v) => n < v,
+            Compare::Le(v) => n <= v,
+            Compare::Gt(v) => n > v,
+            Compare::Ge(v) => n >= v,
+            Compare::Range(from, to) => from <= n && n <= to,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Condition {
+    /// Case insensitive match, `exact` or as a substring
+    Text {
+        field: Field,
+        value: String,
+        exact: bool,
+    },
+    Number {
+        field: Field,
+        compare: Compare,
+    },
+    /// A bare word, matched against the artist, album and title
+    Any(String),
+}
+
+impl Condition {
+    fn matches(&self, song: &Song) -> bool {
+        match self {
+            Condition::Text {
+                field,
+                value,
+                exact,
+            } => field.text(song).is_some_and(|text| {
+                let text = text.to_lowercase();
+                if *exact {
+                    text == *value
+                } else {
+                    text.contains(value.as_str())
+                }
+            }),
+            Condition::Number { field, compare } => {
+                field.number(song).is_some_and(|n| compare.matches(n))
+            }
+            Condition::Any(value) => [Field::Artist, Field::Album, Field::Title]
+                .iter()
+                .filter_map(|f| f.text(song))
+                .any(|text| text.to_lowercase().contains(value.as_str())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Filter {
+    negated: bool,
+    condition: Condition,
+}
+
+/// A filter over songs and an order to sort them in
+/// Terms are separated by spaces and all have to match:
+/// - `artist:"Boards of Canada"` artist contains the text, case insensitive,
+///   `artist:=Aphex` has to match exactly. Works for artist, album, title, genre and path
+/// - `year:>1998`, `track:<=3`, `year:1995..1999` compare numbers, also `<`, `>=` and `=`
+/// - `duration:<5m` compares lengths, written as `90s`, `4m30s`, `1h` or `4:30`
+/// - a bare word matches the artist, album or title
+/// - a leading `-` negates a term, like `-genre:christmas`
+/// - `sort:album,track` sorts by album then track, `-` sorts a key descending (`sort:-year`)
+///
+/// Songs missing a field dont match filters on it and sort last
+/// # Example
+/// ```
+/// let query: melody::Query = r#"artist:"Boards of Canada" year:>1998 sort:album,track"#.parse()?;
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Query {
+    filters: Vec<Filter>,
+    /// Fields to sort by and if they are descending
+    sort: Vec<(Field, bool)>,
+    /// The text the query was parsed from, shown by `Display`
+    source: String,
+}
+
+fn invalid(msg: &str) -> MelodyErrors {
+    MelodyErrors::new(MelodyErrorsKind::InvalidQuery, msg, None)
+}
+
+/// Splits a query into terms on spaces, keeping quoted text together
+/// Quotes are dropped, `"a b"` and `a" "b` are both `a b`.
+/// Each term comes with where its first `:` outside of quotes is, if it has one
+fn terms(query: &str) -> Result<Vec<(String, Option<usize>)>, MelodyErrors> {
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    let mut colon = None;
+    let mut quoted = false;
+    let mut started = false;
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    terms.push((std::mem::take(&mut term), colon.take()));
+                }
+                started = false;
+            }
+            c => {
+                if c == ':' && !quoted && colon.is_none() {
+                    colon = Some(term.len());
+                }
+                term.push(c);
+                started = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(invalid("Unterminated quote in query"));
+    }
+    if started {
+        terms.push((term, colon));
+    }
+    Ok(terms)
+}
+
+/// Parses a duration like `90`, `90s`, `4m30s`, `1h`, `30d`, `2w`, `4:30` or `1:02:03`
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    if s.contains(':') {
+        let mut secs = 0.0;
+        for part in s.split(':') {
+            secs = secs * 60.0 + part.parse::<f64>().ok()?;
+        }
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+    let mut secs = 0.0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 7.0 * 86400.0,
+            'd' => 86400.0,
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        secs += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+fn parse_number(field: Field, s: &str) -> Option<u64> {
+    match field {
+        Field::Duration => parse_duration(s).map(|d| d.as_millis() as u64),
+        _ => s.parse().ok(),
+    }
+}
+
+fn parse_compare(field: Field, value: &str) -> Option<Compare> {
+    if let Some((from, to)) = value.split_once("..") {
+        return Some(Compare::Range(
+            parse_number(field, from)?,
+            parse_number(field, to)?,
+        ));
+    }
+    // Longest operators first, so `>=` isnt read as `>` followed by `=1998`
+    for op in [">=", "<=", ">", "<", "="] {
+        let Some(value) = value.strip_prefix(op) else {
+            continue;
+        };
+        let n = parse_number(field, value)?;
+        return Some(match op {
+            ">=" => Compare::Ge(n),
+            "<=" => Compare::Le(n),
+            ">" => Compare::Gt(n),
+            "<" => Compare::Lt(n),
+            _ => Compare::Eq(n),
+        });
+    }
+    Some(Compare::Eq(parse_number(field, value)?))
+}
+
+fn parse_sort(keys: &str) -> Result<Vec<(Field, bool)>, MelodyErrors> {
+    keys.split(',')
+        .filter(|k| !k.is_empty())
+        .map(|key| {
+            let (name, descending) = match key.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (key, false),
+            };
+            Field::parse(name)
+                .map(|field| (field, descending))
+                .ok_or_else(|| invalid(&format!("Unknown sort key {:?}", name)))
+        })
+        .collect()
+}
+
+impl Query {
+    /// Parse a query, see `Query` for the syntax
+    pub fn parse(query: &str) -> Result<Self, MelodyErrors> {
+        let mut parsed = Query {
+            source: query.trim().to_string(),
+            ..Query::default()
+        };
+        for (term, colon) in terms(query)? {
+            let (negated, term, colon) = match term.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest, colon.map(|c| c - 1)),
+                _ => (false, term.as_str(), colon),
+            };
+            // A quoted `:` is part of the text, `"Re: Stacks"` is a bare word
+            let Some((key, value)) = colon.map(|c| (&term[..c], &term[c + 1..])) else {
+                parsed.filters.push(Filter {
+                    negated,
+                    condition: Condition::Any(term.to_lowercase()),
+                });
+                continue;
+            };
+            if key.eq_ignore_ascii_case("sort") {
+                parsed.sort.extend(parse_sort(value)?);
+                continue;
+            }
+            let field =
+                Field::parse(key).ok_or_else(|| invalid(&format!("Unknown field {:?}", key)))?;
+            let condition = if field.is_numeric() {
+                let compare = parse_compare(field, value)
+                    .ok_or_else(|| invalid(&format!("Invalid value for {}: {:?}", key, value)))?;
+                Condition::Number { field, compare }
+            } else {
+                let (value, exact) = match value.strip_prefix('=') {
+                    Some(value) => (value, true),
+                    None => (value, false),
+                };
+                Condition::Text {
+                    field,
+                    value: value.to_lowercase(),
+                    exact,
+                }
+            };
+            parsed.filters.push(Filter { negated, condition });
+        }
+        Ok(parsed)
+    }
+    /// Add sort keys, written like the value of `sort:` (`album,-track`)
+    pub fn sort_by(mut self, keys: &str) -> Result<Self, MelodyErrors> {
+        self.sort.extend(parse_sort(keys)?);
+        self.source = format!("{} sort:{}", self.source, keys).trim().to_string();
+        Ok(self)
+    }
+    /// Checks if `song` passes every filter of the query
+    pub fn matches(&self, song: &Song) -> bool {
+        self.filters
+            .iter()
+            .all(|f| f.condition.matches(song) != f.negated)
+    }
+    /// Checks if the query sorts songs
+    pub fn has_sort(&self) -> bool {
+        !self.sort.is_empty()
+    }
+    /// Sorts `songs` by the sort keys of the query, keeping the order of equal songs
+    pub fn sort(&self, songs: &mut [Song]) {
+        if self.sort.is_empty() {
+            return;
+        }
+        songs.sort_by(|a, b| {
+            self.sort
+                .iter()
+                .map(|&(field, descending)| field.cmp(a, b, descending))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+}
+
+impl FromStr for Query {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Playlist {
+    /// Keep only the songs matching `query`, sorted by its sort keys
+    pub fn filter(&mut self, query: &Query) {
+        self.tracks.retain(|s| query.matches(s));
+        query.sort(&mut self.tracks);
+    }
+}
+
+impl Library {
+    /// Songs of the library matching `query`, sorted by its sort keys
+    pub fn query(&self, query: &Query) -> Playlist {
+        let mut tracks: Vec<Song> = self.songs().filter(|s| query.matches(s)).cloned().collect();
+        query.sort(&mut tracks);
+        Playlist { tracks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn song(artist: &str, title: &str, year: Option<u32>, secs: u64) -> Song {
+        Song {
+            artist: Some(artist.to_string()),
+            album: Some("Geogaddi".to_string()),
+            album_artist: None,
+            title: Some(title.to_string()),
+            track: None,
+            disc: None,
+            genre: None,
+            year,
+            compilation: false,
+            duration: Duration::from_secs(secs),
+            bitrate: None,
+            file: PathBuf::from(format!("/music/{}.flac", title)),
+            elapsed: Duration::ZERO,
+            start: None,
+        }
+    }
+
+    fn filters(query: &str) -> Vec<Filter> {
+        Query::parse(query).unwrap().filters
+    }
+
+    fn text(field: Field, value: &str, exact: bool) -> Condition {
+        Condition::Text {
+            field,
+            value: value.to_string(),
+            exact,
+        }
+    }
+
+    #[test]
+    fn terms_keep_quoted_text_together() {
+        let terms = terms(r#"artist:"Boards of Canada"  a" "b -x"#).unwrap();
+        assert_eq!(
+            terms,
+            [
+                ("artist:Boards of Canada".to_string(), Some(6)),
+                ("a b".to_string(), None),
+                ("-x".to_string(), None),
+            ]
+        );
+        assert!(Query::parse(r#"artist:"Boards"#).is_err());
+    }
+
+    #[test]
+    fn quoted_colons_are_text() {
+        assert_eq!(
+            filters(r#""Re: Stacks" title:"Re: Stacks""#),
+            [
+                Filter {
+                    negated: false,
+                    condition: Condition::Any("re: stacks".to_string()),
+                },
+                Filter {
+                    negated: false,
+                    condition: text(Field::Title, "re: stacks", false),
+                },
+            ]
+        );
+        assert!(Query::parse("re: stacks").is_err());
+    }
+
+    #[test]
+    fn parses_terms() {
+        assert_eq!(
+            filters("-genre:christmas artist:=Aphex year:1995..1999 duration:<4:30 track:>=3"),
+            [
+                Filter {
+                    negated: true,
+                    condition: text(Field::Genre, "christmas", false),
+                },
+                Filter {
+                    negated: false,
+                    condition: text(Field::Artist, "aphex", true),
+                },
+                Filter {
+                    negated: false,
+                    condition: Condition::Number {
+                        field: Field::Year,
+                        compare: Compare::Range(1995, 1999),
+                    },
+                },
+                Filter {
+                    negated: false,
+                    condition: Condition::Number {
+                        field: Field::Duration,
+                        compare: Compare::Lt(270_000),
+                    },
+                },
+                Filter {
+                    negated: false,
+                    condition: Condition::Number {
+                        field: Field::Track,
+                        compare: Compare::Ge(3),
+                    },
+                },
+            ]
+        );
+        assert!(Query::parse("colour:red").is_err());
+        assert!(Query::parse("year:soon").is_err());
+        assert!(Query::parse("sort:colour").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        let secs = |s| parse_duration(s).map(|d| d.as_secs());
+        assert_eq!(secs("90"), Some(90));
+        assert_eq!(secs("4m30s"), Some(270));
+        assert_eq!(secs("4:30"), Some(270));
+        assert_eq!(secs("1:02:03"), Some(3723));
+        assert_eq!(secs("2w"), Some(14 * 86400));
+        assert_eq!(secs("5x"), None);
+        assert_eq!(secs("5m3"), None);
+        assert_eq!(secs("1e300"), None);
+    }
+
+    #[test]
+    fn filters_and_sorts() {
+        let mut playlist = Playlist {
+            tracks: vec![
+                song("Boards of Canada", "Music Is Math", Some(2002), 321),
+                song("Aphex Twin", "Xtal", Some(1992), 291),
+                song("Boards of Canada", "Roygbiv", Some(1998), 151),
+                song("Boards of Canada", "Dawn Chorus", None, 233),
+            ],
+        };
+        let query: Query = "canada -roygbiv sort:-year".parse().unwrap();
+        playlist.filter(&query);
+        let titles: Vec<_> = playlist.tracks.iter().filter_map(Song::title).collect();
+        // Songs without a year sort last, even when descending
+        assert_eq!(titles, ["Music Is Math", "Dawn Chorus"]);
+        assert_eq!(query.to_string(), "canada -roygbiv sort:-year");
+    }
+}
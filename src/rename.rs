@@ -0,0 +1,181 @@
+use crate::errors::MelodyErrors;
+use crate::history::PlayHistory;
+use crate::library::Library;
+use crate::organize::{ImportMode, transfer};
+use crate::pathtemplate::PathTemplate;
+use crate::song::{Playlist, Song};
+use crate::store::PlaylistStore;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name used when no template is given, like `1-02 Title.flac`
+pub const DEFAULT_RENAME_TEMPLATE: &str = "[{disc}-]{track:02} {title}.{ext}";
+
+/// Options for `Playlist::rename_from_tags`
+#[derive(Clone, Debug)]
+pub struct RenameOptions {
+    /// New name of a file, relative to the directory it is in.
+    /// A `/` in it puts the file in a subdirectory
+    pub template: PathTemplate,
+    /// Only plan the renames, the outcomes say what would happen
+    pub dry_run: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            template: PathTemplate::parse(DEFAULT_RENAME_TEMPLATE)
+                .expect("The default rename template is valid"),
+            dry_run: false,
+        }
+    }
+}
+
+/// What happened to a single file during a rename
+#[derive(Debug)]
+pub enum RenameOutcome {
+    /// Renamed to this path
+    Renamed(PathBuf),
+    /// Already named the way the template says
+    Unchanged,
+    /// Another file is at this path already, or another song would be renamed to it,
+    /// the file was left alone
+    Conflict(PathBuf),
+    /// Holds several songs, like the tracks of a CUE sheet, so it has no single name
+    Skipped,
+    Failed(MelodyErrors),
+}
+
+/// Outcome of `Playlist::rename_from_tags`, one entry per file
+#[derive(Debug, Default)]
+pub struct RenameReport {
+    /// Files and what happened to them, sorted by path
+    pub files: Vec<(PathBuf, RenameOutcome)>,
+}
+
+impl RenameReport {
+    /// Files that were renamed, with their new paths
+    pub fn renamed(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                RenameOutcome::Renamed(to) => Some((from.as_path(), to.as_path())),
+                _ => None,
+            })
+    }
+    /// Files left alone because of a conflict, with the path they would have had
+    pub fn conflicts(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                RenameOutcome::Conflict(to) => Some((from.as_path(), to.as_path())),
+                _ => None,
+            })
+    }
+    /// Errors of the files that failed
+    pub fn failed(&self) -> impl Iterator<Item = &MelodyErrors> {
+        self.files.iter().filter_map(|(_, outcome)| match outcome {
+            RenameOutcome::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+    /// Old and new path of every renamed file
+    pub fn moves(&self) -> BTreeMap<PathBuf, PathBuf> {
+        self.renamed()
+            .map(|(from, to)| (from.to_path_buf(), to.to_path_buf()))
+            .collect()
+    }
+    /// Points the library index, the play history and the saved playlists
+    /// at the new paths of the renamed files, and saves them.
+    /// Returns the names of the playlists that changed
+    pub fn apply(
+        &self,
+        library: &mut Library,
+        history: &mut PlayHistory,
+        store: &PlaylistStore,
+    ) -> Result<Vec<String>, MelodyErrors> {
+        let moves = self.moves();
+        if moves.is_empty() {
+            return Ok(Vec::new());
+        }
+        for (from, to) in &moves {
+            library.move_file(from, to);
+            history.move_file(from, to);
+        }
+        library.save()?;
+        history.save()?;
+        store.move_files(&moves)
+    }
+}
+
+/// Checks if `a` and `b` are the same file, like a rename that only changes case
+/// on a filesystem that ignores it
+fn same_file(a: &Path, b: &Path) -> bool {
+    matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Renames `song` to `dest`, creating the directories it needs
+fn rename_file(song: &Song, dest: &Path) -> Result<(), MelodyErrors> {
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).map_err(|e| MelodyErrors::from_io(e, dir))?;
+    }
+    transfer(song.file(), dest, ImportMode::Move)
+}
+
+impl Playlist {
+    /// Renames the files of the songs where they are, naming them after their tags
+    /// like `options.template` says. Unlike `organize_song` nothing is copied into a library.
+    /// Files are never overwritten: if another file has the new name, or two songs would
+    /// get the same one, the file is left alone and reported as a conflict.
+    /// Saved playlists, the library index and the play history still have the old paths,
+    /// see `RenameReport::apply`
+    /// # Example
+    /// ```no_run
+    /// let playlist = melody::Playlist::from_dir(std::path::PathBuf::from("/home/user/Music")).unwrap();
+    /// let options = melody::RenameOptions {
+    ///     dry_run: true,
+    ///     ..Default::default()
+    /// };
+    /// for (from, to) in playlist.rename_from_tags(&options).renamed() {
+    ///     println!("{} -> {}", from.display(), to.display());
+    /// }
+    /// ```
+    pub fn rename_from_tags(&self, options: &RenameOptions) -> RenameReport {
+        let mut files: BTreeMap<&Path, Vec<&Song>> = BTreeMap::new();
+        for song in &self.tracks {
+            files.entry(song.file()).or_default().push(song);
+        }
+        let mut taken = BTreeSet::new();
+        let mut report = RenameReport::default();
+        for (file, songs) in files {
+            let outcome = match songs.as_slice() {
+                [song] if song.start().is_none() => {
+                    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+                    let dest = dir.join(options.template.render(song));
+                    if dest == file {
+                        RenameOutcome::Unchanged
+                    } else if taken.contains(&dest)
+                        || (dest.symlink_metadata().is_ok() && !same_file(file, &dest))
+                    {
+                        RenameOutcome::Conflict(dest)
+                    } else if options.dry_run {
+                        taken.insert(dest.clone());
+                        RenameOutcome::Renamed(dest)
+                    } else {
+                        match rename_file(song, &dest) {
+                            Ok(()) => {
+                                taken.insert(dest.clone());
+                                RenameOutcome::Renamed(dest)
+                            }
+                            Err(e) => RenameOutcome::Failed(e),
+                        }
+                    }
+                }
+                _ => RenameOutcome::Skipped,
+            };
+            report.files.push((file.to_path_buf(), outcome));
+        }
+        report
+    }
+}
@@ -0,0 +1,497 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::musicplayer::{MusicPlayer, MusicPlayerStatus};
+use crate::song::{Playlist, Song, UNKNOWN_ALBUM, UNKNOWN_ARTIST, UNKNOWN_TITLE};
+use crate::utils::fmt_duration;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+use tabwriter::TabWriter;
+
+/// A field of a song that can be shown
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Column {
+    Track,
+    Artist,
+    Album,
+    Title,
+    Genre,
+    Year,
+    Duration,
+    /// Audio bitrate in kbps
+    Bitrate,
+    Path,
+    /// How long the song has been playing
+    Elapsed,
+}
+
+/// A value of a column, kept typed so JSON gets numbers
+enum Value {
+    Text(String),
+    Number(u64),
+    Time(Duration),
+    Missing,
+}
+
+impl Column {
+    /// Columns shown when none are picked
+    pub const DEFAULT: &'static [Column] = &[
+        Column::Artist,
+        Column::Album,
+        Column::Title,
+        Column::Duration,
+    ];
+    /// Every column, in the order of the table
+    pub const ALL: &'static [Column] = &[
+        Column::Track,
+        Column::Artist,
+        Column::Album,
+        Column::Title,
+        Column::Genre,
+        Column::Year,
+        Column::Duration,
+        Column::Bitrate,
+        Column::Path,
+        Column::Elapsed,
+    ];
+    /// Name of the column in JSON, CSV and templates
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Track => "track",
+            Column::Artist => "artist",
+            Column::Album => "album",
+            Column::Title => "title",
+            Column::Genre => "genre",
+            Column::Year => "year",
+            Column::Duration => "duration",
+            Column::Bitrate => "bitrate",
+            Column::Path => "path",
+            Column::Elapsed => "elapsed",
+        }
+    }
+    fn header(self) -> &'static str {
+        match self {
+            Column::Track => "Track",
+            Column::Artist => "Artist",
+            Column::Album => "Album",
+            Column::Title => "Title",
+            Column::Genre => "Genre",
+            Column::Year => "Year",
+            Column::Duration => "Duration",
+            Column::Bitrate => "Bitrate",
+            Column::Path => "Path",
+            Column::Elapsed => "Elapsed",
+        }
+    }
+    /// Parse a comma separated list of columns, like `track,title,year`
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, MelodyErrors> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+    fn value(self, song: &Song) -> Value {
+        let text = |s: Option<&str>| s.map_or(Value::Missing, |s| Value::Text(s.to_string()));
+        let number = |n: Option<u32>| n.map_or(Value::Missing, |n| Value::Number(n.into()));
+        match self {
+            Column::Track => number(song.track()),
+            Column::Artist => text(song.artist()),
+            Column::Album => text(song.album()),
+            Column::Title => text(song.title()),
+            Column::Genre => text(song.genre()),
+            Column::Year => number(song.year()),
+            Column::Duration => Value::Time(song.duration()),
+            Column::Bitrate => number(song.bitrate()),
+            Column::Path => Value::Text(song.file().to_string_lossy().into_owned()),
+            Column::Elapsed => Value::Time(song.elapsed()),
+        }
+    }
+    /// Value for people, as shown in tables and templates
+    fn human(self, song: &Song) -> String {
+        match self.value(song) {
+            Value::Text(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Time(d) => fmt_duration(&d),
+            Value::Missing => match self {
+                // Same placeholders as `Song`'s display
+                Column::Artist => UNKNOWN_ARTIST.to_string(),
+                Column::Album => UNKNOWN_ALBUM.to_string(),
+                Column::Title => UNKNOWN_TITLE.to_string(),
+                _ => String::new(),
+            },
+        }
+    }
+    /// Value for scripts, durations are in seconds and missing values are empty
+    fn plain(self, song: &Song) -> String {
+        match self.value(song) {
+            Value::Text(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Time(d) => d.as_secs_f64().to_string(),
+            Value::Missing => String::new(),
+        }
+    }
+    fn json(self, song: &Song) -> String {
+        match self.value(song) {
+            Value::Text(s) => json_string(&s),
+            Value::Number(n) => n.to_string(),
+            Value::Time(d) => d.as_secs_f64().to_string(),
+            Value::Missing => "null".to_string(),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Column::ALL
+            .iter()
+            .copied()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                MelodyErrors::new(
+                    MelodyErrorsKind::InvalidTemplate,
+                    &format!("Unknown column {:?}", s),
+                    None,
+                )
+            })
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// A piece of a template
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Text(String),
+    /// A column padded to a width, with zeros if the flag is set
+    Column(Column, usize, bool),
+    /// `{status}`, only filled in for the player
+    Status,
+}
+
+/// A line template like `{track}. {artist} - {title} ({duration})`
+/// Placeholders are column names, `{{` and `}}` are literal braces.
+/// A width pads the value, `{track:02}` with zeros and `{title:20}` with spaces.
+/// Missing values are left empty
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, MelodyErrors> {
+        let invalid = |msg: &str| MelodyErrors::new(MelodyErrorsKind::InvalidTemplate, msg, None);
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid("Unterminated `{` in template")),
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    let (name, width) = name.split_once(':').unwrap_or((&name, ""));
+                    if name == "status" {
+                        parts.push(Part::Status);
+                        continue;
+                    }
+                    let zeros = width.starts_with('0');
+                    let width = match width {
+                        "" => 0,
+                        w => w.parse().map_err(|_| {
+                            invalid(&format!("Invalid width for {}: {:?}", name, w))
+                        })?,
+                    };
+                    parts.push(Part::Column(name.parse()?, width, zeros));
+                }
+                '}' => return Err(invalid("Unmatched `}` in template")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+    fn render(&self, song: &Song, status: &str) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Column(column, width, zeros) => match column.value(song) {
+                    Value::Missing => String::new(),
+                    _ if *zeros => format!("{:0>width$}", column.human(song), width = width),
+                    _ => format!("{:width$}", column.human(song), width = width),
+                },
+                Part::Status => status.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// How songs are written out
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Aligned table, for people
+    #[default]
+    Table,
+    /// An array of objects keyed by column name
+    Json,
+    /// A header line with the column names, then a line per song
+    Csv,
+    /// A line per song
+    Template(Template),
+}
+
+impl FromStr for OutputFormat {
+    type Err = MelodyErrors;
+    /// Parses `table`, `json` or `csv`, see `Template::parse` for templates
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidTemplate,
+                &format!("Unknown output format {:?}", s),
+                None,
+            )),
+        }
+    }
+}
+
+fn status_name(status: &MusicPlayerStatus) -> &'static str {
+    match status {
+        MusicPlayerStatus::NowPlaying(_) => "playing",
+        MusicPlayerStatus::Paused(_) => "paused",
+        MusicPlayerStatus::Stopped(_) => "stopped",
+    }
+}
+
+/// Renders songs, playlists and the player in an `OutputFormat`
+/// # Example
+/// ```
+/// use melody::{Column, OutputFormat, Playlist, Renderer};
+/// let renderer = Renderer::new(OutputFormat::Json)
+///     .with_columns(Column::parse_list("track,title,year,path")?);
+/// println!("{}", Playlist::from(Vec::new()).render(&renderer));
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Renderer {
+    format: OutputFormat,
+    columns: Vec<Column>,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new(OutputFormat::Table)
+    }
+}
+
+impl Renderer {
+    /// Render with the default columns
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            columns: Column::DEFAULT.to_vec(),
+        }
+    }
+    /// Pick the columns shown, an empty list keeps the default ones.
+    /// Templates pick their own
+    pub fn with_columns(mut self, columns: Vec<Column>) -> Self {
+        if !columns.is_empty() {
+            self.columns = columns;
+        }
+        self
+    }
+    pub fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+    fn json_object(&self, song: &Song) -> String {
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| format!("{}:{}", json_string(c.name()), c.json(song)))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+    fn table(&self, songs: &[Song]) -> String {
+        let row = |cells: Vec<String>| format!("|\t{}\t|", cells.join("\t|\t"));
+        let mut lines = vec![row(self
+            .columns
+            .iter()
+            .map(|c| c.header().into())
+            .collect())];
+        for song in songs {
+            // Tabs would break the alignment
+            let cells = self
+                .columns
+                .iter()
+                .map(|c| c.human(song).replace(['\t', '\n'], " "))
+                .collect();
+            lines.push(row(cells));
+        }
+        let mut tw = TabWriter::new(Vec::new());
+        // Writing to a Vec cant fail
+        let _ = tw.write_all(lines.join("\n").as_bytes());
+        let _ = tw.flush();
+        match tw.into_inner() {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => lines.join("\n"),
+        }
+    }
+    /// Render a list of songs
+    pub fn songs(&self, songs: &[Song]) -> String {
+        match &self.format {
+            OutputFormat::Table => self.table(songs),
+            OutputFormat::Json => {
+                let objects: Vec<String> = songs.iter().map(|s| self.json_object(s)).collect();
+                format!("[{}]", objects.join(","))
+            }
+            OutputFormat::Csv => {
+                let mut lines = vec![
+                    self.columns
+                        .iter()
+                        .map(|c| c.name())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ];
+                for song in songs {
+                    let cells: Vec<String> = self
+                        .columns
+                        .iter()
+                        .map(|c| csv_field(&c.plain(song)))
+                        .collect();
+                    lines.push(cells.join(","));
+                }
+                lines.join("\n")
+            }
+            OutputFormat::Template(template) => songs
+                .iter()
+                .map(|s| template.render(s, ""))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+    /// Render the state of a player, the current song followed by the queue
+    /// Templates only render the current song, nothing while stopped,
+    /// `{status}` is `playing` or `paused`
+    pub fn player(&self, status: &MusicPlayerStatus, queue: &[Song]) -> String {
+        let state = status_name(status);
+        let current = match status {
+            MusicPlayerStatus::NowPlaying(song) | MusicPlayerStatus::Paused(song) => Some(song),
+            MusicPlayerStatus::Stopped(_) => None,
+        };
+        match &self.format {
+            OutputFormat::Table => {
+                let now = match status {
+                    MusicPlayerStatus::Paused(song) => format!("[paused] {}\n", song),
+                    MusicPlayerStatus::NowPlaying(song) => format!("{}\n", song),
+                    MusicPlayerStatus::Stopped(_) => String::new(),
+                };
+                format!("{}{}", now, self.table(queue))
+            }
+            OutputFormat::Json => format!(
+                "{{\"status\":{},\"current\":{},\"queue\":{}}}",
+                json_string(state),
+                current.map_or("null".to_string(), |s| self.json_object(s)),
+                self.songs(queue)
+            ),
+            OutputFormat::Csv => {
+                // Rows get a status column, the queued songs are `queued`
+                let mut lines = vec![format!(
+                    "status,{}",
+                    self.columns
+                        .iter()
+                        .map(|c| c.name())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )];
+                let rows = current
+                    .map(|s| (state, s))
+                    .into_iter()
+                    .chain(queue.iter().map(|s| ("queued", s)));
+                for (state, song) in rows {
+                    let cells: Vec<String> = self
+                        .columns
+                        .iter()
+                        .map(|c| csv_field(&c.plain(song)))
+                        .collect();
+                    lines.push(format!("{},{}", state, cells.join(",")));
+                }
+                lines.join("\n")
+            }
+            OutputFormat::Template(template) => current
+                .map(|song| template.render(song, state))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Playlist {
+    /// Render the songs of the playlist
+    pub fn render(&self, renderer: &Renderer) -> String {
+        renderer.songs(&self.tracks)
+    }
+}
+
+impl MusicPlayer {
+    /// Render the current song and the queue
+    pub fn render(&self, renderer: &Renderer) -> String {
+        renderer.player(&self.status(), self.queue())
+    }
+}
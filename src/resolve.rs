@@ -0,0 +1,288 @@
+use crate::duplicates::DuplicateGroup;
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::journal::backup_path;
+use crate::organize::{ImportMode, numbered, quality, transfer};
+use crate::song::Song;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// What makes one copy of a duplicate song better than another
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum KeepPolicy {
+    /// Lossless formats over lossy ones
+    Lossless,
+    /// The higher bitrate
+    Bitrate,
+    /// More of the tags set, like album artist, track and year
+    Tags,
+    /// Songs inside this directory
+    Directory(PathBuf),
+}
+
+impl KeepPolicy {
+    /// Lossless first, then bitrate, then tags
+    pub fn defaults() -> Vec<KeepPolicy> {
+        vec![KeepPolicy::Lossless, KeepPolicy::Bitrate, KeepPolicy::Tags]
+    }
+    /// Higher is better
+    fn score(&self, song: &Song) -> u32 {
+        match self {
+            KeepPolicy::Lossless => quality(song).0 as u32,
+            KeepPolicy::Bitrate => quality(song).1,
+            KeepPolicy::Tags => [
+                song.artist().is_some(),
+                song.album().is_some(),
+                song.title().is_some(),
+                song.album_artist().is_some(),
+                song.genre().is_some(),
+                song.track().is_some(),
+                song.disc().is_some(),
+                song.year().is_some(),
+            ]
+            .iter()
+            .filter(|set| **set)
+            .count() as u32,
+            KeepPolicy::Directory(dir) => song.file().starts_with(dir) as u32,
+        }
+    }
+}
+
+/// Parses `lossless`, `bitrate`, `tags` or `dir:<path>`
+impl FromStr for KeepPolicy {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(dir) = s.strip_prefix("dir:") {
+            return Ok(KeepPolicy::Directory(PathBuf::from(dir)));
+        }
+        match s.to_lowercase().as_str() {
+            "lossless" => Ok(KeepPolicy::Lossless),
+            "bitrate" => Ok(KeepPolicy::Bitrate),
+            "tags" => Ok(KeepPolicy::Tags),
+            other => Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidOption,
+                &format!(
+                    "Unknown keep policy {:?}, expected lossless, bitrate, tags or dir:<path>",
+                    other
+                ),
+                None,
+            )),
+        }
+    }
+}
+
+/// What happens to the copies that arent kept
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ResolveAction {
+    Delete,
+    /// Move them into this directory, under their full path so nothing clashes
+    Quarantine(PathBuf),
+    /// Replace them with a symbolic link to the kept copy
+    Symlink,
+}
+
+/// Options for `resolve_duplicates`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolveOptions {
+    /// Tried in order, the first policy that tells the copies apart picks the keeper.
+    /// If none do, real files beat links and then the shortest path wins
+    pub keep: Vec<KeepPolicy>,
+    pub action: ResolveAction,
+    /// Only pick the keepers, the outcomes say what would happen
+    pub dry_run: bool,
+}
+
+impl ResolveOptions {
+    /// The default keep policies with `action`
+    pub fn new(action: ResolveAction) -> Self {
+        Self {
+            keep: KeepPolicy::defaults(),
+            action,
+            dry_run: false,
+        }
+    }
+}
+
+/// What happened to a copy that wasnt kept
+#[derive(Debug)]
+pub enum ResolveOutcome {
+    Deleted,
+    /// Moved to this path in the quarantine directory
+    Quarantined(PathBuf),
+    /// Replaced with a link to the kept copy
+    Linked,
+    /// Already a link to the kept copy, it was left alone
+    AlreadyLinked,
+    Failed(MelodyErrors),
+}
+
+/// A duplicate group after resolving it
+#[derive(Debug)]
+pub struct Resolution {
+    /// The copy that was kept
+    pub keep: PathBuf,
+    /// The other copies and what happened to them
+    pub files: Vec<(PathBuf, ResolveOutcome)>,
+}
+
+/// Outcome of `resolve_duplicates`, one resolution per group
+#[derive(Debug, Default)]
+pub struct ResolveReport {
+    pub groups: Vec<Resolution>,
+}
+
+impl ResolveReport {
+    /// Number of copies that were deleted, quarantined or linked
+    pub fn resolved(&self) -> usize {
+        self.outcomes()
+            .filter(|o| {
+                matches!(
+                    o,
+                    ResolveOutcome::Deleted
+                        | ResolveOutcome::Quarantined(_)
+                        | ResolveOutcome::Linked
+                )
+            })
+            .count()
+    }
+    /// Errors of the copies that failed
+    pub fn failed(&self) -> impl Iterator<Item = &MelodyErrors> {
+        self.outcomes().filter_map(|o| match o {
+            ResolveOutcome::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+    fn outcomes(&self) -> impl Iterator<Item = &ResolveOutcome> {
+        self.groups
+            .iter()
+            .flat_map(|g| g.files.iter().map(|(_, o)| o))
+    }
+}
+
+fn is_link(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|m| m.is_symlink())
+}
+
+/// Index of the copy to keep in `group`
+pub fn pick_keeper(group: &DuplicateGroup, keep: &[KeepPolicy]) -> usize {
+    group
+        .songs
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, song)| {
+            let scores: Vec<u32> = keep.iter().map(|p| p.score(song)).collect();
+            let path_len = song.file().as_os_str().len();
+            (
+                scores,
+                !is_link(song.file()),
+                Reverse(path_len),
+                Reverse(*i),
+            )
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+/// Where `file` goes in the quarantine directory, its path without the root
+fn quarantine_path(dir: &Path, file: &Path) -> PathBuf {
+    let relative: PathBuf = file
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let dest = dir.join(relative);
+    let mut free = dest.clone();
+    let mut n = 0;
+    while free.symlink_metadata().is_ok() {
+        n += 1;
+        free = numbered(&dest, n);
+    }
+    free
+}
+
+/// Replaces `file` with a link to `keep`, the file is put back if linking fails
+fn replace_with_link(file: &Path, keep: &Path) -> Result<(), MelodyErrors> {
+    let backup = backup_path(file);
+    fs::rename(file, &backup).map_err(|e| MelodyErrors::from_io(e, file))?;
+    if let Err(e) = transfer(keep, file, ImportMode::Symlink) {
+        if let Err(e) = fs::rename(&backup, file) {
+            log::warn!("Failed to restore {:?} from {:?}: {}", file, backup, e);
+        }
+        return Err(e);
+    }
+    fs::remove_file(&backup).map_err(|e| MelodyErrors::from_io(e, &backup))
+}
+
+fn resolve_file(file: &Path, keep: &Path, options: &ResolveOptions) -> ResolveOutcome {
+    // A link to the keeper, or the keeper reached through a linked directory
+    if let (Ok(a), Ok(b)) = (file.canonicalize(), keep.canonicalize())
+        && a == b
+    {
+        return ResolveOutcome::AlreadyLinked;
+    }
+    let (outcome, result) = match &options.action {
+        ResolveAction::Delete => (
+            ResolveOutcome::Deleted,
+            (!options.dry_run)
+                .then(|| fs::remove_file(file).map_err(|e| MelodyErrors::from_io(e, file))),
+        ),
+        ResolveAction::Quarantine(dir) => {
+            let dest = quarantine_path(dir, file);
+            let result = (!options.dry_run).then(|| {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).map_err(|e| MelodyErrors::from_io(e, parent))?;
+                }
+                transfer(file, &dest, ImportMode::Move)
+            });
+            (ResolveOutcome::Quarantined(dest), result)
+        }
+        ResolveAction::Symlink => (
+            ResolveOutcome::Linked,
+            (!options.dry_run).then(|| {
+                let target = keep
+                    .canonicalize()
+                    .map_err(|e| MelodyErrors::from_io(e, keep))?;
+                replace_with_link(file, &target)
+            }),
+        ),
+    };
+    match result {
+        Some(Err(e)) => ResolveOutcome::Failed(e),
+        _ => outcome,
+    }
+}
+
+/// Keeps the best copy of every group and deletes, quarantines or links the others,
+/// groups of less than two songs are left out
+/// # Example
+/// ```no_run
+/// use melody::{ResolveAction, ResolveOptions};
+/// let groups = melody::find_duplicates(std::path::Path::new("/home/user/Music"));
+/// let options = ResolveOptions {
+///     dry_run: true,
+///     ..ResolveOptions::new(ResolveAction::Delete)
+/// };
+/// for group in melody::resolve_duplicates(&groups, &options).groups {
+///     println!("Keeping {:?}", group.keep);
+/// }
+/// ```
+pub fn resolve_duplicates(groups: &[DuplicateGroup], options: &ResolveOptions) -> ResolveReport {
+    let mut report = ResolveReport::default();
+    // Groups are public, a hand made one might have nothing to resolve
+    for group in groups.iter().filter(|g| g.songs.len() >= 2) {
+        let keeper = pick_keeper(group, &options.keep);
+        let keep = group.songs[keeper].file().to_path_buf();
+        let files = group
+            .songs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != keeper)
+            .map(|(_, song)| {
+                let file = song.file().to_path_buf();
+                let outcome = resolve_file(&file, &keep, options);
+                (file, outcome)
+            })
+            .collect();
+        report.groups.push(Resolution { keep, files });
+    }
+    report
+}
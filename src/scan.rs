@@ -0,0 +1,284 @@
+use crate::cue::load_tracks;
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::{Playlist, Song};
+use crate::utils::supported_song;
+use crate::walk::{ScanOptions, walk_files};
+use lofty::error::ErrorKind as LoftyErrorKind;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+/// Progress of a metadata scan
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ScanProgress {
+    /// Files found so far that have to be read
+    pub seen: usize,
+    /// Files read successfully
+    pub loaded: usize,
+    /// Files that failed to load
+    pub failed: usize,
+}
+
+impl ScanProgress {
+    /// Files that are done, successfully or not
+    pub fn done(&self) -> usize {
+        self.loaded + self.failed
+    }
+}
+
+/// Why a file was left out of a scan
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum SkipReason {
+    /// The file isnt in a supported audio format (judging by content and extension)
+    UnsupportedExtension,
+    /// The file has no tags, or they couldnt be parsed
+    UnreadableTags,
+    /// Not allowed to read the file or directory
+    PermissionDenied,
+    /// The file isnt valid audio (unknown or corrupt format)
+    DecodeError,
+    /// A symbolic link points to one of its own parents
+    SymlinkLoop,
+    /// Shorter than `ScanOptions::min_duration`
+    TooShort,
+    /// Any other I/O error
+    Io,
+}
+
+impl SkipReason {
+    /// Classify an error returned while scanning
+    pub fn of(error: &MelodyErrors) -> Self {
+        use MelodyErrorsKind::*;
+        match error.kind() {
+            Io(IoErrorKind::PermissionDenied) => SkipReason::PermissionDenied,
+            Io(_) => SkipReason::Io,
+            UnsupportedFileType | CanNotReadFileEXT | UnkownFileType => {
+                SkipReason::UnsupportedExtension
+            }
+            FailedToReadTag => SkipReason::UnreadableTags,
+            SymlinkLoop => SkipReason::SymlinkLoop,
+            TooShort => SkipReason::TooShort,
+            MetaDataError(e) => match e.kind() {
+                LoftyErrorKind::Io(io) if io.kind() == IoErrorKind::PermissionDenied => {
+                    SkipReason::PermissionDenied
+                }
+                LoftyErrorKind::TextDecode(_)
+                | LoftyErrorKind::BadTimestamp(_)
+                | LoftyErrorKind::Id3v2(_)
+                | LoftyErrorKind::FakeTag
+                | LoftyErrorKind::UnsupportedTag
+                | LoftyErrorKind::StringFromUtf8(_)
+                | LoftyErrorKind::StrFromUtf8(_) => SkipReason::UnreadableTags,
+                _ => SkipReason::DecodeError,
+            },
+            _ => SkipReason::DecodeError,
+        }
+    }
+}
+
+/// A file left out of a scan
+#[derive(Debug)]
+pub struct Skipped {
+    pub reason: SkipReason,
+    /// What went wrong, `error.file()` is the skipped file
+    pub error: MelodyErrors,
+}
+
+/// Outcome of `Playlist::scan_dir`
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Number of files that were loaded
+    pub loaded: usize,
+    /// Files that were left out, in no particular order
+    pub skipped: Vec<Skipped>,
+}
+
+impl ScanReport {
+    /// Record a skipped file
+    fn skip(&mut self, error: MelodyErrors) {
+        self.skipped.push(Skipped {
+            reason: SkipReason::of(&error),
+            error,
+        })
+    }
+    /// Errors of the files skipped for `reason`
+    pub fn skipped_for(&self, reason: SkipReason) -> impl Iterator<Item = &MelodyErrors> {
+        self.skipped
+            .iter()
+            .filter(move |s| s.reason == reason)
+            .map(|s| &s.error)
+    }
+}
+
+/// Result of loading a single file
+pub(crate) type Loaded = (PathBuf, Result<Vec<Song>, MelodyErrors>);
+
+/// Number of workers used to read tags
+pub(crate) fn worker_count() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .clamp(1, 16)
+}
+
+/// Runs `work` on every item on a pool of worker threads and hands the results to `done`
+/// on the calling thread, in the order they finished, along with the number of items
+/// taken so far. `items` is consumed on the calling thread while the workers run,
+/// so it can be a directory walk
+pub(crate) fn parallel_map<T: Send, R: Send>(
+    items: impl IntoIterator<Item = T>,
+    work: impl Fn(T) -> R + Sync,
+    mut done: impl FnMut(R, usize),
+) {
+    let items = items.into_iter();
+    let workers = worker_count().min(items.size_hint().1.unwrap_or(usize::MAX));
+    let (job_tx, job_rx) = mpsc::channel::<T>();
+    let (result_tx, result_rx) = mpsc::channel::<R>();
+    let job_rx = Mutex::new(job_rx);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let (jobs, results, work) = (&job_rx, result_tx.clone(), &work);
+            scope.spawn(move || {
+                loop {
+                    // Only hold the lock while waiting for a job, not while working on it
+                    let job = match jobs.lock() {
+                        Ok(jobs) => jobs.recv(),
+                        Err(_) => return,
+                    };
+                    let Ok(item) = job else {
+                        return;
+                    };
+                    if results.send(work(item)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        // Workers hold the remaining senders, the results end once they all exit
+        drop(result_tx);
+        let mut taken = 0;
+        for item in items {
+            if job_tx.send(item).is_err() {
+                break;
+            }
+            taken += 1;
+            while let Ok(result) = result_rx.try_recv() {
+                done(result, taken);
+            }
+        }
+        drop(job_tx);
+        for result in result_rx {
+            done(result, taken);
+        }
+    });
+}
+
+/// Loads the songs of every file in `paths` using a pool of worker threads.
+/// `paths` is consumed on the calling thread, so a directory walk can run
+/// while the workers read tags. `progress` is called whenever a file is done.
+/// Results are returned in the order they finished
+pub(crate) fn load_files(
+    paths: impl Iterator<Item = PathBuf>,
+    progress: &mut dyn FnMut(&ScanProgress),
+) -> Vec<Loaded> {
+    let mut status = ScanProgress::default();
+    let mut results = Vec::new();
+    parallel_map(
+        paths,
+        |path: PathBuf| {
+            let songs = load_tracks(path.clone());
+            (path, songs)
+        },
+        |loaded: Loaded, seen| {
+            status.seen = seen;
+            match loaded.1 {
+                Ok(_) => status.loaded += 1,
+                Err(_) => status.failed += 1,
+            }
+            results.push(loaded);
+            progress(&status);
+        },
+    );
+    results
+}
+
+impl Playlist {
+    /// Create a playlist from a file or directory, like `from_dir`,
+    /// along with a report of every file that was skipped and why
+    pub fn scan_dir(path: PathBuf) -> Result<(Self, ScanReport), MelodyErrors> {
+        Self::scan_dir_with(path, &ScanOptions::default(), |_| ())
+    }
+    /// Same as `scan_dir`, `progress` is called as files are found and loaded
+    pub fn scan_dir_with_progress(
+        path: PathBuf,
+        progress: impl FnMut(&ScanProgress),
+    ) -> Result<(Self, ScanReport), MelodyErrors> {
+        Self::scan_dir_with(path, &ScanOptions::default(), progress)
+    }
+    /// Same as `scan_dir`, walking the directory according to `options`.
+    /// `progress` is called as files are found and loaded
+    pub fn scan_dir_with(
+        path: PathBuf,
+        options: &ScanOptions,
+        mut progress: impl FnMut(&ScanProgress),
+    ) -> Result<(Self, ScanReport), MelodyErrors> {
+        if !path.exists() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::PathDoesNotExist,
+                "Path does not exist",
+                Some(&path),
+            ));
+        }
+        let mut report = ScanReport::default();
+        let files = walk_files(&path, options)?.filter_map(|f| match f {
+            Ok(f) if supported_song(&f) => Some(f),
+            Ok(f) => {
+                report.skip(MelodyErrors::new(
+                    MelodyErrorsKind::UnsupportedFileType,
+                    "Unsupported file format",
+                    Some(&f),
+                ));
+                None
+            }
+            Err(e) => {
+                report.skip(e);
+                None
+            }
+        });
+        let loaded = load_files(files, &mut progress);
+        let mut tracks = Vec::with_capacity(loaded.len());
+        for (path, songs) in loaded {
+            let songs = match songs {
+                Ok(songs) => songs,
+                Err(e) => {
+                    report.skip(e);
+                    continue;
+                }
+            };
+            let (keep, short): (Vec<Song>, Vec<Song>) =
+                songs.into_iter().partition(|s| options.keeps(s));
+            if keep.is_empty() && !short.is_empty() {
+                report.skip(MelodyErrors::new(
+                    MelodyErrorsKind::TooShort,
+                    "Shorter than the minimum duration",
+                    Some(&path),
+                ));
+            } else {
+                report.loaded += 1;
+                tracks.extend(keep)
+            }
+        }
+        // Workers finish in any order
+        tracks.sort_by(|a, b| (&a.file, a.start).cmp(&(&b.file, b.start)));
+        tracks.dedup();
+        Ok((Self { tracks }, report))
+    }
+    /// Create a playlist from a directory like `from_dir`, walking it according to `options`
+    pub fn from_dir_with_options(path: PathBuf, options: &ScanOptions) -> Option<Self> {
+        Self::scan_dir_with(path, options, |_| ())
+            .ok()
+            .map(|(playlist, _)| playlist)
+    }
+}
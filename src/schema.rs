@@ -0,0 +1,275 @@
+//! Serde support, enabled by the `serde` feature
+//!
+//! The wire format is kept apart from the structs through schema types,
+//! so renaming a field in Rust doesnt break stored data.
+//! Durations are written in milliseconds.
+//! Playlists, player statuses and errors are written with a `version` field,
+//! data written by a newer version of Melody is rejected
+//!
+//! A playlist looks like this in JSON:
+//! ```text
+//! {"version":1,"tracks":[{"artist":"Aphex Twin","album":"SAW","title":"Xtal",
+//!   "track":1,"genre":"Ambient","year":1992,"duration_ms":293000,"bitrate":320,
+//!   "file":"/music/xtal.flac","elapsed_ms":0,"start_ms":null}]}
+//! ```
+//! A player status is `{"version":1,"state":"playing","song":{..}}`, where `state`
+//! is `playing`, `paused` or `stopped` and `song` is the last played song when stopped
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::musicplayer::MusicPlayerStatus;
+use crate::song::{Playlist, Song};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Version of the serialized formats, bumped on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+fn check_version<E: serde::de::Error>(version: u32) -> Result<(), E> {
+    if version > SCHEMA_VERSION {
+        Err(E::custom(format!(
+            "unsupported schema version {}, the newest known is {}",
+            version, SCHEMA_VERSION
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+#[derive(Serialize, Deserialize)]
+struct SongSchema {
+    artist: Option<String>,
+    album: Option<String>,
+    #[serde(default)]
+    album_artist: Option<String>,
+    title: Option<String>,
+    track: Option<u32>,
+    #[serde(default)]
+    disc: Option<u32>,
+    genre: Option<String>,
+    year: Option<u32>,
+    #[serde(default)]
+    compilation: bool,
+    duration_ms: u64,
+    bitrate: Option<u32>,
+    file: PathBuf,
+    #[serde(default)]
+    elapsed_ms: u64,
+    #[serde(default)]
+    start_ms: Option<u64>,
+}
+
+impl From<&Song> for SongSchema {
+    fn from(song: &Song) -> Self {
+        Self {
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            album_artist: song.album_artist.clone(),
+            title: song.title.clone(),
+            track: song.track,
+            disc: song.disc,
+            genre: song.genre.clone(),
+            year: song.year,
+            compilation: song.compilation,
+            duration_ms: millis(song.duration),
+            bitrate: song.bitrate,
+            file: song.file.clone(),
+            elapsed_ms: millis(song.elapsed),
+            start_ms: song.start.map(millis),
+        }
+    }
+}
+
+impl From<SongSchema> for Song {
+    fn from(song: SongSchema) -> Self {
+        Self {
+            artist: song.artist,
+            album: song.album,
+            album_artist: song.album_artist,
+            title: song.title,
+            track: song.track,
+            disc: song.disc,
+            genre: song.genre,
+            year: song.year,
+            compilation: song.compilation,
+            duration: Duration::from_millis(song.duration_ms),
+            bitrate: song.bitrate,
+            file: song.file,
+            elapsed: Duration::from_millis(song.elapsed_ms),
+            start: song.start_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+impl Serialize for Song {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SongSchema::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Song {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SongSchema::deserialize(deserializer).map(Song::from)
+    }
+}
+
+#[derive(Serialize)]
+struct PlaylistOut<'a> {
+    version: u32,
+    tracks: &'a [Song],
+}
+
+#[derive(Deserialize)]
+struct PlaylistIn {
+    version: u32,
+    tracks: Vec<Song>,
+}
+
+impl Serialize for Playlist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PlaylistOut {
+            version: SCHEMA_VERSION,
+            tracks: &self.tracks,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Playlist {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let playlist = PlaylistIn::deserialize(deserializer)?;
+        check_version(playlist.version)?;
+        Ok(Playlist {
+            tracks: playlist.tracks,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct StatusOut<'a> {
+    version: u32,
+    state: &'static str,
+    song: Option<&'a Song>,
+}
+
+#[derive(Deserialize)]
+struct StatusIn {
+    version: u32,
+    state: String,
+    song: Option<Song>,
+}
+
+impl Serialize for MusicPlayerStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (state, song) = match self {
+            MusicPlayerStatus::NowPlaying(song) => ("playing", Some(song)),
+            MusicPlayerStatus::Paused(song) => ("paused", Some(song)),
+            MusicPlayerStatus::Stopped(song) => ("stopped", song.as_ref()),
+        };
+        StatusOut {
+            version: SCHEMA_VERSION,
+            state,
+            song,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MusicPlayerStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let StatusIn {
+            version,
+            state,
+            song,
+        } = StatusIn::deserialize(deserializer)?;
+        check_version(version)?;
+        let missing_song = || D::Error::custom(format!("missing song for state {}", state));
+        match state.as_str() {
+            "playing" => Ok(MusicPlayerStatus::NowPlaying(
+                song.ok_or_else(missing_song)?,
+            )),
+            "paused" => Ok(MusicPlayerStatus::Paused(song.ok_or_else(missing_song)?)),
+            "stopped" => Ok(MusicPlayerStatus::Stopped(song)),
+            other => Err(D::Error::unknown_variant(
+                other,
+                &["playing", "paused", "stopped"],
+            )),
+        }
+    }
+}
+
+/// Serializable form of `MelodyErrors`
+/// The underlying tag or io error cant be restored, only its description
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub version: u32,
+    /// Snake case name of the `MelodyErrorsKind`, like `path_does_not_exist`
+    pub kind: String,
+    /// Name of the `std::io::ErrorKind` for `io` errors, like `NotFound`
+    pub io_kind: Option<String>,
+    pub description: String,
+    pub file: Option<PathBuf>,
+}
+
+/// Stable name of an error kind
+fn kind_name(kind: &MelodyErrorsKind) -> &'static str {
+    use MelodyErrorsKind::*;
+    match kind {
+        Io(_) => "io",
+        NotAbsolutePath => "not_absolute_path",
+        PathDoesNotExist => "path_does_not_exist",
+        PathIsNotADir => "path_is_not_a_dir",
+        PathIsNotAFile => "path_is_not_a_file",
+        ChildOfParentRecursion => "child_of_parent_recursion",
+        FailedToFindParent => "failed_to_find_parent",
+        UnsupportedFileType => "unsupported_file_type",
+        FailedToReadTag => "failed_to_read_tag",
+        UnkownFileType => "unknown_file_type",
+        CanNotReadFileEXT => "can_not_read_file_ext",
+        EmptyQueue => "empty_queue",
+        NotPaused => "not_paused",
+        AlreadyPlaying => "already_playing",
+        MissingDuration => "missing_duration",
+        MalformedPlaylist => "malformed_playlist",
+        FailedToGetDataDir => "failed_to_get_data_dir",
+        InvalidPlaylistName => "invalid_playlist_name",
+        PlaylistNotFound => "playlist_not_found",
+        PlaylistExists => "playlist_exists",
+        InvalidGlob => "invalid_glob",
+        SymlinkLoop => "symlink_loop",
+        TooShort => "too_short",
+        FailedToWatch => "failed_to_watch",
+        InvalidQuery => "invalid_query",
+        ReadOnlyPlaylist => "read_only_playlist",
+        InvalidTemplate => "invalid_template",
+        InvalidOption => "invalid_option",
+        FailedToDecode => "failed_to_decode",
+        FailedToEncode => "failed_to_encode",
+        MetaDataError(_) => "metadata",
+    }
+}
+
+impl From<&MelodyErrors> for ErrorRecord {
+    fn from(e: &MelodyErrors) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            kind: kind_name(e.kind()).to_string(),
+            io_kind: match e.kind() {
+                MelodyErrorsKind::Io(kind) => Some(format!("{:?}", kind)),
+                _ => None,
+            },
+            description: e.to_string(),
+            file: e.file(),
+        }
+    }
+}
+
+impl Serialize for MelodyErrors {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorRecord::from(self).serialize(serializer)
+    }
+}
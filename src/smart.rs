@@ -0,0 +1,177 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::history::PlayHistory;
+use crate::library::Library;
+use crate::query::{Query, parse_duration};
+use crate::song::Playlist;
+use crate::utils::read_text;
+use rand::{rng, seq::SliceRandom};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// How big a smart playlist can get
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    /// At most this many songs
+    Songs(usize),
+    /// Songs are added until the next one would go over this
+    Duration(Duration),
+}
+
+/// A playlist defined by rules, its songs are picked from the library
+/// every time it is evaluated
+///
+/// Saved as `key=value` lines:
+/// ```text
+/// filter=genre:jazz
+/// unplayed=30d
+/// limit=2h
+/// order=random
+/// ```
+/// `filter` is a `Query`, `unplayed` leaves out songs played more recently than
+/// that, `limit` is a song count or a duration and `order` is `random` or sort keys
+/// # Example
+/// ```no_run
+/// let jazz = melody::SmartPlaylist::parse("filter=genre:jazz\nunplayed=30d\nlimit=2h\norder=random")?;
+/// let playlist = jazz.evaluate(
+///     &melody::Library::open_default()?,
+///     &melody::PlayHistory::open_default()?,
+/// );
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SmartPlaylist {
+    /// Songs have to match this, its sort keys order the playlist
+    pub query: Query,
+    /// Leave out songs played more recently than this
+    pub unplayed_for: Option<Duration>,
+    pub limit: Option<Limit>,
+    /// Shuffle the songs instead of sorting them
+    pub random: bool,
+}
+
+fn invalid(msg: &str) -> MelodyErrors {
+    MelodyErrors::new(MelodyErrorsKind::MalformedPlaylist, msg, None)
+}
+
+/// Writes a duration in the largest unit that fits exactly, like `30d` or `90s`
+fn fmt_secs(d: Duration) -> String {
+    let secs = d.as_secs();
+    [(86400, "d"), (3600, "h"), (60, "m")]
+        .iter()
+        .find(|(unit, _)| secs != 0 && secs.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", secs / unit, suffix))
+        .unwrap_or_else(|| format!("{}s", secs))
+}
+
+impl SmartPlaylist {
+    /// Set a single rule, `key` is one of `filter`, `unplayed`, `limit` or `order`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), MelodyErrors> {
+        let value = value.trim();
+        let bad_value = || invalid(&format!("Invalid value for {}: {:?}", key, value));
+        match key.trim() {
+            // Replaces the sort keys too, so it has to come before `order`
+            "filter" => self.query = Query::parse(value)?,
+            "unplayed" => {
+                self.unplayed_for = match value {
+                    "" => None,
+                    v => Some(parse_duration(v).ok_or_else(bad_value)?),
+                }
+            }
+            "limit" => {
+                self.limit = match value {
+                    "" => None,
+                    v if v.bytes().all(|b| b.is_ascii_digit()) => {
+                        Some(Limit::Songs(v.parse().map_err(|_| bad_value())?))
+                    }
+                    v => Some(Limit::Duration(parse_duration(v).ok_or_else(bad_value)?)),
+                }
+            }
+            "order" => match value {
+                "random" | "shuffle" => self.random = true,
+                keys => {
+                    self.random = false;
+                    self.query = self.query.clone().sort_by(keys)?;
+                }
+            },
+            other => return Err(invalid(&format!("Unknown smart playlist rule {:?}", other))),
+        }
+        Ok(())
+    }
+    /// Parse rules written as `key=value` lines, empty lines and `#` comments are skipped
+    pub fn parse(text: &str) -> Result<Self, MelodyErrors> {
+        let mut rules = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = line
+                .split_once('=')
+                .ok_or_else(|| invalid(&format!("Line {}: expected key=value", n + 1)))?;
+            rules.push(rule);
+        }
+        // The filter goes first so it doesnt drop the sort keys of `order`
+        rules.sort_by_key(|(key, _)| key.trim() != "filter");
+        let mut smart = Self::default();
+        for (key, value) in rules {
+            smart.set(key, value)?;
+        }
+        Ok(smart)
+    }
+    /// Read the rules saved at `path`
+    pub fn open(path: &Path) -> Result<Self, MelodyErrors> {
+        Self::parse(&read_text(path)?).map_err(|e| e.with_file(path))
+    }
+    /// Pick the songs of the playlist from `library`
+    pub fn evaluate(&self, library: &Library, history: &PlayHistory) -> Playlist {
+        let mut playlist = library.query(&self.query);
+        if let Some(age) = self.unplayed_for {
+            let now = SystemTime::now();
+            playlist.tracks.retain(|s| {
+                history
+                    .last_played(s)
+                    .is_none_or(|last| now.duration_since(last).is_ok_and(|d| d >= age))
+            });
+        }
+        if self.random {
+            playlist.tracks.shuffle(&mut rng());
+        }
+        match self.limit {
+            Some(Limit::Songs(n)) => playlist.tracks.truncate(n),
+            Some(Limit::Duration(max)) => {
+                let mut total = Duration::from_secs(0);
+                let fits = playlist
+                    .tracks
+                    .iter()
+                    .take_while(|s| {
+                        total += s.duration();
+                        total <= max
+                    })
+                    .count();
+                playlist.tracks.truncate(fits);
+            }
+            None => (),
+        }
+        playlist
+    }
+}
+
+/// Writes the rules in the format read by `SmartPlaylist::parse`
+impl fmt::Display for SmartPlaylist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "filter={}", self.query)?;
+        if let Some(age) = self.unplayed_for {
+            writeln!(f, "unplayed={}", fmt_secs(age))?;
+        }
+        match self.limit {
+            Some(Limit::Songs(n)) => writeln!(f, "limit={}", n)?,
+            Some(Limit::Duration(d)) => writeln!(f, "limit={}", fmt_secs(d))?,
+            None => (),
+        }
+        if self.random {
+            writeln!(f, "order=random")?;
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,46 @@
-use crate::utils::{fmt_duration, list_files, supported_song};
+use crate::cue::load_tracks;
+use crate::duplicates::DUPLICATE_TOLERANCE;
+use crate::render::Renderer;
+use crate::scan::ScanProgress;
+use crate::utils::fmt_duration;
 use std::convert::AsRef;
 use std::fmt;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use crate::errors::MelodyErrors;
-use lofty::file::{AudioFile, TaggedFileExt};
-use lofty::tag::Accessor;
-use tabwriter::TabWriter;
+use lofty::file::{AudioFile, TaggedFile, TaggedFileExt};
+use lofty::tag::{Accessor, ItemKey};
+
+/// Shown in place of a missing artist, album or title
+pub(crate) const UNKNOWN_ARTIST: &str = "Unknown Artist";
+pub(crate) const UNKNOWN_ALBUM: &str = "Unknown Album";
+pub(crate) const UNKNOWN_TITLE: &str = "Unknown Title";
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Song {
     pub artist: Option<String>,
     pub album: Option<String>,
+    /// Artist of the whole album, when it differs from the song's
+    pub album_artist: Option<String>,
     pub title: Option<String>,
     pub track: Option<u32>,
+    /// Disc number, for albums spread over several discs
+    pub disc: Option<u32>,
     pub genre: Option<String>,
+    /// Release year
+    pub year: Option<u32>,
+    /// Tagged as part of a compilation
+    pub compilation: bool,
     pub duration: Duration,
+    /// Audio bitrate in kbps
+    pub bitrate: Option<u32>,
     pub file: PathBuf,
     pub elapsed: Duration,
+    /// Position inside `file` where the song starts
+    /// Only set for virtual songs, like the tracks of a CUE sheet,
+    /// in which case `duration` is the length of the span
+    pub start: Option<Duration>,
 }
 
 impl fmt::Display for Song {
@@ -28,9 +49,9 @@ impl fmt::Display for Song {
         write!(
             f,
             "{} - {} - {} ({})",
-            self.artist().unwrap_or("Unkown Artist"),
-            self.album().unwrap_or("Unkown Album"),
-            self.title().unwrap_or("Unkown Title"),
+            self.artist().unwrap_or(UNKNOWN_ARTIST),
+            self.album().unwrap_or(UNKNOWN_ALBUM),
+            self.title().unwrap_or(UNKNOWN_TITLE),
             duration
         )
     }
@@ -53,6 +74,11 @@ impl Song {
             None => None,
         }
     }
+    /// Optionally return the artist of the album
+    /// If `None` the song isnt tagged with one
+    pub fn album_artist(&self) -> Option<&str> {
+        self.album_artist.as_deref()
+    }
     /// Optionally return the title of the song
     /// If `None` it wasnt able to read the tags
     pub fn title(&self) -> Option<&str> {
@@ -66,6 +92,11 @@ impl Song {
     pub fn track(&self) -> Option<u32> {
         self.track
     }
+    /// Optionally returns the disc the song is on
+    /// If `None` the song isnt tagged with one
+    pub fn disc(&self) -> Option<u32> {
+        self.disc
+    }
     /// Optionally returns the song's genere
     /// If `None` it wasnt able to read the tags
     pub fn genre(&self) -> Option<&str> {
@@ -74,10 +105,28 @@ impl Song {
             None => None,
         }
     }
+    /// Optionally returns the song's release year
+    /// If `None` it wasnt able to read the tags
+    pub fn year(&self) -> Option<u32> {
+        self.year
+    }
+    /// Checks if the song is part of a compilation, either by its tag
+    /// or by an album artist like "Various Artists"
+    pub fn is_compilation(&self) -> bool {
+        self.compilation
+            || self.album_artist().is_some_and(|a| {
+                a.eq_ignore_ascii_case("various artists") || a.eq_ignore_ascii_case("va")
+            })
+    }
     /// Returns the `Duration` of the song
     pub fn duration(&self) -> Duration {
         self.duration
     }
+    /// Optionally returns the audio bitrate in kbps
+    /// If `None` it couldnt be read from the file
+    pub fn bitrate(&self) -> Option<u32> {
+        self.bitrate
+    }
     /// Returns the elapsed time the song has been played
     pub fn elapsed(&self) -> Duration {
         self.elapsed
@@ -86,16 +135,28 @@ impl Song {
     pub fn file(&self) -> &Path {
         &self.file
     }
+    /// Optionally returns where the song starts inside its file
+    /// If `None` the song spans the whole file
+    pub fn start(&self) -> Option<Duration> {
+        self.start
+    }
     /// Load song from Pathbuf
     pub fn load(file: PathBuf) -> Result<Self, MelodyErrors> {
-        let track: Option<u32> = None;
+        Self::load_tagged(file).map(|(song, _)| song)
+    }
+    /// Same as `load`, also returns the parsed file so other tags can be read without opening it again
+    pub(crate) fn load_tagged(file: PathBuf) -> Result<(Self, TaggedFile), MelodyErrors> {
         let tagged_file = lofty::probe::Probe::open(&file)
             .map_err(|e| {
                 MelodyErrors::new(e.into(), "Invalid path provided", Some(file.as_path()))
             })?
+            // Trust the content over the extension
+            .guess_file_type()
+            .map_err(|e| MelodyErrors::from_io(e, &file))?
             .read()
             .map_err(|e| {
-                MelodyErrors::new(e.into(), "Failed to read file", Some(file.as_path()))
+                let description = format!("Failed to read file: {}", e);
+                MelodyErrors::new(e.into(), &description, Some(file.as_path()))
             })?;
         let metadata = match tagged_file.primary_tag() {
             Some(tag) => tag,
@@ -107,19 +168,29 @@ impl Song {
                 )
             })?,
         };
-        Ok(Self {
+        let song = Self {
             artist: metadata.artist().map(String::from),
             album: metadata.album().map(String::from),
+            album_artist: metadata.get_string(&ItemKey::AlbumArtist).map(String::from),
             title: metadata.title().map(String::from),
             genre: metadata.genre().map(String::from),
-            track,
+            track: metadata.track(),
+            disc: metadata.disk(),
+            year: metadata.year(),
+            compilation: metadata
+                .get_string(&ItemKey::FlagCompilation)
+                .is_some_and(|c| c == "1" || c.eq_ignore_ascii_case("true")),
             duration: tagged_file.properties().duration(),
+            bitrate: tagged_file.properties().audio_bitrate(),
             file,
             elapsed: Duration::from_millis(0),
-        })
+            start: None,
+        };
+        Ok((song, tagged_file))
     }
     /// Checks if the song is the same
     /// if matching_genre is true it will check genre as well
+    /// Durations can differ by up to `DUPLICATE_TOLERANCE`, as different encodings rarely match exactly
     pub fn matching_song(&self, s: &Song, matching_genre: bool) -> bool {
         if self.artist() != s.artist() {
             return false;
@@ -139,10 +210,7 @@ impl Song {
         if self.track() != s.track() {
             return false;
         }
-        if self.duration() == s.duration() {
-            return false;
-        }
-        true
+        self.duration().abs_diff(s.duration()) <= DUPLICATE_TOLERANCE
     }
     /// Checks if the song is an exact match
     /// Checks the song's tags and if the path is the same
@@ -167,28 +235,27 @@ impl Playlist {
     /// will walk through the directory and
     /// collect the songs it can process
     pub fn from_dir(path: PathBuf) -> Option<Self> {
+        Self::from_dir_with_progress(path, |_| ())
+    }
+    /// Same as `from_dir`, tags are read by a pool of worker threads
+    /// and `progress` is called as files are found and loaded
+    pub fn from_dir_with_progress(
+        path: PathBuf,
+        progress: impl FnMut(&ScanProgress),
+    ) -> Option<Self> {
         if !path.exists() {
             return None;
         };
         if path.is_file() {
-            if let Ok(song) = Song::load(path) {
-                return Some(Self { tracks: vec![song] });
+            if let Ok(tracks) = load_tracks(path) {
+                return Some(Self { tracks });
             } else {
                 return None;
             }
         };
-        println!("Collecting tracks..");
-        let mut tracks: Vec<Song> = list_files(path)
-            .filter_map(|f| {
-                if supported_song(&f) {
-                    Song::load(f).ok()
-                } else {
-                    None
-                }
-            })
-            .collect();
-        tracks.dedup();
-        Some(Self { tracks })
+        Self::scan_dir_with_progress(path, progress)
+            .ok()
+            .map(|(playlist, _)| playlist)
     }
     /// Returns if the playlist is currently empty
     pub fn is_empty(&self) -> bool {
@@ -204,21 +271,6 @@ impl From<Vec<Song>> for Playlist {
 
 impl fmt::Display for Playlist {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut tw = TabWriter::new(Vec::new());
-        let mut lines: Vec<String> = vec![String::from(
-            "|\tArtist\t|\tAlbum\t|\tTitle\t|\tDuration\t|",
-        )];
-        for track in &self.tracks {
-            lines.push(format!(
-                "|\t{}\t|\t{}\t|\t{}\t|\t{}\t|",
-                track.artist().unwrap_or("Unkown Artist"),
-                track.album().unwrap_or("Unkown Album"),
-                track.title().unwrap_or("Unkown Title"),
-                fmt_duration(&track.duration)
-            ))
-        }
-        write!(tw, "{}", lines.join("\n")).unwrap();
-        tw.flush().unwrap();
-        f.write_str(&String::from_utf8(tw.into_inner().unwrap()).unwrap())
+        f.write_str(&self.render(&Renderer::default()))
     }
 }
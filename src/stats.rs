@@ -0,0 +1,224 @@
+use crate::history::PlayHistory;
+use crate::library::Library;
+use crate::song::{Playlist, Song};
+use crate::utils::{fmt_duration, non_empty_tag};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Lower bounds of the bitrate ranges in `LibraryStats::bitrates`, in kbps
+pub const BITRATE_RANGES: &[u32] = &[0, 128, 192, 256, 320, 500, 1000];
+
+/// Tags `LibraryStats::missing` counts
+const KEY_TAGS: &[&str] = &["artist", "album", "title", "track", "genre", "year"];
+
+/// How many artists `LibraryStats::top_artists` keeps
+const TOP_ARTISTS: usize = 10;
+
+/// A summary of a collection of songs
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LibraryStats {
+    pub tracks: usize,
+    /// Different albums, told apart by album artist and name
+    pub albums: usize,
+    pub artists: usize,
+    pub duration: Duration,
+    /// Bytes on disk, every file counts once
+    pub size: u64,
+    /// Files per extension, like `flac` or `mp3`
+    pub formats: BTreeMap<String, usize>,
+    /// Tracks per bitrate range, keyed by the lower bound from `BITRATE_RANGES`.
+    /// Tracks without a bitrate arent counted
+    pub bitrates: BTreeMap<u32, usize>,
+    /// Tracks per genre, `None` for tracks without one
+    pub genres: BTreeMap<Option<String>, usize>,
+    /// Tracks missing each of artist, album, title, track, genre and year
+    pub missing: BTreeMap<&'static str, usize>,
+    /// Tracks missing any of artist, album or title
+    pub untagged: usize,
+    /// The most played artists with their play counts, empty without a history
+    pub top_artists: Vec<(String, u64)>,
+}
+
+impl LibraryStats {
+    /// Sums up `songs`, play counts come from `history` if there is one
+    pub fn new<'a>(
+        songs: impl IntoIterator<Item = &'a Song>,
+        history: Option<&PlayHistory>,
+    ) -> Self {
+        let mut stats = Self::default();
+        let mut files = BTreeSet::new();
+        let mut albums = BTreeSet::new();
+        // Artists are counted without case, shown as first seen
+        let mut artists: HashMap<String, (String, u64)> = HashMap::new();
+        for song in songs {
+            stats.tracks += 1;
+            stats.duration += song.duration();
+            if files.insert(song.file().to_path_buf()) {
+                stats.size += fs::metadata(song.file()).map_or(0, |m| m.len());
+                let ext = song.file().extension().and_then(|e| e.to_str());
+                *stats
+                    .formats
+                    .entry(ext.unwrap_or("none").to_lowercase())
+                    .or_default() += 1;
+            }
+            if let Some(bitrate) = song.bitrate() {
+                let range = BITRATE_RANGES
+                    .iter()
+                    .rev()
+                    .find(|&&low| bitrate >= low)
+                    .copied()
+                    .unwrap_or(0);
+                *stats.bitrates.entry(range).or_default() += 1;
+            }
+            *stats
+                .genres
+                .entry(non_empty_tag(song.genre()).map(String::from))
+                .or_default() += 1;
+            let artist = non_empty_tag(song.artist());
+            if let Some(album) = non_empty_tag(song.album()) {
+                let by = non_empty_tag(song.album_artist())
+                    .or(artist)
+                    .unwrap_or_default();
+                albums.insert((by.to_lowercase(), album.to_lowercase()));
+            }
+            if let Some(artist) = artist {
+                let plays = history.map_or(0, |h| h.plays(song).count as u64);
+                let entry = artists
+                    .entry(artist.to_lowercase())
+                    .or_insert_with(|| (artist.to_string(), 0));
+                entry.1 += plays;
+            }
+            let present = [
+                artist.is_some(),
+                non_empty_tag(song.album()).is_some(),
+                non_empty_tag(song.title()).is_some(),
+                song.track().is_some(),
+                non_empty_tag(song.genre()).is_some(),
+                song.year().is_some(),
+            ];
+            for (name, _) in KEY_TAGS.iter().zip(present).filter(|(_, p)| !p) {
+                *stats.missing.entry(name).or_default() += 1;
+            }
+            if !present[..3].iter().all(|p| *p) {
+                stats.untagged += 1;
+            }
+        }
+        stats.albums = albums.len();
+        stats.artists = artists.len();
+        let mut top: Vec<(String, u64)> = artists
+            .into_values()
+            .filter(|(_, plays)| *plays > 0)
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(TOP_ARTISTS);
+        stats.top_artists = top;
+        stats
+    }
+}
+
+/// Formats a number of bytes like `1.5 GiB`
+fn fmt_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Name of the bitrate range starting at `low`, like `192-255 kbps`
+fn range_name(low: u32) -> String {
+    match BITRATE_RANGES.iter().find(|&&high| high > low) {
+        Some(high) if low == 0 => format!("under {} kbps", high),
+        Some(high) => format!("{}-{} kbps", low, high - 1),
+        None => format!("{}+ kbps", low),
+    }
+}
+
+/// A report for people, section by section
+impl fmt::Display for LibraryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |n: usize| n as f64 * 100.0 / self.tracks.max(1) as f64;
+        writeln!(
+            f,
+            "{} tracks, {} albums, {} artists",
+            self.tracks, self.albums, self.artists
+        )?;
+        writeln!(f, "Total duration: {}", fmt_duration(&self.duration).trim())?;
+        writeln!(f, "Size on disk: {}", fmt_size(self.size))?;
+        writeln!(f, "\nFormats:")?;
+        let mut formats: Vec<_> = self.formats.iter().collect();
+        formats.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (format, count) in formats {
+            writeln!(f, "    {:<12}{:>7}", format, count)?;
+        }
+        writeln!(f, "\nBitrates:")?;
+        for (low, count) in &self.bitrates {
+            writeln!(
+                f,
+                "    {:<16}{:>7}  {:>5.1}%",
+                range_name(*low),
+                count,
+                share(*count)
+            )?;
+        }
+        writeln!(f, "\nGenres:")?;
+        let mut genres: Vec<_> = self.genres.iter().collect();
+        genres.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (genre, count) in genres {
+            let name = genre.as_deref().unwrap_or("(none)");
+            writeln!(f, "    {:<24}{:>7}  {:>5.1}%", name, count, share(*count))?;
+        }
+        writeln!(f, "\nMissing tags:")?;
+        for name in KEY_TAGS {
+            let count = self.missing.get(name).copied().unwrap_or(0);
+            writeln!(f, "    {:<12}{:>7}", name, count)?;
+        }
+        write!(
+            f,
+            "    {} tracks are missing artist, album or title",
+            self.untagged
+        )?;
+        if !self.top_artists.is_empty() {
+            write!(f, "\n\nMost played artists:")?;
+            for (i, (artist, plays)) in self.top_artists.iter().enumerate() {
+                let s = if *plays == 1 { "" } else { "s" };
+                write!(f, "\n    {:>2}. {} ({} play{})", i + 1, artist, plays, s)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Playlist {
+    /// Sums up the songs of the playlist, see `LibraryStats::new`
+    pub fn stats(&self, history: Option<&PlayHistory>) -> LibraryStats {
+        LibraryStats::new(&self.tracks, history)
+    }
+}
+
+impl Library {
+    /// Sums up the songs in the index, or only the ones below `dir`
+    /// # Example
+    /// ```no_run
+    /// let library = melody::Library::open_default()?;
+    /// let history = melody::PlayHistory::open_default()?;
+    /// println!("{}", library.stats(None, Some(&history)));
+    /// # Ok::<(), melody::MelodyErrors>(())
+    /// ```
+    pub fn stats(&self, dir: Option<&Path>, history: Option<&PlayHistory>) -> LibraryStats {
+        let songs = self
+            .songs()
+            .filter(|s| dir.is_none_or(|dir| s.file().starts_with(dir)));
+        LibraryStats::new(songs, history)
+    }
+}
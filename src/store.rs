@@ -0,0 +1,269 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::history::PlayHistory;
+use crate::library::Library;
+use crate::m3u::append_m3u;
+use crate::musicplayer::MusicPlayer;
+use crate::smart::SmartPlaylist;
+use crate::song::{Playlist, Song};
+use crate::utils::{entry_path, playlist_entry, read_text};
+use directories::ProjectDirs;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns Melody's data directory
+pub(crate) fn data_dir() -> Result<PathBuf, MelodyErrors> {
+    ProjectDirs::from("info", "LovingMelody", "Melody")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .ok_or_else(|| {
+            MelodyErrors::new(
+                MelodyErrorsKind::FailedToGetDataDir,
+                "Failed to find the data directory",
+                None,
+            )
+        })
+}
+
+/// Named playlists saved as M3U8 files in a directory
+/// Smart playlists are saved next to them as `.smart` files holding their rules
+/// # Example
+/// ```no_run
+/// let store = melody::PlaylistStore::open_default()?;
+/// store.create("Road trip")?;
+/// for name in store.list()? {
+///     println!("{}", name);
+/// }
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct PlaylistStore {
+    dir: PathBuf,
+}
+
+impl PlaylistStore {
+    /// Store playlists in `dir`, creating it if needed
+    pub fn new(dir: PathBuf) -> Result<Self, MelodyErrors> {
+        fs::create_dir_all(&dir).map_err(|e| MelodyErrors::from_io(e, &dir))?;
+        Ok(Self { dir })
+    }
+    /// Store playlists in the `playlists` folder of Melody's data directory
+    pub fn open_default() -> Result<Self, MelodyErrors> {
+        Self::new(data_dir()?.join("playlists"))
+    }
+    /// Directory the playlists are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    /// Path of the file backing the playlist `name`
+    /// Fails if `name` can not be used as a file name
+    pub fn path(&self, name: &str) -> Result<PathBuf, MelodyErrors> {
+        Self::check_name(name)?;
+        Ok(self.dir.join(format!("{}.m3u8", name)))
+    }
+    /// Path of the file holding the rules of the smart playlist `name`
+    /// Fails if `name` can not be used as a file name
+    pub fn smart_path(&self, name: &str) -> Result<PathBuf, MelodyErrors> {
+        Self::check_name(name)?;
+        Ok(self.dir.join(format!("{}.smart", name)))
+    }
+    fn check_name(name: &str) -> Result<(), MelodyErrors> {
+        let invalid = name.trim().is_empty()
+            || name.trim() != name
+            || name.starts_with('.')
+            || name.contains(['/', '\\', '\0']);
+        if invalid {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidPlaylistName,
+                &format!("Invalid playlist name: {:?}", name),
+                None,
+            ));
+        }
+        Ok(())
+    }
+    /// Path of an existing playlist, smart or not
+    fn existing(&self, name: &str) -> Result<PathBuf, MelodyErrors> {
+        let path = self.path(name)?;
+        let smart = self.smart_path(name)?;
+        if path.is_file() {
+            Ok(path)
+        } else if smart.is_file() {
+            Ok(smart)
+        } else {
+            Err(MelodyErrors::new(
+                MelodyErrorsKind::PlaylistNotFound,
+                &format!("No playlist named {:?}", name),
+                Some(&path),
+            ))
+        }
+    }
+    /// Path of a static playlist, fails if `name` is a smart playlist
+    fn editable(&self, name: &str) -> Result<PathBuf, MelodyErrors> {
+        let smart = self.smart_path(name)?;
+        if smart.exists() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::ReadOnlyPlaylist,
+                &format!(
+                    "{:?} is a smart playlist, its songs come from its rules",
+                    name
+                ),
+                Some(&smart),
+            ));
+        }
+        self.path(name)
+    }
+    /// Path of a playlist that does not exist yet, smart or not
+    fn vacant(&self, name: &str, smart: bool) -> Result<PathBuf, MelodyErrors> {
+        let path = if smart {
+            self.smart_path(name)?
+        } else {
+            self.path(name)?
+        };
+        if self.contains(name) {
+            Err(MelodyErrors::new(
+                MelodyErrorsKind::PlaylistExists,
+                &format!("A playlist named {:?} already exists", name),
+                Some(&path),
+            ))
+        } else {
+            Ok(path)
+        }
+    }
+    /// Names of all saved playlists, smart ones included, sorted
+    pub fn list(&self) -> Result<Vec<String>, MelodyErrors> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| MelodyErrors::from_io(e, &self.dir))?;
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.is_file()
+                    && p.extension()
+                        .is_some_and(|ext| ext == "m3u8" || ext == "smart")
+            })
+            .filter_map(|p| p.file_stem()?.to_str().map(String::from))
+            .collect();
+        names.sort_by_key(|n| n.to_lowercase());
+        names.dedup();
+        Ok(names)
+    }
+    /// Checks if a playlist exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.existing(name).is_ok()
+    }
+    /// Checks if `name` is a smart playlist
+    pub fn is_smart(&self, name: &str) -> bool {
+        self.smart_path(name).is_ok_and(|p| p.is_file())
+    }
+    /// Create an empty playlist
+    pub fn create(&self, name: &str) -> Result<(), MelodyErrors> {
+        let path = self.vacant(name, false)?;
+        Playlist::from(Vec::new()).write_m3u(&path)
+    }
+    /// Load a playlist
+    /// Smart playlists are evaluated against the default library and play history
+    /// Returns the playlist along with the entries that could not be loaded
+    pub fn load(&self, name: &str) -> Result<(Playlist, Vec<MelodyErrors>), MelodyErrors> {
+        if self.is_smart(name) {
+            let smart = self.load_smart(name)?;
+            let playlist = smart.evaluate(&Library::open_default()?, &PlayHistory::open_default()?);
+            return Ok((playlist, Vec::new()));
+        }
+        Playlist::from_m3u(&self.existing(name)?)
+    }
+    /// Save `playlist` as `name`, replacing any playlist with the same name
+    /// Fails if `name` is a smart playlist
+    pub fn save(&self, name: &str, playlist: &Playlist) -> Result<(), MelodyErrors> {
+        playlist.write_m3u(&self.editable(name)?)
+    }
+    /// Load the rules of the smart playlist `name`
+    pub fn load_smart(&self, name: &str) -> Result<SmartPlaylist, MelodyErrors> {
+        let path = self.smart_path(name)?;
+        if !path.is_file() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::PlaylistNotFound,
+                &format!("No smart playlist named {:?}", name),
+                Some(&path),
+            ));
+        }
+        SmartPlaylist::open(&path)
+    }
+    /// Save `smart` as `name`, replacing any smart playlist with the same name
+    /// Fails if `name` is a normal playlist
+    pub fn save_smart(&self, name: &str, smart: &SmartPlaylist) -> Result<(), MelodyErrors> {
+        let path = self.path(name)?;
+        if path.exists() {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::PlaylistExists,
+                &format!("A playlist named {:?} already exists", name),
+                Some(&path),
+            ));
+        }
+        let path = self.smart_path(name)?;
+        fs::write(&path, smart.to_string()).map_err(|e| MelodyErrors::from_io(e, &path))
+    }
+    /// Add songs to the end of an existing playlist
+    /// Fails if `name` is a smart playlist
+    pub fn append(&self, name: &str, songs: &[Song]) -> Result<(), MelodyErrors> {
+        let path = self.editable(name)?;
+        self.existing(name)?;
+        append_m3u(&path, songs)
+    }
+    /// Rename a playlist, fails if `to` already exists
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), MelodyErrors> {
+        let from = self.existing(from)?;
+        let smart = from.extension().is_some_and(|ext| ext == "smart");
+        let to = self.vacant(to, smart)?;
+        fs::rename(&from, &to).map_err(|e| MelodyErrors::from_io(e, &from))
+    }
+    /// Points the entries of the saved playlists at the new paths of moved files,
+    /// the rest of every playlist is left as it is. Smart playlists dont hold paths.
+    /// Returns the names of the playlists that changed
+    pub fn move_files(
+        &self,
+        moves: &BTreeMap<PathBuf, PathBuf>,
+    ) -> Result<Vec<String>, MelodyErrors> {
+        let mut changed = Vec::new();
+        for name in self.list()? {
+            if self.is_smart(&name) {
+                continue;
+            }
+            let path = self.path(&name)?;
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            let text = read_text(&path)?;
+            let mut moved = false;
+            let lines: Vec<String> = text
+                .lines()
+                .map(|line| {
+                    let entry = line.trim();
+                    if entry.is_empty() || entry.starts_with('#') {
+                        return line.to_string();
+                    }
+                    match entry_path(base, entry).ok().and_then(|p| moves.get(&p)) {
+                        Some(to) => {
+                            moved = true;
+                            playlist_entry(base, to).display().to_string()
+                        }
+                        None => line.to_string(),
+                    }
+                })
+                .collect();
+            if moved {
+                fs::write(&path, lines.join("\n") + "\n")
+                    .map_err(|e| MelodyErrors::from_io(e, &path))?;
+                changed.push(name);
+            }
+        }
+        Ok(changed)
+    }
+    /// Delete a playlist
+    pub fn delete(&self, name: &str) -> Result<(), MelodyErrors> {
+        let path = self.existing(name)?;
+        fs::remove_file(&path).map_err(|e| MelodyErrors::from_io(e, &path))
+    }
+}
+
+impl MusicPlayer {
+    /// Save the songs in queue as the playlist `name`
+    /// Replaces any playlist with the same name
+    pub fn save_queue(&self, store: &PlaylistStore, name: &str) -> Result<(), MelodyErrors> {
+        store.save(name, &Playlist::from(self.queue().clone()))
+    }
+}
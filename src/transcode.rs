@@ -0,0 +1,533 @@
+use crate::cue::{CueSheet, load_tracks};
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::scan::parallel_map;
+use crate::song::{Playlist, Song};
+use crate::utils::open_decoder;
+use lofty::config::WriteOptions;
+use lofty::file::TaggedFileExt;
+use lofty::prelude::ItemKey;
+use lofty::tag::{Tag, TagExt, TagType};
+use rodio::Source;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+/// Formats songs can be transcoded to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TranscodeFormat {
+    /// Encoded by `opusenc`, or `ffmpeg` if it isnt installed
+    Opus,
+    /// Encoded by `lame`, or `ffmpeg` if it isnt installed
+    Mp3,
+}
+
+impl TranscodeFormat {
+    /// Extension of the files written
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscodeFormat::Opus => "opus",
+            TranscodeFormat::Mp3 => "mp3",
+        }
+    }
+    /// Bitrate used when none is given, in kbps
+    pub fn default_bitrate(self) -> u32 {
+        match self {
+            TranscodeFormat::Opus => 128,
+            TranscodeFormat::Mp3 => 192,
+        }
+    }
+    fn tag_type(self) -> TagType {
+        match self {
+            TranscodeFormat::Opus => TagType::VorbisComments,
+            TranscodeFormat::Mp3 => TagType::Id3v2,
+        }
+    }
+    /// Encoder programs, the preferred one first
+    fn encoders(self) -> [Encoder; 2] {
+        match self {
+            TranscodeFormat::Opus => [Encoder::Opusenc, Encoder::Ffmpeg],
+            TranscodeFormat::Mp3 => [Encoder::Lame, Encoder::Ffmpeg],
+        }
+    }
+}
+
+/// Parses `opus` or `mp3`
+impl FromStr for TranscodeFormat {
+    type Err = MelodyErrors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "opus" => Ok(TranscodeFormat::Opus),
+            "mp3" => Ok(TranscodeFormat::Mp3),
+            other => Err(MelodyErrors::new(
+                MelodyErrorsKind::InvalidOption,
+                &format!("Unknown transcode format {:?}, expected opus or mp3", other),
+                None,
+            )),
+        }
+    }
+}
+
+/// Programs that encode the decoded audio, it is piped to them as raw 16 bit samples
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Encoder {
+    Opusenc,
+    Lame,
+    Ffmpeg,
+}
+
+impl Encoder {
+    fn program(self) -> &'static str {
+        match self {
+            Encoder::Opusenc => "opusenc",
+            Encoder::Lame => "lame",
+            Encoder::Ffmpeg => "ffmpeg",
+        }
+    }
+    /// Checks if the program is in `PATH`
+    fn installed(self) -> bool {
+        env::var_os("PATH").is_some_and(|paths| {
+            env::split_paths(&paths).any(|dir| {
+                let program = dir.join(self.program());
+                program.is_file() || program.with_extension("exe").is_file()
+            })
+        })
+    }
+    /// Starts encoding audio with `rate` and `channels` from stdin to `out`
+    fn spawn(
+        self,
+        format: TranscodeFormat,
+        bitrate: u32,
+        (rate, channels): (u32, u16),
+        out: &Path,
+    ) -> Result<Child, MelodyErrors> {
+        let mut command = Command::new(self.program());
+        match self {
+            Encoder::Opusenc => command
+                .args(["--quiet", "--bitrate", &bitrate.to_string(), "--raw"])
+                .args(["--raw-bits", "16", "--raw-endianness", "0"])
+                .args(["--raw-rate", &rate.to_string()])
+                .args(["--raw-chan", &channels.to_string(), "-"])
+                .arg(out),
+            Encoder::Lame => command
+                .args([
+                    "--quiet",
+                    "-r",
+                    "--signed",
+                    "--little-endian",
+                    "--bitwidth",
+                    "16",
+                ])
+                .args(["-s", &(rate as f64 / 1000.0).to_string()])
+                .args(["-m", if channels == 1 { "m" } else { "j" }])
+                .args(["-b", &bitrate.to_string(), "-"])
+                .arg(out),
+            Encoder::Ffmpeg => command
+                .args(["-hide_banner", "-loglevel", "error", "-y", "-f", "s16le"])
+                .args(["-ar", &rate.to_string(), "-ac", &channels.to_string()])
+                .args(["-i", "-", "-map_metadata", "-1", "-c:a"])
+                .arg(match format {
+                    TranscodeFormat::Opus => "libopus",
+                    TranscodeFormat::Mp3 => "libmp3lame",
+                })
+                .args(["-b:a", &format!("{}k", bitrate), "-f", format.extension()])
+                .arg(out),
+        };
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| MelodyErrors::from_io(e, Path::new(self.program())))
+    }
+}
+
+/// Options for `Playlist::transcode`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TranscodeOptions {
+    pub format: TranscodeFormat,
+    /// In kbps
+    pub bitrate: u32,
+}
+
+impl TranscodeOptions {
+    /// The default bitrate of `format`
+    pub fn new(format: TranscodeFormat) -> Self {
+        Self {
+            format,
+            bitrate: format.default_bitrate(),
+        }
+    }
+    /// Written to the encoder settings tag of the outputs, so they are
+    /// encoded again when the options change
+    fn settings(&self) -> String {
+        format!("melody {} {} kbps", self.format.extension(), self.bitrate)
+    }
+}
+
+/// What happened to a single file during a transcode
+#[derive(Debug)]
+pub enum TranscodeOutcome {
+    /// Encoded to this path
+    Transcoded(PathBuf),
+    /// Already in the target format, copied to this path as it is
+    Copied(PathBuf),
+    /// This path is newer than the file and was encoded with the same options,
+    /// it was left alone
+    UpToDate(PathBuf),
+    Failed(MelodyErrors),
+}
+
+/// Outcome of `Playlist::transcode`, one entry per file
+#[derive(Debug, Default)]
+pub struct TranscodeReport {
+    /// Files and what happened to them, sorted by path
+    pub files: Vec<(PathBuf, TranscodeOutcome)>,
+}
+
+impl TranscodeReport {
+    /// Files that were encoded or copied, with their new paths
+    pub fn written(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files
+            .iter()
+            .filter_map(|(from, outcome)| match outcome {
+                TranscodeOutcome::Transcoded(to) | TranscodeOutcome::Copied(to) => {
+                    Some((from.as_path(), to.as_path()))
+                }
+                _ => None,
+            })
+    }
+    /// Number of files that were already up to date
+    pub fn up_to_date(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, o)| matches!(o, TranscodeOutcome::UpToDate(_)))
+            .count()
+    }
+    /// Errors of the files that failed
+    pub fn failed(&self) -> impl Iterator<Item = &MelodyErrors> {
+        self.files.iter().filter_map(|(_, outcome)| match outcome {
+            TranscodeOutcome::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+}
+
+fn encode_error(msg: &str, file: &Path) -> MelodyErrors {
+    MelodyErrors::new(MelodyErrorsKind::FailedToEncode, msg, Some(file))
+}
+
+/// Where `file` goes in `dest`, its path below `root`, or its full path without the root
+/// if it isnt below it, with the extension of `format`
+fn output_path(root: &Path, dest: &Path, file: &Path, format: TranscodeFormat) -> PathBuf {
+    let relative: PathBuf = file
+        .strip_prefix(root)
+        .unwrap_or(file)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    dest.join(relative).with_extension(format.extension())
+}
+
+/// Where `out` is written before it is complete, a hidden file in the same directory
+fn part_path(out: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(out.file_name().unwrap_or_default());
+    name.push(".melody-part");
+    out.with_file_name(name)
+}
+
+/// Checks if `out` was written after `file` last changed,
+/// and with `settings` if it was encoded
+fn is_up_to_date(file: &Path, out: &Path, settings: Option<&str>) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    let newer = matches!((modified(file), modified(out)), (Some(a), Some(b)) if b >= a);
+    newer
+        && settings.is_none_or(|settings| {
+            lofty::read_from_path(out).is_ok_and(|tagged| {
+                tagged
+                    .primary_tag()
+                    .and_then(|tag| tag.get_string(&ItemKey::EncoderSettings))
+                    == Some(settings)
+            })
+        })
+}
+
+/// How much of each channel goes to the left and right side when mixing down to stereo,
+/// for the channel orders of WAV and FLAC like 5.1 as FL FR FC LFE SL SR
+fn stereo_gains(channels: u16) -> Vec<(f32, f32)> {
+    const LEFT: (f32, f32) = (1.0, 0.0);
+    const RIGHT: (f32, f32) = (0.0, 1.0);
+    // Center, LFE and back center go to both sides, surrounds to their own side
+    const BOTH: (f32, f32) = (FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+    const LEFT_SURROUND: (f32, f32) = (FRAC_1_SQRT_2, 0.0);
+    const RIGHT_SURROUND: (f32, f32) = (0.0, FRAC_1_SQRT_2);
+    let gains = match channels {
+        3 => vec![LEFT, RIGHT, BOTH],
+        4 => vec![LEFT, RIGHT, LEFT_SURROUND, RIGHT_SURROUND],
+        5 => vec![LEFT, RIGHT, BOTH, LEFT_SURROUND, RIGHT_SURROUND],
+        6 => vec![LEFT, RIGHT, BOTH, BOTH, LEFT_SURROUND, RIGHT_SURROUND],
+        7 => vec![LEFT, RIGHT, BOTH, BOTH, BOTH, LEFT_SURROUND, RIGHT_SURROUND],
+        8 => vec![
+            LEFT,
+            RIGHT,
+            BOTH,
+            BOTH,
+            LEFT_SURROUND,
+            RIGHT_SURROUND,
+            LEFT_SURROUND,
+            RIGHT_SURROUND,
+        ],
+        n => [LEFT, RIGHT]
+            .into_iter()
+            .chain(std::iter::repeat_n(BOTH, (n as usize).saturating_sub(2)))
+            .collect(),
+    };
+    // Scaled down so every channel at full scale doesnt clip
+    let (left, right) = gains
+        .iter()
+        .fold((0.0, 0.0), |(l, r), &(gl, gr)| (l + gl, r + gr));
+    gains
+        .into_iter()
+        .map(|(l, r)| (l / left, r / right))
+        .collect()
+}
+
+/// Decodes `file` and pipes it through `encoder` into `out`.
+/// MP3 only has room for two channels, more are mixed down, see `stereo_gains`
+fn encode(
+    file: &Path,
+    out: &Path,
+    encoder: Encoder,
+    options: &TranscodeOptions,
+) -> Result<(), MelodyErrors> {
+    let decoder = open_decoder(file)?;
+    let (rate, channels) = (decoder.sample_rate(), decoder.channels().max(1));
+    let downmix =
+        (options.format == TranscodeFormat::Mp3 && channels > 2).then(|| stereo_gains(channels));
+    let encoded = if downmix.is_some() { 2 } else { channels };
+    let mut child = encoder.spawn(options.format, options.bitrate, (rate, encoded), out)?;
+    let mut stderr = child.stderr.take();
+    // Read stderr on the side so a chatty encoder cant block on a full pipe
+    let errors = thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(stderr) = &mut stderr {
+            let _ = stderr.read_to_string(&mut text);
+        }
+        text
+    });
+    let written = child.stdin.take().map_or(Ok(()), |stdin| {
+        let mut stdin = BufWriter::new(stdin);
+        let mut frame = Vec::with_capacity(channels as usize);
+        for sample in decoder {
+            frame.push(sample);
+            if frame.len() < channels as usize {
+                continue;
+            }
+            if let Some(gains) = &downmix {
+                let (left, right) = frame
+                    .iter()
+                    .zip(gains)
+                    .fold((0.0, 0.0), |(l, r), (&s, &(gl, gr))| {
+                        (l + s as f32 * gl, r + s as f32 * gr)
+                    });
+                for side in [left, right] {
+                    stdin.write_all(&(side.round() as i16).to_le_bytes())?;
+                }
+            } else {
+                for s in &frame {
+                    stdin.write_all(&s.to_le_bytes())?;
+                }
+            }
+            frame.clear();
+        }
+        stdin.flush()
+    });
+    let status = child.wait().map_err(|e| MelodyErrors::from_io(e, file))?;
+    let errors = errors.join().unwrap_or_default();
+    if !status.success() {
+        let reason = errors.lines().last().unwrap_or("no output").trim();
+        return Err(encode_error(
+            &format!("{} failed, {}: {}", encoder.program(), status, reason),
+            file,
+        ));
+    }
+    written.map_err(|e| MelodyErrors::from_io(e, file))
+}
+
+/// Copies the tags and embedded pictures of `file` to `out`, along with the encoder settings
+fn copy_tags(file: &Path, out: &Path, options: &TranscodeOptions) -> Result<(), MelodyErrors> {
+    let meta_err = |e: lofty::error::LoftyError, path: &Path| {
+        let description = format!("Failed to copy tags: {}", e);
+        MelodyErrors::new(e.into(), &description, Some(path))
+    };
+    let tagged = lofty::read_from_path(file).map_err(|e| meta_err(e, file))?;
+    let mut tag = match tagged.primary_tag().or_else(|| tagged.first_tag()) {
+        Some(tag) => tag.clone(),
+        None => Tag::new(options.format.tag_type()),
+    };
+    tag.re_map(options.format.tag_type());
+    tag.insert_text(ItemKey::EncoderSettings, options.settings());
+    tag.save_to_path(out, WriteOptions::default())
+        .map_err(|e| meta_err(e, out))
+}
+
+/// Writes a CUE sheet of the tracks of `file` next to `out`, the transcode of the whole file.
+/// The sheet lists every track of the file, even if only some of them are in the playlist
+fn write_sheet(file: &Path, out: &Path, songs: &[&Song]) -> Result<(), MelodyErrors> {
+    let mut tracks: Vec<Song> = load_tracks(file.to_path_buf())?
+        .into_iter()
+        .filter(|s| s.start().is_some())
+        .collect();
+    // The sheet isnt next to the file, like a playlist read from `Playlist::from_cue`
+    if tracks.is_empty() {
+        tracks = songs.iter().map(|&s| s.clone()).collect();
+        tracks.sort_by_key(|s| s.start());
+    }
+    let name = out.file_name().unwrap_or_default().to_string_lossy();
+    let sheet = CueSheet::from_songs(&tracks, name.into_owned());
+    let path = out.with_extension("cue");
+    fs::write(&path, sheet.to_string()).map_err(|e| MelodyErrors::from_io(e, &path))
+}
+
+/// Transcodes or copies `file` to `out`, through a hidden file so an interrupted
+/// run doesnt leave a file that looks up to date
+fn transcode_file(
+    file: &Path,
+    out: &Path,
+    encoder: Encoder,
+    options: &TranscodeOptions,
+) -> TranscodeOutcome {
+    let same_format = file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(options.format.extension()));
+    let settings = (!same_format).then(|| options.settings());
+    if is_up_to_date(file, out, settings.as_deref()) {
+        return TranscodeOutcome::UpToDate(out.to_path_buf());
+    }
+    let part = part_path(out);
+    let result = (|| {
+        if let Some(dir) = out.parent() {
+            fs::create_dir_all(dir).map_err(|e| MelodyErrors::from_io(e, dir))?;
+        }
+        if same_format {
+            fs::copy(file, &part).map_err(|e| MelodyErrors::from_io(e, file))?;
+        } else {
+            encode(file, &part, encoder, options)?;
+            copy_tags(file, &part, options)?;
+        }
+        fs::rename(&part, out).map_err(|e| MelodyErrors::from_io(e, &part))
+    })();
+    match result {
+        Ok(()) if same_format => TranscodeOutcome::Copied(out.to_path_buf()),
+        Ok(()) => TranscodeOutcome::Transcoded(out.to_path_buf()),
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            TranscodeOutcome::Failed(e)
+        }
+    }
+}
+
+impl Playlist {
+    /// Converts the songs to `options.format` for devices like phones, on a few threads.
+    /// Files keep their path below `root` inside `dest`, with the new extension.
+    /// Tags and embedded cover art are copied, songs already in the format are copied
+    /// as they are. Outputs newer than their song are skipped, unless they were
+    /// encoded with another bitrate. Files split by a CUE sheet are transcoded whole,
+    /// with a sheet of their tracks written next to them.
+    /// The audio is decoded by Melody and encoded by `opusenc` or `lame`, or `ffmpeg`
+    /// if those arent installed. Fails if none of them are, or if Melody was built
+    /// without the `external-encoders` feature.
+    /// `progress` is called with the number of files done and the number to do
+    /// # Example
+    /// ```no_run
+    /// use std::path::Path;
+    /// use melody::{TranscodeFormat, TranscodeOptions};
+    /// let music = Path::new("/home/user/Music");
+    /// let playlist = melody::Playlist::from_dir(music.to_path_buf()).unwrap();
+    /// let options = TranscodeOptions::new(TranscodeFormat::Opus);
+    /// let report = playlist.transcode(music, Path::new("/media/phone/Music"), &options, |_, _| ())?;
+    /// println!("{} files written", report.written().count());
+    /// # Ok::<(), melody::MelodyErrors>(())
+    /// ```
+    pub fn transcode(
+        &self,
+        root: &Path,
+        dest: &Path,
+        options: &TranscodeOptions,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<TranscodeReport, MelodyErrors> {
+        if !cfg!(feature = "external-encoders") {
+            return Err(MelodyErrors::new(
+                MelodyErrorsKind::FailedToEncode,
+                "Melody was built without the external-encoders feature, it has no encoder of its own",
+                None,
+            ));
+        }
+        let encoder = options
+            .format
+            .encoders()
+            .into_iter()
+            .find(|e| e.installed())
+            .ok_or_else(|| {
+                let [preferred, fallback] = options.format.encoders().map(Encoder::program);
+                MelodyErrors::new(
+                    MelodyErrorsKind::FailedToEncode,
+                    &format!(
+                        "No {} encoder found, install {} or {}",
+                        options.format.extension(),
+                        preferred,
+                        fallback
+                    ),
+                    None,
+                )
+            })?;
+        let mut songs: BTreeMap<&Path, Vec<&Song>> = BTreeMap::new();
+        for song in &self.tracks {
+            songs.entry(song.file()).or_default().push(song);
+        }
+        let mut report = TranscodeReport::default();
+        let mut outputs = BTreeSet::new();
+        let mut todo = Vec::new();
+        for (file, songs) in songs {
+            let out = output_path(root, dest, file, options.format);
+            if !outputs.insert(out.clone()) {
+                // Like `song.flac` and `song.mp3` in one directory
+                let msg = format!("Another song is written to {:?} already", out);
+                let e = encode_error(&msg, file);
+                report
+                    .files
+                    .push((file.to_path_buf(), TranscodeOutcome::Failed(e)));
+            } else {
+                todo.push((file, out, songs));
+            }
+        }
+        let (total, mut done) = (todo.len(), 0);
+        parallel_map(
+            todo,
+            |(file, out, songs)| {
+                let mut outcome = transcode_file(file, &out, encoder, options);
+                if songs.iter().any(|s| s.start().is_some())
+                    && !matches!(outcome, TranscodeOutcome::Failed(_))
+                    && let Err(e) = write_sheet(file, &out, &songs)
+                {
+                    outcome = TranscodeOutcome::Failed(e);
+                }
+                (file.to_path_buf(), outcome)
+            },
+            |result, _| {
+                report.files.push(result);
+                done += 1;
+                progress(done, total);
+            },
+        );
+        report.files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(report)
+    }
+}
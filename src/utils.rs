@@ -1,8 +1,10 @@
 use crate::errors::{MelodyErrors, MelodyErrorsKind};
-use crate::song::{Playlist, Song};
+use lofty::file::FileType;
+use lofty::probe::Probe;
 use num_integer::div_mod_floor;
-use std::fs;
-use std::io::ErrorKind as IoErrorKind;
+use rodio::Decoder;
+use std::fs::{self, File};
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use walkdir::WalkDir;
@@ -42,160 +44,164 @@ pub fn list_files(path: PathBuf) -> impl Iterator<Item = PathBuf> {
         .filter_map(|e| e.ok().map(|e| e.into_path()))
         .filter(|p| ignored_file(p))
 }
+
+/// `None` for tags that arent set or are only whitespace
+pub(crate) fn non_empty_tag(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|v| !v.is_empty())
+}
+
 /// Optionally returns the file's extention as a String
 pub fn get_filetype(path: &Path) -> Option<String> {
     Some(path.extension()?.to_str()?.to_lowercase())
 }
 
-/// Checks if a song is supported
-/// If a song's file extention is
-/// flac | wav | vorbis | mp3 | ogg
-/// its supported
-pub fn supported_song(path: &Path) -> bool {
-    path.exists()
-        && path.is_file()
-        && match get_filetype(path) {
-            Some(ext) => matches!(ext.as_str(), "flac" | "wav" | "vorbis" | "mp3" | "ogg"),
-            None => false,
-        }
+/// Resolves a playlist entry against the directory of the playlist it came from
+/// Accepts plain paths (absolute or relative) and `file://` URIs,
+/// other URI schemes are rejected as unsupported
+pub(crate) fn resolve_entry(base: &Path, entry: &str) -> Result<PathBuf, MelodyErrors> {
+    let path = entry_path(base, entry)?;
+    if path.exists() {
+        Ok(path)
+    } else {
+        Err(MelodyErrors::new(
+            MelodyErrorsKind::PathDoesNotExist,
+            "Playlist entry does not exist",
+            Some(&path),
+        ))
+    }
 }
 
-/// Function meant to organize a specific song
-/// Song: Song to organize
-/// to: Directory move song to
-pub fn organize_song(song: Song, mut to: PathBuf) -> Result<(), MelodyErrors> {
-    use self::MelodyErrorsKind::*;
-    if song.file().is_dir() {
+/// Path of a playlist entry like `resolve_entry`, without checking that it exists
+pub(crate) fn entry_path(base: &Path, entry: &str) -> Result<PathBuf, MelodyErrors> {
+    let entry = entry.trim();
+    let path = if let Some(uri) = entry.strip_prefix("file://") {
+        // `file://localhost/path` and `file:///path` are both valid
+        let uri = uri.strip_prefix("localhost").unwrap_or(uri);
+        PathBuf::from(percent_decode(uri))
+    } else if entry.contains("://") {
         return Err(MelodyErrors::new(
-            PathIsNotAFile,
-            "Song is not a file",
-            Some(song.file()),
+            MelodyErrorsKind::UnsupportedFileType,
+            &format!("Remote playlist entries are not supported: {}", entry),
+            None,
         ));
+    } else {
+        PathBuf::from(entry)
+    };
+    if path.is_relative() {
+        Ok(base.join(path))
+    } else {
+        Ok(path)
     }
-    match song.artist() {
-        Some(artist) => to.push(artist),
-        None => to.push("Uknown Artist"),
-    }
-    match song.album() {
-        Some(album) => to.push(album),
-        None => to.push("Unkown Album"),
+}
+
+/// Returns the path written into a playlist stored in `base`
+/// Songs below `base` are written relative to it, everything else stays absolute
+pub(crate) fn playlist_entry(base: &Path, file: &Path) -> PathBuf {
+    match file.strip_prefix(base) {
+        Ok(relative) if !base.as_os_str().is_empty() => relative.to_path_buf(),
+        _ => file.to_path_buf(),
     }
-    fs::create_dir_all(&to)?;
-    to.push(song.file().file_name().expect("Failed to read file name"));
-    fs::copy(song, to)?;
-    Ok(())
 }
 
-/// Add to library
-/// `from` - Original Directory that the music being moved from, must be an absolute path
-/// `to` - New Directory that the music is being moved to, must be an absolute path
-/// `from` must be in a folder above or on the same level as ``to``
-/// Files that failed to be sorted will remain
-pub fn add_to_library(from: &Path, to: &Path) -> Result<Option<Vec<MelodyErrors>>, MelodyErrors> {
-    use self::MelodyErrorsKind::*;
-    if from.is_relative() {
-        return Err(MelodyErrors::new(
-            NotAbsolutePath,
-            "`from` path is not absolute",
-            None,
-        ));
-    };
-    if to.is_relative() {
-        return Err(MelodyErrors::new(
-            NotAbsolutePath,
-            "`to` path is not absolute",
-            None,
-        ));
-    };
-    if !from.exists() {
-        return Err(MelodyErrors::new(
-            Io(IoErrorKind::NotFound),
-            "`from` path is not absolute",
-            None,
-        ));
-    };
-    if !to.exists() {
-        return Err(MelodyErrors::new(
-            Io(IoErrorKind::NotFound),
-            "`to` path is not absolute",
-            None,
-        ));
-    };
-    if from.is_file() {
-        return Err(MelodyErrors::new(
-            PathIsNotADir,
-            "`from` is not a directory",
-            None,
-        ));
-    };
-    if to.is_file() {
-        return Err(MelodyErrors::new(
-            PathIsNotADir,
-            "`to` is not a directory",
-            None,
-        ));
-    };
-    match from.parent() {
-        Some(parent) => {
-            if parent == to {
-                return Err(MelodyErrors::new(
-                    ChildOfParentRecursion,
-                    "`from` is a direc child of to",
-                    None,
-                ));
-            }
+/// Decodes `%XX` escapes, invalid escapes are kept as is
+pub(crate) fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = s.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(hex, 16)
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
         }
-        None => {
-            return Err(MelodyErrors::new(
-                FailedToFindParent,
-                "could not find parent of `from`.",
-                None,
-            ));
-        }
-    };
-    let files = list_files(from.to_path_buf());
-    Ok(Some(
-        files
-            .filter_map(|f| match Song::load(f) {
-                Ok(_) => None,
-                Err(e) => Some(e),
-            })
-            .collect(),
-    ))
+    }
+    String::from_utf8_lossy(&out).into_owned()
 }
 
-/// Find  Duplicates
-/// `music_dir` - Music directory to find duplicates
-/// Returns a list of duplicates
-pub fn find_duplicates(music_dir: &Path) -> Vec<PathBuf> {
-    fn list_occ(s: &Song, v: &[Song]) -> Vec<usize> {
-        let mut occ = vec![];
-        for (pos, song) in v.iter().enumerate() {
-            if song.matching_song(s, false) {
-                occ.push(pos)
+/// Encodes a path for use inside a URI, keeping `/` and unreserved characters
+pub(crate) fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
             }
+            _ => out.push_str(&format!("%{:02X}", b)),
         }
-        occ
     }
-    let music_dir = music_dir.to_path_buf();
-    if let Some(pl) = Playlist::from_dir(music_dir) {
-        let tracks = pl.tracks;
-        let mut dupes = Vec::with_capacity(tracks.len());
-        // TODO: convert it into a lazy iter
-        for track in &tracks {
-            let occ = list_occ(track, &tracks);
-            if occ.len() == 1 {
-                continue;
-            }
-            for pos in occ {
-                if !dupes.contains(&pos) {
-                    dupes.push(pos)
-                }
-            }
+    out
+}
+
+/// Reads a text file as UTF-8 (dropping a BOM), falling back to Latin-1
+pub(crate) fn read_text(path: &Path) -> Result<String, MelodyErrors> {
+    let bytes = fs::read(path).map_err(|e| MelodyErrors::from_io(e, path))?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    Ok(match ::std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    })
+}
+
+/// Opens the audio decoder for the file at `path`
+pub(crate) fn open_decoder(path: &Path) -> Result<Decoder<BufReader<File>>, MelodyErrors> {
+    let file = File::open(path).map_err(|e| MelodyErrors::from_io(e, path))?;
+    Decoder::new(BufReader::new(file)).map_err(|e| {
+        MelodyErrors::new(
+            MelodyErrorsKind::FailedToDecode,
+            &format!("Failed to decode: {}", e),
+            Some(path),
+        )
+    })
+}
+
+/// Audio formats Melody supports, lofty has to be able to read
+/// the tags and rodio has to be able to decode the audio
+const SUPPORTED_FORMATS: &[FileType] = &[
+    FileType::Aac,
+    FileType::Aiff,
+    FileType::Flac,
+    FileType::Mp4,
+    FileType::Mpeg,
+    FileType::Vorbis,
+    FileType::Wav,
+];
+
+/// Returns the audio formats that can be loaded and played
+pub fn supported_formats() -> &'static [FileType] {
+    SUPPORTED_FORMATS
+}
+
+/// Detects the format of a file from its magic bytes,
+/// falling back to the extension if the content isnt recognized
+pub fn detect_format(path: &Path) -> Option<FileType> {
+    Probe::open(path).ok()?.guess_file_type().ok()?.file_type()
+}
+
+/// Extensions of files that often sit next to songs but are never audio,
+/// like cover art and rip logs, they arent worth opening
+const NON_AUDIO_EXTENSIONS: &[&str] = &[
+    "accurip", "bmp", "cue", "db", "gif", "ini", "jpeg", "jpg", "log", "m3u", "m3u8", "md", "md5",
+    "nfo", "pdf", "pls", "png", "sfv", "txt", "webp", "xspf",
+];
+
+/// Checks if a song is supported
+/// Files named like a supported format are trusted, the format of files with
+/// an unknown or unsupported extension is detected from their content so misnamed
+/// files work, see `supported_formats` for the list of formats
+pub fn supported_song(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    match get_filetype(path) {
+        Some(ext) if NON_AUDIO_EXTENSIONS.contains(&ext.as_str()) => false,
+        Some(ext) if FileType::from_ext(&ext).is_some_and(|f| SUPPORTED_FORMATS.contains(&f)) => {
+            true
         }
-        dupes.shrink_to_fit();
-        dupes.iter().map(|pos| tracks[*pos].file.clone()).collect()
-    } else {
-        Vec::with_capacity(0)
+        _ => detect_format(path).is_some_and(|f| SUPPORTED_FORMATS.contains(&f)),
     }
 }
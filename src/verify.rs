@@ -0,0 +1,264 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::scan::parallel_map;
+use crate::song::Playlist;
+use crate::utils::{detect_format, fmt_duration, open_decoder};
+use lofty::file::FileType;
+use md5::{Digest, Md5};
+use rodio::Source;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How far the decoded length can be off from the duration in the tags,
+/// lossy formats only estimate it from the bitrate
+pub const DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Something wrong with a file, found by `verify_file`
+#[derive(Debug)]
+pub enum Problem {
+    /// The file couldnt be opened, or the audio is corrupt
+    DecodeError(MelodyErrors),
+    /// The audio ends before the length the FLAC or WAV header promises
+    Truncated {
+        expected: Duration,
+        decoded: Duration,
+    },
+    /// The decoded audio is longer or shorter than the duration in the tags
+    DurationMismatch {
+        expected: Duration,
+        decoded: Duration,
+    },
+    /// The FLAC audio doesnt match the MD5 signature in its header
+    Md5Mismatch,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::DecodeError(e) => write!(f, "{}", e),
+            Problem::Truncated { expected, decoded } => write!(
+                f,
+                "Truncated, ends at {} of {}",
+                fmt_duration(decoded),
+                fmt_duration(expected)
+            ),
+            Problem::DurationMismatch { expected, decoded } => write!(
+                f,
+                "Decodes to {} but the tags say {}",
+                fmt_duration(decoded),
+                fmt_duration(expected)
+            ),
+            Problem::Md5Mismatch => f.write_str("Audio doesnt match its MD5 signature"),
+        }
+    }
+}
+
+/// Outcome of `Playlist::verify`
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of files decoded, CUE tracks of the same file count once
+    pub checked: usize,
+    /// Files with problems and what is wrong with them, sorted by path
+    pub problems: Vec<(PathBuf, Vec<Problem>)>,
+}
+
+impl VerifyReport {
+    /// Number of files that decoded cleanly
+    pub fn ok(&self) -> usize {
+        self.checked - self.problems.len()
+    }
+}
+
+fn samples_duration(samples: u64, rate: u32) -> Duration {
+    Duration::from_secs_f64(samples as f64 / rate.max(1) as f64)
+}
+
+/// Decodes a FLAC file with claxon, which reports errors rodio skips over,
+/// and checks the length and MD5 signature in its header
+fn verify_flac(path: &Path) -> Vec<Problem> {
+    let mut reader = match claxon::FlacReader::open(path) {
+        Ok(reader) => reader,
+        Err(claxon::Error::IoError(e)) => {
+            return vec![Problem::DecodeError(MelodyErrors::from_io(e, path))];
+        }
+        Err(e) => {
+            return vec![Problem::DecodeError(MelodyErrors::new(
+                MelodyErrorsKind::FailedToDecode,
+                &format!("Failed to decode: {}", e),
+                Some(path),
+            ))];
+        }
+    };
+    let info = reader.streaminfo();
+    let bytes = info.bits_per_sample.div_ceil(8) as usize;
+    // An all zero signature means the encoder didnt write one
+    let mut md5 = (info.md5sum != [0; 16]).then(Md5::new);
+    let (mut decoded, mut buffer, mut bytes_out) = (0u64, Vec::new(), Vec::new());
+    let mut blocks = reader.blocks();
+    loop {
+        match blocks.read_next_or_eof(buffer) {
+            Ok(Some(block)) => {
+                decoded += block.duration() as u64;
+                if let Some(md5) = &mut md5 {
+                    bytes_out.clear();
+                    for i in 0..block.duration() {
+                        for ch in 0..block.channels() {
+                            let sample = block.sample(ch, i).to_le_bytes();
+                            bytes_out.extend_from_slice(&sample[..bytes]);
+                        }
+                    }
+                    md5.update(&bytes_out);
+                }
+                buffer = block.into_buffer();
+            }
+            Ok(None) => break,
+            // Running out of file part way through a frame
+            Err(claxon::Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => {
+                let at = fmt_duration(&samples_duration(decoded, info.sample_rate));
+                return vec![Problem::DecodeError(MelodyErrors::new(
+                    MelodyErrorsKind::FailedToDecode,
+                    &format!("Failed to decode at {}: {}", at, e),
+                    Some(path),
+                ))];
+            }
+        }
+    }
+    if let Some(expected) = info.samples.filter(|&s| s > 0)
+        && decoded < expected
+    {
+        return vec![Problem::Truncated {
+            expected: samples_duration(expected, info.sample_rate),
+            decoded: samples_duration(decoded, info.sample_rate),
+        }];
+    }
+    if md5.is_some_and(|md5| md5.finalize()[..] != info.md5sum) {
+        return vec![Problem::Md5Mismatch];
+    }
+    Vec::new()
+}
+
+/// Reads every sample of a WAV file with hound, rodio pads a cut off file with silence
+fn verify_wav(path: &Path) -> Vec<Problem> {
+    let mut reader = match hound::WavReader::open(path) {
+        Ok(reader) => reader,
+        Err(hound::Error::IoError(e)) => {
+            return vec![Problem::DecodeError(MelodyErrors::from_io(e, path))];
+        }
+        Err(e) => {
+            return vec![Problem::DecodeError(MelodyErrors::new(
+                MelodyErrorsKind::FailedToDecode,
+                &format!("Failed to decode: {}", e),
+                Some(path),
+            ))];
+        }
+    };
+    let spec = reader.spec();
+    let (expected, channels) = (reader.duration() as u64, spec.channels.max(1) as u64);
+    let mut samples = 0u64;
+    let error = match spec.sample_format {
+        hound::SampleFormat::Int => reader.samples::<i32>().find_map(|s| {
+            samples += 1;
+            s.err()
+        }),
+        hound::SampleFormat::Float => reader.samples::<f32>().find_map(|s| {
+            samples += 1;
+            s.err()
+        }),
+    };
+    let decoded = samples.saturating_sub(error.is_some() as u64) / channels;
+    match error {
+        None => Vec::new(),
+        // Running out of data is the only io error once the header is read
+        Some(hound::Error::IoError(_)) => {
+            vec![Problem::Truncated {
+                expected: samples_duration(expected, spec.sample_rate),
+                decoded: samples_duration(decoded, spec.sample_rate),
+            }]
+        }
+        Some(e) => vec![Problem::DecodeError(MelodyErrors::new(
+            MelodyErrorsKind::FailedToDecode,
+            &format!(
+                "Failed to decode at {}: {}",
+                fmt_duration(&samples_duration(decoded, spec.sample_rate)),
+                e
+            ),
+            Some(path),
+        ))],
+    }
+}
+
+/// Decodes every sample of the file at `path` and reports what is wrong with it.
+/// `expected` is the duration from the tags, `Song::duration`, it is left out
+/// for files split into CUE tracks
+pub fn verify_file(path: &Path, expected: Option<Duration>) -> Vec<Problem> {
+    // FLAC and WAV headers know the exact length, no need to compare with the tags
+    match detect_format(path) {
+        Some(FileType::Flac) => return verify_flac(path),
+        Some(FileType::Wav) => return verify_wav(path),
+        _ => (),
+    }
+    let decoder = match open_decoder(path) {
+        Ok(decoder) => decoder,
+        Err(e) => return vec![Problem::DecodeError(e)],
+    };
+    let (rate, channels) = (decoder.sample_rate(), decoder.channels().max(1));
+    let decoded = samples_duration(decoder.count() as u64 / channels as u64, rate);
+    let mut problems = Vec::new();
+    if decoded.is_zero() {
+        problems.push(Problem::DecodeError(MelodyErrors::new(
+            MelodyErrorsKind::FailedToDecode,
+            "No audio could be decoded",
+            Some(path),
+        )));
+    } else if let Some(expected) = expected
+        && expected.abs_diff(decoded) > DURATION_TOLERANCE
+    {
+        problems.push(Problem::DurationMismatch { expected, decoded });
+    }
+    problems
+}
+
+impl Playlist {
+    /// Fully decodes every file of the playlist on a few threads, see `verify_file`.
+    /// `progress` is called with the number of files done and the number to do
+    /// # Example
+    /// ```no_run
+    /// let playlist = melody::Playlist::from_dir(std::path::PathBuf::from("/home/user/Music")).unwrap();
+    /// for (file, problems) in playlist.verify(|_, _| ()).problems {
+    ///     println!("{:?}: {}", file, problems[0]);
+    /// }
+    /// ```
+    pub fn verify(&self, mut progress: impl FnMut(usize, usize)) -> VerifyReport {
+        let mut files: BTreeMap<&Path, Option<Duration>> = BTreeMap::new();
+        for song in &self.tracks {
+            let expected = song.start().is_none().then(|| song.duration());
+            files
+                .entry(song.file())
+                .and_modify(|d| *d = None)
+                .or_insert(expected);
+        }
+        let todo: Vec<(&Path, Option<Duration>)> = files.into_iter().collect();
+        let mut report = VerifyReport {
+            checked: todo.len(),
+            problems: Vec::new(),
+        };
+        let mut done = 0;
+        parallel_map(
+            todo,
+            |(path, expected)| (path, verify_file(path, expected)),
+            |(path, problems), _| {
+                if !problems.is_empty() {
+                    report.problems.push((path.to_path_buf(), problems));
+                }
+                done += 1;
+                progress(done, report.checked);
+            },
+        );
+        report.problems.sort_by(|a, b| a.0.cmp(&b.0));
+        report
+    }
+}
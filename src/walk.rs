@@ -0,0 +1,205 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::journal::is_backup;
+use crate::song::Song;
+use crate::utils::ignored_file;
+use ignore::gitignore::Gitignore;
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{Error as IgnoreError, Match, WalkBuilder};
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the gitignore style files honoured while walking a directory
+pub const IGNORE_FILE: &str = ".melodyignore";
+
+/// Options for walking a music directory
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Maximum depth to descend to, `Some(0)` only looks at the root itself
+    pub max_depth: Option<usize>,
+    /// Follow symbolic links to directories, loops are detected and reported
+    pub follow_symlinks: bool,
+    /// Include hidden files and directories (names starting with `.`)
+    pub hidden: bool,
+    /// Leave out songs shorter than this
+    pub min_duration: Option<Duration>,
+    /// Only include files matching at least one of these globs, if any are given
+    /// Globs are matched relative to the scanned directory
+    pub include: Vec<String>,
+    /// Leave out files matching any of these globs
+    pub exclude: Vec<String>,
+    /// Honour `.melodyignore` files
+    pub ignore_files: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            follow_symlinks: false,
+            // Hidden files were always scanned before there was a choice
+            hidden: true,
+            min_duration: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            ignore_files: true,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Checks if a loaded song should be kept
+    pub fn keeps(&self, song: &Song) -> bool {
+        self.min_duration.is_none_or(|min| song.duration() >= min)
+    }
+    /// Compiles the include and exclude globs
+    fn overrides(&self, root: &Path) -> Result<Override, MelodyErrors> {
+        let mut builder = OverrideBuilder::new(root);
+        let globs = self
+            .include
+            .iter()
+            .cloned()
+            .chain(self.exclude.iter().map(|g| format!("!{}", g)));
+        for glob in globs {
+            builder.add(&glob).map_err(|e| {
+                MelodyErrors::new(
+                    MelodyErrorsKind::InvalidGlob,
+                    &format!("Invalid glob {:?}: {}", glob, e),
+                    None,
+                )
+            })?;
+        }
+        builder
+            .build()
+            .map_err(|e| MelodyErrors::new(MelodyErrorsKind::InvalidGlob, &e.to_string(), None))
+    }
+}
+
+/// Finds the path an error of the walker relates to
+fn error_path(e: &IgnoreError) -> Option<&Path> {
+    match e {
+        IgnoreError::WithPath { path, .. } => Some(path),
+        IgnoreError::Loop { child, .. } => Some(child),
+        IgnoreError::WithDepth { err, .. } | IgnoreError::WithLineNumber { err, .. } => {
+            error_path(err)
+        }
+        IgnoreError::Partial(errs) => errs.iter().find_map(error_path),
+        _ => None,
+    }
+}
+
+fn is_loop(e: &IgnoreError) -> bool {
+    match e {
+        IgnoreError::Loop { .. } => true,
+        IgnoreError::WithPath { err, .. }
+        | IgnoreError::WithDepth { err, .. }
+        | IgnoreError::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+fn walk_error(e: IgnoreError) -> MelodyErrors {
+    let kind = if is_loop(&e) {
+        MelodyErrorsKind::SymlinkLoop
+    } else {
+        MelodyErrorsKind::Io(
+            e.io_error()
+                .map(|io| io.kind())
+                .unwrap_or(IoErrorKind::Other),
+        )
+    };
+    MelodyErrors::new(kind, &e.to_string(), error_path(&e))
+}
+
+/// Recursively list the files of `root` according to `options`,
+/// keeping the errors hit while walking.
+/// Fails if the include or exclude globs are invalid
+pub(crate) fn walk_files(
+    root: &Path,
+    options: &ScanOptions,
+) -> Result<impl Iterator<Item = Result<PathBuf, MelodyErrors>> + use<>, MelodyErrors> {
+    // Globs are checked on the files found rather than handed to the walker,
+    // where an include would override the `.melodyignore` files
+    let globs = options.overrides(root)?;
+    let mut builder = WalkBuilder::new(root);
+    builder
+        // Skip .gitignore and friends, only .melodyignore applies
+        .standard_filters(false)
+        .parents(true)
+        .hidden(!options.hidden)
+        .follow_links(options.follow_symlinks)
+        .max_depth(options.max_depth);
+    if options.ignore_files {
+        builder.add_custom_ignore_filename(IGNORE_FILE);
+    }
+    Ok(builder.build().filter_map(move |e| match e {
+        Ok(entry) => {
+            let is_file = match entry.file_type() {
+                Some(t) if t.is_symlink() => entry.path().is_file(),
+                Some(t) => t.is_file(),
+                None => false,
+            };
+            let path = entry.into_path();
+            let keep = is_file
+                && ignored_file(&path)
+                && !is_backup(&path)
+                && !globs.matched(&path, false).is_ignore();
+            keep.then_some(Ok(path))
+        }
+        Err(e) => Some(Err(walk_error(e))),
+    }))
+}
+
+/// Checks if `walk_files(root, options)` would list the file at `path`,
+/// without walking the whole of `root`
+/// Fails if the include or exclude globs are invalid
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub(crate) fn accepts(
+    root: &Path,
+    path: &Path,
+    options: &ScanOptions,
+) -> Result<bool, MelodyErrors> {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Ok(false);
+    };
+    if options
+        .max_depth
+        .is_some_and(|depth| relative.components().count() > depth)
+    {
+        return Ok(false);
+    }
+    let hidden = relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    if (hidden && !options.hidden) || !ignored_file(path) || is_backup(path) {
+        return Ok(false);
+    }
+    if options.overrides(root)?.matched(path, false).is_ignore() {
+        return Ok(false);
+    }
+    if options.ignore_files {
+        // Like the walker, ignore files of the parents of `root` apply too
+        // and deeper files take precedence
+        let mut ignored = false;
+        let dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        for dir in dirs.into_iter().rev() {
+            let file = dir.join(IGNORE_FILE);
+            if !file.is_file() {
+                continue;
+            }
+            let (rules, err) = Gitignore::new(&file);
+            if let Some(e) = err {
+                log::warn!("{}", e);
+            }
+            match rules.matched_path_or_any_parents(path, false) {
+                Match::Ignore(_) => ignored = true,
+                Match::Whitelist(_) => ignored = false,
+                Match::None => (),
+            }
+        }
+        if ignored {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
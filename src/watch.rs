@@ -0,0 +1,226 @@
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::library::{Library, LibraryScan};
+use crate::utils::supported_song;
+use crate::walk::{ScanOptions, accepts, walk_files};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How long a path has to be quiet before its changes are handled
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// A change to the library noticed by `LibraryWatcher`
+#[derive(Debug)]
+pub enum LibraryEvent {
+    /// A new file was indexed, see `Library::songs_of` for its songs
+    Added(PathBuf),
+    /// A file is gone from the index
+    Removed(PathBuf),
+    /// An indexed file changed and was reloaded
+    Changed(PathBuf),
+    /// Events were lost (the OS queue overflowed), so the whole library was rescanned
+    Rescanned(LibraryScan),
+}
+
+fn watch_error(e: notify::Error, root: &Path) -> MelodyErrors {
+    let kind = match e.kind {
+        notify::ErrorKind::Io(ref io) => MelodyErrorsKind::Io(io.kind()),
+        notify::ErrorKind::PathNotFound => MelodyErrorsKind::PathDoesNotExist,
+        _ => MelodyErrorsKind::FailedToWatch,
+    };
+    MelodyErrors::new(kind, &format!("Failed to watch: {}", e), Some(root))
+}
+
+/// Watches a music directory (using inotify on Linux) and keeps a `Library` up to date
+/// Changes are debounced per path, so a file is only reloaded once it has been
+/// left alone for a while, big copies dont reload a file for every write
+/// # Example
+/// ```no_run
+/// let music = std::path::Path::new("/home/melody/Music");
+/// let mut library = melody::Library::open_default()?;
+/// let mut watcher = melody::LibraryWatcher::new(music, melody::ScanOptions::default())?;
+/// loop {
+///     let events = watcher.wait(&mut library, std::time::Duration::from_secs(60));
+///     if !events.is_empty() {
+///         library.save()?;
+///     }
+/// }
+/// # Ok::<(), melody::MelodyErrors>(())
+/// ```
+pub struct LibraryWatcher {
+    root: PathBuf,
+    options: ScanOptions,
+    debounce: Duration,
+    events: Receiver<notify::Result<Event>>,
+    /// Changed paths and when they last changed
+    pending: HashMap<PathBuf, Instant>,
+    /// Set when events were lost
+    rescan: bool,
+    // Watching stops once this is dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl LibraryWatcher {
+    /// Start watching `root` recursively, files are filtered like `Library::scan_with` does
+    pub fn new(root: &Path, options: ScanOptions) -> Result<Self, MelodyErrors> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(|e| watch_error(e, root))?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(|e| watch_error(e, root))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            options,
+            debounce: DEFAULT_DEBOUNCE,
+            events,
+            pending: HashMap::new(),
+            rescan: false,
+            _watcher: watcher,
+        })
+    }
+    /// Set how long a path has to be quiet before its changes are handled
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+    /// The watched directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    fn record(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log::warn!("{}", watch_error(e, &self.root));
+                return;
+            }
+        };
+        if event.need_rescan() {
+            self.rescan = true;
+        }
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let now = Instant::now();
+        for path in event.paths {
+            self.pending.insert(path, now);
+        }
+    }
+    /// Handle the changes that settled, without blocking
+    /// `library` is updated in place, it is up to the caller to save it
+    pub fn poll(&mut self, library: &mut Library) -> Vec<LibraryEvent> {
+        while let Ok(event) = self.events.try_recv() {
+            self.record(event);
+        }
+        if self.rescan {
+            self.rescan = false;
+            self.pending.clear();
+            return match library.scan_with(&self.root, &self.options, |_| ()) {
+                Ok(scan) => vec![LibraryEvent::Rescanned(scan)],
+                Err(e) => {
+                    log::warn!("Failed to rescan the library: {}", e);
+                    Vec::new()
+                }
+            };
+        }
+        let now = Instant::now();
+        let mut ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= self.debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        let mut events = Vec::new();
+        for path in ready {
+            self.pending.remove(&path);
+            self.update(library, &path, &mut events);
+        }
+        events
+    }
+    /// Wait up to `timeout` for changes to settle and handle them
+    /// Returns as soon as there are events
+    pub fn wait(&mut self, library: &mut Library, timeout: Duration) -> Vec<LibraryEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let events = self.poll(library);
+            let now = Instant::now();
+            if !events.is_empty() || now >= deadline {
+                return events;
+            }
+            // Sleep until the oldest pending change settles, or something else happens
+            let next = self
+                .pending
+                .values()
+                .map(|changed| *changed + self.debounce)
+                .min()
+                .map_or(deadline, |settled| settled.min(deadline));
+            match self
+                .events
+                .recv_timeout(next.saturating_duration_since(now))
+            {
+                Ok(event) => self.record(event),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return Vec::new(),
+            }
+        }
+    }
+    /// Bring the index entries of `path` up to date
+    fn update(&self, library: &mut Library, path: &Path, events: &mut Vec<LibraryEvent>) {
+        if path.is_dir() {
+            // A directory that was moved or copied in, the files inside may not have events
+            let files = match walk_files(
+                path,
+                &ScanOptions {
+                    include: Vec::new(),
+                    exclude: Vec::new(),
+                    ..self.options.clone()
+                },
+            ) {
+                Ok(files) => files,
+                Err(e) => {
+                    log::warn!("{}", e);
+                    return;
+                }
+            };
+            for file in files.filter_map(Result::ok) {
+                self.update_file(library, &file, events);
+            }
+        } else if path.exists() {
+            self.update_file(library, path, events);
+        } else {
+            events.extend(
+                library
+                    .remove_files(path)
+                    .into_iter()
+                    .map(LibraryEvent::Removed),
+            );
+        }
+    }
+    fn update_file(&self, library: &mut Library, path: &Path, events: &mut Vec<LibraryEvent>) {
+        let keep = match accepts(&self.root, path, &self.options) {
+            Ok(keep) => keep,
+            Err(e) => {
+                log::warn!("{}", e);
+                false
+            }
+        };
+        if !keep || !supported_song(path) {
+            // Files that stopped being audio or are ignored leave the index
+            events.extend(
+                library
+                    .remove_files(path)
+                    .into_iter()
+                    .map(LibraryEvent::Removed),
+            );
+            return;
+        }
+        match library.update_file(path) {
+            Some(true) => events.push(LibraryEvent::Changed(path.to_path_buf())),
+            Some(false) => events.push(LibraryEvent::Added(path.to_path_buf())),
+            None => (),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::cue::{format_span, load_span, parse_span};
+use crate::errors::{MelodyErrors, MelodyErrorsKind};
+use crate::song::{Playlist, Song};
+use crate::utils::{percent_decode, percent_encode, playlist_entry, read_text, resolve_entry};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// `application` of the `<extension>` Melody keeps the span of tracks of CUE sheets in
+const APPLICATION: &str = "https://github.com/LovingMelody/Melody";
+
+/// Iterates over the contents of every `<name>` element in `xml`
+/// Only handles the subset of XML used by XSPF (no nesting of the same element)
+fn elements<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut rest = xml;
+    ::std::iter::from_fn(move || {
+        loop {
+            let start = rest.find(&open)?;
+            let after = &rest[start + open.len()..];
+            // Make sure `<track` didnt match `<trackList` or `<trackNum`
+            match after.chars().next() {
+                Some('>') | Some('/') => (),
+                Some(c) if c.is_whitespace() => (),
+                _ => {
+                    rest = after;
+                    continue;
+                }
+            }
+            let tag_end = after.find('>')?;
+            if after[..tag_end].ends_with('/') {
+                // `<name/>`
+                rest = &after[tag_end + 1..];
+                return Some("");
+            }
+            let body = &after[tag_end + 1..];
+            let end = body.find(&close)?;
+            rest = &body[end + close.len()..];
+            return Some(&body[..end]);
+        }
+    })
+}
+
+/// Returns the unescaped text of the first `<name>` element in `xml`
+fn text(xml: &str, name: &str) -> Option<String> {
+    elements(xml, name)
+        .next()
+        .map(unescape)
+        .filter(|t| !t.is_empty())
+}
+
+/// Resolves XML entities and CDATA sections
+fn unescape(s: &str) -> String {
+    let s = s.trim();
+    if let Some(cdata) = s
+        .strip_prefix("<![CDATA[")
+        .and_then(|s| s.strip_suffix("]]>"))
+    {
+        return cdata.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Escapes text for use inside an XML element
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Playlist {
+    /// Read an XSPF playlist
+    /// Tracks of CUE sheets written by `write_xspf` are resolved again.
+    /// `creator`, `album`, `title`, `trackNum` and `duration` fill in tags missing from the files.
+    /// Returns the playlist along with the entries that could not be resolved or loaded
+    pub fn from_xspf(path: &Path) -> Result<(Self, Vec<MelodyErrors>), MelodyErrors> {
+        let xml = read_text(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let track_list = elements(&xml, "trackList").next().ok_or_else(|| {
+            MelodyErrors::new(
+                MelodyErrorsKind::MalformedPlaylist,
+                "Missing <trackList> element",
+                Some(path),
+            )
+        })?;
+        let mut tracks = Vec::new();
+        let mut unresolved = Vec::new();
+        for track in elements(track_list, "track") {
+            let Some(location) = text(track, "location") else {
+                unresolved.push(MelodyErrors::new(
+                    MelodyErrorsKind::MalformedPlaylist,
+                    "Track has no <location>",
+                    Some(path),
+                ));
+                continue;
+            };
+            // Relative locations are URI references as well
+            let location = if location.contains("://") {
+                location
+            } else {
+                percent_decode(&location)
+            };
+            let span = elements(track, "extension")
+                .find_map(|extension| text(extension, "span"))
+                .and_then(|span| parse_span(&span));
+            let loaded = match span {
+                Some(span) => resolve_entry(base, &location).and_then(|file| load_span(file, span)),
+                None => resolve_entry(base, &location).and_then(Song::load),
+            };
+            match loaded {
+                Ok(mut song) => {
+                    if song.artist.is_none() {
+                        song.artist = text(track, "creator");
+                    }
+                    if song.album.is_none() {
+                        song.album = text(track, "album");
+                    }
+                    if song.title.is_none() {
+                        song.title = text(track, "title");
+                    }
+                    if song.track.is_none() {
+                        song.track = text(track, "trackNum").and_then(|n| n.parse().ok());
+                    }
+                    if song.duration.is_zero()
+                        && let Some(ms) = text(track, "duration").and_then(|d| d.parse().ok())
+                    {
+                        song.duration = Duration::from_millis(ms);
+                    }
+                    tracks.push(song)
+                }
+                Err(e) => unresolved.push(e),
+            }
+        }
+        Ok((Self { tracks }, unresolved))
+    }
+
+    /// Write the playlist as an XSPF file
+    /// Songs stored below the playlist's directory are written as relative locations,
+    /// tracks of CUE sheets get an `<extension>` with where they start
+    pub fn write_xspf(&self, path: &Path) -> Result<(), MelodyErrors> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let io_err = |e| MelodyErrors::from_io(e, path);
+        let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).map_err(io_err)?;
+        writeln!(
+            out,
+            r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#
+        )
+        .map_err(io_err)?;
+        writeln!(out, "  <trackList>").map_err(io_err)?;
+        for song in &self.tracks {
+            let entry = playlist_entry(base, song.file());
+            let location = percent_encode(&entry.to_string_lossy());
+            let location = if entry.is_absolute() {
+                format!("file://{}", location)
+            } else {
+                location
+            };
+            writeln!(out, "    <track>").map_err(io_err)?;
+            writeln!(out, "      <location>{}</location>", escape(&location)).map_err(io_err)?;
+            if let Some(artist) = song.artist() {
+                writeln!(out, "      <creator>{}</creator>", escape(artist)).map_err(io_err)?;
+            }
+            if let Some(album) = song.album() {
+                writeln!(out, "      <album>{}</album>", escape(album)).map_err(io_err)?;
+            }
+            if let Some(title) = song.title() {
+                writeln!(out, "      <title>{}</title>", escape(title)).map_err(io_err)?;
+            }
+            if let Some(track) = song.track() {
+                writeln!(out, "      <trackNum>{}</trackNum>", track).map_err(io_err)?;
+            }
+            if !song.duration().is_zero() {
+                writeln!(
+                    out,
+                    "      <duration>{}</duration>",
+                    song.duration().as_millis()
+                )
+                .map_err(io_err)?;
+            }
+            if let Some(span) = format_span(song) {
+                writeln!(out, r#"      <extension application="{}">"#, APPLICATION)
+                    .map_err(io_err)?;
+                writeln!(out, "        <span>{}</span>", span).map_err(io_err)?;
+                writeln!(out, "      </extension>").map_err(io_err)?;
+            }
+            writeln!(out, "    </track>").map_err(io_err)?;
+        }
+        writeln!(out, "  </trackList>").map_err(io_err)?;
+        writeln!(out, "</playlist>").map_err(io_err)?;
+        out.flush().map_err(io_err)
+    }
+}